@@ -0,0 +1,63 @@
+use std::path::Path;
+
+use async_trait::async_trait;
+
+use bytes::Bytes;
+
+use interface::BlobMeta;
+use menmos_client::{Client, Meta, Query, QueryResponse};
+
+use super::{Backend, Result};
+use crate::error::RequestError;
+
+#[async_trait]
+impl Backend for Client {
+    async fn push(&self, path: &Path, meta: Meta) -> Result<String> {
+        Client::push(self, path, meta)
+            .await
+            .map_err(RequestError::from_client)
+    }
+
+    async fn create_empty(&self, meta: Meta) -> Result<String> {
+        Client::create_empty(self, meta)
+            .await
+            .map_err(RequestError::from_client)
+    }
+
+    async fn write(&self, blob_id: &str, offset: u64, buffer: Bytes) -> Result<()> {
+        Client::write(self, blob_id, offset, buffer)
+            .await
+            .map_err(RequestError::from_client)
+    }
+
+    async fn read_range(&self, blob_id: &str, range: (u64, u64)) -> Result<Vec<u8>> {
+        Client::read_range(self, blob_id, range)
+            .await
+            .map_err(RequestError::from_client)
+    }
+
+    async fn get_meta(&self, blob_id: &str) -> Result<Option<BlobMeta>> {
+        Client::get_meta(self, blob_id)
+            .await
+            .map_err(RequestError::from_client)
+    }
+
+    async fn query(&self, query: Query) -> Result<QueryResponse> {
+        Client::query(self, query)
+            .await
+            .map_err(RequestError::from_client)
+    }
+
+    async fn delete(&self, blob_id: &str) -> Result<()> {
+        // TODO: Update the menmos client so that Client::delete takes a ref.
+        Client::delete(self, String::from(blob_id))
+            .await
+            .map_err(RequestError::from_client)
+    }
+
+    async fn update_meta(&self, blob_id: &str, meta: Meta) -> Result<()> {
+        Client::update_meta(self, blob_id, meta)
+            .await
+            .map_err(RequestError::from_client)
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use bytes::Bytes;
+
+use chrono::Utc;
+
+use interface::{BlobMeta, Expression, FacetResponse, Hit};
+use menmos_client::{Meta, Query, QueryResponse, Type};
+
+use super::{Backend, Result};
+use crate::error::{ErrorKind, RequestError};
+
+/// The largest blob this backend will hold, so that a write at a bogus offset fails instead of
+/// exhausting memory.
+const MAX_BLOB_SIZE: u64 = 1 << 30;
+
+struct Blob {
+    meta: BlobMeta,
+    content: Vec<u8>,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    blobs: BTreeMap<String, Blob>,
+}
+
+fn not_found(blob_id: &str) -> RequestError {
+    RequestError::new(
+        ErrorKind::NotFound,
+        format!("blob '{}' does not exist", blob_id),
+    )
+}
+
+fn matches(expression: &Expression, meta: &BlobMeta) -> bool {
+    match expression {
+        Expression::Empty => true,
+        Expression::Tag { tag } => meta.tags.contains(tag),
+        Expression::KeyValue { key, value } => meta.metadata.get(key) == Some(value),
+        Expression::HasKey { key } => meta.metadata.contains_key(key),
+        Expression::Parent { parent } => meta.parents.contains(parent),
+        Expression::And { and: (lhs, rhs) } => matches(lhs, meta) && matches(rhs, meta),
+        Expression::Or { or: (lhs, rhs) } => matches(lhs, meta) || matches(rhs, meta),
+        Expression::Not { not } => !matches(not, meta),
+    }
+}
+
+fn compute_facets<'a, I: Iterator<Item = &'a BlobMeta>>(metas: I) -> FacetResponse {
+    let mut tags: HashMap<String, u64> = HashMap::new();
+    let mut meta: HashMap<String, HashMap<String, u64>> = HashMap::new();
+
+    for blob_meta in metas {
+        for tag in blob_meta.tags.iter() {
+            *tags.entry(tag.clone()).or_default() += 1;
+        }
+        for (k, v) in blob_meta.metadata.iter() {
+            *meta
+                .entry(k.clone())
+                .or_default()
+                .entry(v.clone())
+                .or_default() += 1;
+        }
+    }
+
+    FacetResponse { tags, meta }
+}
+
+/// A backend keeping all blobs in memory.
+///
+/// It supports every operation of the SDK, including tag, key/value and parent queries,
+/// which makes it suitable for running tests without a menmos cluster.
+///
+/// Clones of a `MemoryBackend` share the same blobs.
+///
+/// # Examples
+/// ```
+/// use menmos::{backend::MemoryBackend, FileMetadata, Menmos};
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let client = Menmos::from_backend(MemoryBackend::new());
+/// let file = client.fs.create_file(FileMetadata::new("test.txt")).await.unwrap();
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct MemoryBackend {
+    state: Arc<Mutex<State>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn insert(&self, meta: Meta, content: Vec<u8>) -> String {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        let blob_id = format!("{:032x}", state.next_id);
+
+        let now = Utc::now();
+        let meta = BlobMeta {
+            name: meta.name,
+            blob_type: meta.blob_type,
+            metadata: meta.metadata,
+            tags: meta.tags,
+            parents: meta.parents,
+            size: meta.size,
+            created_at: now,
+            modified_at: now,
+        };
+
+        state.blobs.insert(blob_id.clone(), Blob { meta, content });
+        blob_id
+    }
+}
+
+#[async_trait]
+impl Backend for MemoryBackend {
+    async fn push(&self, path: &Path, meta: Meta) -> Result<String> {
+        let content = if meta.blob_type == Type::File {
+            std::fs::read(path).map_err(|e| {
+                let kind = if e.kind() == std::io::ErrorKind::NotFound {
+                    ErrorKind::NotFound
+                } else {
+                    ErrorKind::Other
+                };
+                RequestError::new(kind, e)
+            })?
+        } else {
+            Vec::new()
+        };
+
+        Ok(self.insert(meta, content))
+    }
+
+    async fn create_empty(&self, meta: Meta) -> Result<String> {
+        Ok(self.insert(meta, Vec::new()))
+    }
+
+    async fn write(&self, blob_id: &str, offset: u64, buffer: Bytes) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let blob = state
+            .blobs
+            .get_mut(blob_id)
+            .ok_or_else(|| not_found(blob_id))?;
+
+        let end = offset
+            .checked_add(buffer.len() as u64)
+            .filter(|end| *end <= MAX_BLOB_SIZE)
+            .ok_or_else(|| {
+                RequestError::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "write at offset {} would grow blob '{}' past {} bytes",
+                        offset, blob_id, MAX_BLOB_SIZE
+                    ),
+                )
+            })?;
+        let start = offset as usize;
+        let end = end as usize;
+        if blob.content.len() < end {
+            blob.content.resize(end, 0);
+        }
+        blob.content[start..end].copy_from_slice(&buffer);
+
+        blob.meta.size = blob.meta.size.max(end as u64);
+        blob.meta.modified_at = Utc::now();
+
+        Ok(())
+    }
+
+    async fn read_range(&self, blob_id: &str, range: (u64, u64)) -> Result<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+        let blob = state.blobs.get(blob_id).ok_or_else(|| not_found(blob_id))?;
+
+        let len = blob.content.len() as u64;
+        let start = range.0.min(len) as usize;
+        let end = range.1.saturating_add(1).min(len) as usize;
+
+        Ok(blob.content[start..end.max(start)].to_vec())
+    }
+
+    async fn get_meta(&self, blob_id: &str) -> Result<Option<BlobMeta>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.blobs.get(blob_id).map(|b| b.meta.clone()))
+    }
+
+    async fn query(&self, query: Query) -> Result<QueryResponse> {
+        let state = self.state.lock().unwrap();
+
+        let matching: Vec<(&String, &Blob)> = state
+            .blobs
+            .iter()
+            .filter(|(_, blob)| matches(&query.expression, &blob.meta))
+            .collect();
+
+        let facets = if query.facets {
+            Some(compute_facets(matching.iter().map(|(_, b)| &b.meta)))
+        } else {
+            None
+        };
+
+        let hits: Vec<Hit> = matching
+            .iter()
+            .skip(query.from)
+            .take(query.size)
+            .map(|(id, blob)| Hit::new((*id).clone(), blob.meta.clone(), String::new()))
+            .collect();
+
+        Ok(QueryResponse {
+            count: hits.len(),
+            total: matching.len(),
+            hits,
+            facets,
+        })
+    }
+
+    async fn delete(&self, blob_id: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .blobs
+            .remove(blob_id)
+            .map(|_| ())
+            .ok_or_else(|| not_found(blob_id))
+    }
+
+    async fn update_meta(&self, blob_id: &str, meta: Meta) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let blob = state
+            .blobs
+            .get_mut(blob_id)
+            .ok_or_else(|| not_found(blob_id))?;
+
+        blob.meta.name = meta.name;
+        blob.meta.blob_type = meta.blob_type;
+        blob.meta.metadata = meta.metadata;
+        blob.meta.tags = meta.tags;
+        blob.meta.parents = meta.parents;
+        blob.meta.size = meta.size;
+        blob.meta.modified_at = Utc::now();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_meta(name: &str) -> Meta {
+        Meta::new(name, Type::File)
+    }
+
+    #[tokio::test]
+    async fn write_and_read_range() {
+        let backend = MemoryBackend::new();
+        let id = backend.create_empty(file_meta("a")).await.unwrap();
+
+        backend
+            .write(&id, 0, Bytes::from_static(b"hello world"))
+            .await
+            .unwrap();
+        backend
+            .write(&id, 6, Bytes::from_static(b"there"))
+            .await
+            .unwrap();
+
+        assert_eq!(backend.read_range(&id, (0, 4)).await.unwrap(), b"hello");
+        assert_eq!(backend.read_range(&id, (6, 100)).await.unwrap(), b"there");
+        assert!(backend.read_range(&id, (50, 60)).await.unwrap().is_empty());
+        assert_eq!(backend.get_meta(&id).await.unwrap().unwrap().size, 11);
+    }
+
+    #[tokio::test]
+    async fn writes_past_the_size_limit_are_rejected() {
+        let backend = MemoryBackend::new();
+        let id = backend.create_empty(file_meta("a")).await.unwrap();
+
+        for offset in [MAX_BLOB_SIZE, u64::MAX] {
+            let err = backend
+                .write(&id, offset, Bytes::from_static(b"x"))
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+        assert_eq!(backend.get_meta(&id).await.unwrap().unwrap().size, 0);
+    }
+
+    #[tokio::test]
+    async fn query_by_parent_and_tag() {
+        let backend = MemoryBackend::new();
+        let dir = backend
+            .create_empty(Meta::new("dir", Type::Directory))
+            .await
+            .unwrap();
+
+        for i in 0..5 {
+            let mut meta = file_meta(&format!("file_{}", i)).with_parent(dir.clone());
+            if i % 2 == 0 {
+                meta = meta.with_tag("even");
+            }
+            backend.create_empty(meta).await.unwrap();
+        }
+        backend.create_empty(file_meta("orphan")).await.unwrap();
+
+        let children = backend
+            .query(Query::default().and_parent(&dir))
+            .await
+            .unwrap();
+        assert_eq!(children.total, 5);
+
+        let even = backend
+            .query(Query::default().and_parent(&dir).and_tag("even"))
+            .await
+            .unwrap();
+        assert_eq!(even.total, 3);
+
+        let page = backend
+            .query(Query::default().and_parent(&dir).with_from(4).with_size(10))
+            .await
+            .unwrap();
+        assert_eq!(page.count, 1);
+        assert_eq!(page.total, 5);
+    }
+
+    #[tokio::test]
+    async fn missing_blobs_are_not_found() {
+        let backend = MemoryBackend::new();
+        assert!(backend.get_meta("nope").await.unwrap().is_none());
+
+        let err = backend.delete("nope").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+}
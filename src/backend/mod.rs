@@ -0,0 +1,59 @@
+//! Storage backends the SDK can run against.
+
+mod client;
+mod memory;
+
+pub use memory::MemoryBackend;
+
+use std::path::Path;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use bytes::Bytes;
+
+use interface::BlobMeta;
+use menmos_client::{Meta, Query, QueryResponse};
+
+use crate::error::RequestError;
+
+pub type Result<T> = std::result::Result<T, RequestError>;
+
+/// The blob operations used by the SDK.
+///
+/// This is implemented by [`menmos_client::Client`] to talk to a real cluster,
+/// and by [`MemoryBackend`] to run without one.
+#[async_trait]
+pub trait Backend: Send + Sync {
+    /// Upload the file at `path` as a new blob. Directories are pushed without a body.
+    ///
+    /// Returns the ID of the created blob.
+    async fn push(&self, path: &Path, meta: Meta) -> Result<String>;
+
+    /// Create an empty blob.
+    ///
+    /// Returns the ID of the created blob.
+    async fn create_empty(&self, meta: Meta) -> Result<String>;
+
+    /// Write a buffer at the given offset in a blob.
+    async fn write(&self, blob_id: &str, offset: u64, buffer: Bytes) -> Result<()>;
+
+    /// Read a range of bytes from a blob.
+    ///
+    /// The `range` argument is end-inclusive.
+    async fn read_range(&self, blob_id: &str, range: (u64, u64)) -> Result<Vec<u8>>;
+
+    /// Get the metadata of a blob, if it exists.
+    async fn get_meta(&self, blob_id: &str) -> Result<Option<BlobMeta>>;
+
+    /// Run a query.
+    async fn query(&self, query: Query) -> Result<QueryResponse>;
+
+    /// Delete a blob.
+    async fn delete(&self, blob_id: &str) -> Result<()>;
+
+    /// Replace the metadata of a blob without touching its contents.
+    async fn update_meta(&self, blob_id: &str, meta: Meta) -> Result<()>;
+}
+
+pub type BackendRC = Arc<dyn Backend>;
@@ -0,0 +1,273 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use snafu::prelude::*;
+
+use tokio::process::Command;
+
+use zeroize::Zeroizing;
+
+use crate::error::ErrorKind;
+
+#[derive(Debug, Snafu)]
+pub enum CredentialError {
+    #[snafu(display("failed to read password file '{:?}': {}", path, source))]
+    PasswordFileReadError {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("failed to run credential process '{}': {}", command, source))]
+    CredentialProcessSpawnError {
+        source: std::io::Error,
+        command: String,
+    },
+
+    #[snafu(display("credential process '{}' exited with {}", command, status))]
+    CredentialProcessFailedError { command: String, status: ExitStatus },
+
+    #[snafu(display("credential process '{}' did not print valid UTF-8", command))]
+    InvalidCredentialProcessOutput { command: String },
+}
+
+impl CredentialError {
+    /// Get the kind of this error.
+    ///
+    /// Failing to obtain a password is reported as [`ErrorKind::Unauthorized`].
+    pub fn kind(&self) -> ErrorKind {
+        ErrorKind::Unauthorized
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.kind().is_retryable()
+    }
+}
+
+type Result<T> = std::result::Result<T, CredentialError>;
+
+/// A secret value that is wiped from memory when dropped.
+///
+/// The value is redacted from `Debug` output. Use [`Secret::expose`] to access it.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(Zeroizing<String>);
+
+impl Secret {
+    pub fn new<S: Into<String>>(value: S) -> Self {
+        Self(Zeroizing::new(value.into()))
+    }
+
+    /// Get the secret value.
+    pub fn expose(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self::new(value)
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.expose())
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Secret::new)
+    }
+}
+
+/// Where the password of a profile comes from.
+///
+/// In the config file, exactly one of `password`, `password_file` or `credential_process`
+/// must be set on a profile.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged, try_from = "CredentialFields")]
+pub enum Credential {
+    /// A password stored inline in the config file.
+    Password { password: Secret },
+
+    /// A password read from a file. Trailing newlines are ignored.
+    PasswordFile { password_file: PathBuf },
+
+    /// A shell command printing the password on its standard output.
+    ///
+    /// This works like git or AWS credential helpers, and allows fetching the password
+    /// from a secret store instead of keeping it on disk.
+    CredentialProcess { credential_process: String },
+}
+
+/// The credential fields of a profile, as written in the config file.
+#[derive(Deserialize)]
+struct CredentialFields {
+    password: Option<Secret>,
+    password_file: Option<PathBuf>,
+    credential_process: Option<String>,
+}
+
+impl TryFrom<CredentialFields> for Credential {
+    type Error = String;
+
+    fn try_from(fields: CredentialFields) -> std::result::Result<Self, Self::Error> {
+        match (
+            fields.password,
+            fields.password_file,
+            fields.credential_process,
+        ) {
+            (Some(password), None, None) => Ok(Credential::Password { password }),
+            (None, Some(password_file), None) => Ok(Credential::PasswordFile { password_file }),
+            (None, None, Some(credential_process)) => {
+                Ok(Credential::CredentialProcess { credential_process })
+            }
+            _ => Err(String::from(
+                "exactly one of `password`, `password_file` or `credential_process` must be set",
+            )),
+        }
+    }
+}
+
+impl Credential {
+    /// Get an inline password credential.
+    pub fn password<S: Into<Secret>>(password: S) -> Self {
+        Credential::Password {
+            password: password.into(),
+        }
+    }
+
+    /// Get the password this credential points to.
+    ///
+    /// Password files are read and credential processes are executed on every call.
+    pub async fn resolve(&self) -> Result<Secret> {
+        match self {
+            Credential::Password { password } => Ok(password.clone()),
+            Credential::PasswordFile { password_file } => {
+                let contents =
+                    Zeroizing::new(tokio::fs::read_to_string(password_file).await.context(
+                        PasswordFileReadSnafu {
+                            path: password_file.clone(),
+                        },
+                    )?);
+                Ok(Secret::new(contents.trim_end_matches(&['\r', '\n'][..])))
+            }
+            Credential::CredentialProcess { credential_process } => {
+                run_credential_process(credential_process).await
+            }
+        }
+    }
+}
+
+fn shell_command(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.args(["/C", command]);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", command]);
+        cmd
+    }
+}
+
+async fn run_credential_process(command: &str) -> Result<Secret> {
+    let output = shell_command(command)
+        .output()
+        .await
+        .context(CredentialProcessSpawnSnafu {
+            command: String::from(command),
+        })?;
+
+    let stdout = Zeroizing::new(output.stdout);
+
+    ensure!(
+        output.status.success(),
+        CredentialProcessFailedSnafu {
+            command: String::from(command),
+            status: output.status
+        }
+    );
+
+    let password = std::str::from_utf8(&stdout).map_err(|_| {
+        CredentialError::InvalidCredentialProcessOutput {
+            command: String::from(command),
+        }
+    })?;
+
+    Ok(Secret::new(password.trim_end_matches(&['\r', '\n'][..])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_is_redacted_from_debug() {
+        let credential = Credential::password("hunter2");
+        let debug = format!("{:?}", credential);
+        assert!(!debug.contains("hunter2"));
+    }
+
+    #[tokio::test]
+    async fn resolve_inline_password() {
+        let credential = Credential::password("hunter2");
+        assert_eq!(credential.resolve().await.unwrap().expose(), "hunter2");
+    }
+
+    #[tokio::test]
+    async fn resolve_password_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("password");
+        std::fs::write(&path, "hunter2\n").unwrap();
+
+        let credential = Credential::PasswordFile {
+            password_file: path,
+        };
+        assert_eq!(credential.resolve().await.unwrap().expose(), "hunter2");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn resolve_credential_process() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("helper.sh");
+        std::fs::write(&path, "#!/bin/sh\necho hunter2\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o700)).unwrap();
+
+        let credential = Credential::CredentialProcess {
+            credential_process: path.to_string_lossy().to_string(),
+        };
+        assert_eq!(credential.resolve().await.unwrap().expose(), "hunter2");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn failing_credential_process() {
+        let credential = Credential::CredentialProcess {
+            credential_process: String::from("exit 3"),
+        };
+        let err = credential.resolve().await.unwrap_err();
+        assert!(matches!(
+            err,
+            CredentialError::CredentialProcessFailedError { .. }
+        ));
+    }
+}
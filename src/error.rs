@@ -0,0 +1,327 @@
+use std::error::Error;
+use std::fmt;
+
+pub use crate::credential::CredentialError;
+pub use crate::fs::FsError;
+pub use crate::metadata_detector::MetadataDetectorError;
+pub use crate::profile::ProfileError;
+pub use crate::push::PushError;
+
+/// A broad classification of SDK errors.
+///
+/// This lets callers react to a failure (e.g. retry it, or report a missing blob)
+/// without matching on every error variant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The requested blob or profile does not exist.
+    NotFound,
+
+    /// The cluster rejected our credentials, or they could not be obtained.
+    Unauthorized,
+
+    /// The cluster could not be reached.
+    Network,
+
+    /// The cluster did not answer in time.
+    Timeout,
+
+    /// The operation conflicts with the current state of the target.
+    Conflict,
+
+    /// The operation was called with invalid arguments.
+    InvalidInput,
+
+    /// The cluster failed to process the request.
+    Server,
+
+    /// A local failure, such as an unreadable config file.
+    Other,
+}
+
+impl ErrorKind {
+    /// Whether an operation failing with this kind of error could succeed if attempted again.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ErrorKind::Network | ErrorKind::Timeout | ErrorKind::Server
+        )
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorKind::NotFound => "not found",
+            ErrorKind::Unauthorized => "unauthorized",
+            ErrorKind::Network => "network error",
+            ErrorKind::Timeout => "timed out",
+            ErrorKind::Conflict => "conflict",
+            ErrorKind::InvalidInput => "invalid input",
+            ErrorKind::Server => "server error",
+            ErrorKind::Other => "other error",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A failed request to a menmos cluster.
+#[derive(Debug)]
+pub struct RequestError {
+    kind: ErrorKind,
+    source: Box<dyn Error + Send + Sync>,
+}
+
+impl RequestError {
+    pub fn new<E: Into<Box<dyn Error + Send + Sync>>>(kind: ErrorKind, error: E) -> Self {
+        Self {
+            kind,
+            source: error.into(),
+        }
+    }
+
+    /// Wrap an error returned by the low-level menmos client, classifying it along the way.
+    pub(crate) fn from_client<E: Error + Send + Sync + 'static>(error: E) -> Self {
+        let kind = classify(&error);
+        Self::new(kind, error)
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.kind.is_retryable()
+    }
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl Error for RequestError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+fn classify(error: &(dyn Error + 'static)) -> ErrorKind {
+    // Transport failures are reported by reqwest somewhere down the cause chain.
+    let mut current = Some(error);
+    while let Some(e) = current {
+        if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+            if e.is_timeout() {
+                return ErrorKind::Timeout;
+            }
+            if e.is_connect() || e.is_request() || e.is_body() {
+                return ErrorKind::Network;
+            }
+            if e.status().map(|s| s.is_server_error()).unwrap_or(false) {
+                return ErrorKind::Server;
+            }
+        }
+        current = e.source();
+    }
+
+    // The menmos client doesn't expose its error variants or the status codes, so errors
+    // can only be told apart by their message. Client errors can be wrapped, e.g. by the
+    // client builder, so the whole cause chain is considered.
+    let mut current = Some(error);
+    while let Some(e) = current {
+        if let Some(kind) = classify_message(&e.to_string().to_lowercase()) {
+            return kind;
+        }
+        current = e.source();
+    }
+
+    ErrorKind::Other
+}
+
+/// Classify a menmos client error from its message, if it is one we know.
+fn classify_message(message: &str) -> Option<ErrorKind> {
+    if let Some(reply) = message.strip_prefix("server returned an error: ") {
+        return Some(classify_reply(reply));
+    }
+
+    let kind = if message.starts_with("file [") && message.ends_with("] does not exist") {
+        ErrorKind::NotFound
+    } else if message.starts_with("failed to serialize metadata")
+        || message.starts_with("failed to build request")
+    {
+        ErrorKind::InvalidInput
+    } else if message == "too many retries" || message.starts_with("the redirect limit of") {
+        ErrorKind::Network
+    } else if message == "did not get a redirect when expected" {
+        // The cluster answered a request it should have redirected, which it does on failures.
+        ErrorKind::Server
+    } else {
+        return None;
+    };
+    Some(kind)
+}
+
+/// Classify the error message sent by the cluster along with a non-success status.
+fn classify_reply(reply: &str) -> ErrorKind {
+    let matches_any = |needles: &[&str]| needles.iter().any(|n| reply.contains(n));
+
+    if matches_any(&["not found", "does not exist", "no such"]) {
+        ErrorKind::NotFound
+    } else if reply == "forbidden"
+        || matches_any(&[
+            "unauthorized",
+            "permission denied",
+            "invalid credentials",
+            "invalid token",
+        ])
+    {
+        ErrorKind::Unauthorized
+    } else if matches_any(&["already exists", "conflict"]) {
+        ErrorKind::Conflict
+    } else if reply.starts_with("bad request") {
+        ErrorKind::InvalidInput
+    } else if reply == "unknown error"
+        || matches_any(&[
+            "internal server error",
+            "bad gateway",
+            "service unavailable",
+            "gateway timeout",
+        ])
+    {
+        // "unknown error" is what the cluster answers with a 500 for unhandled failures.
+        ErrorKind::Server
+    } else {
+        ErrorKind::Other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Message(&'static str);
+
+    impl fmt::Display for Message {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    impl Error for Message {}
+
+    #[test]
+    fn classify_server_messages() {
+        let cases = [
+            (
+                "server returned an error: blob not found",
+                ErrorKind::NotFound,
+            ),
+            (
+                "server returned an error: unauthorized",
+                ErrorKind::Unauthorized,
+            ),
+            (
+                "server returned an error: blob already exists",
+                ErrorKind::Conflict,
+            ),
+            (
+                "server returned an error: forbidden",
+                ErrorKind::Unauthorized,
+            ),
+            (
+                "server returned an error: bad request",
+                ErrorKind::InvalidInput,
+            ),
+            ("server returned an error: unknown error", ErrorKind::Server),
+            (
+                "server returned an error: injected failure: 503 Service Unavailable",
+                ErrorKind::Server,
+            ),
+            ("server returned an error: boom", ErrorKind::Other),
+            ("server returned an error: invalid value", ErrorKind::Other),
+            ("file [\"a.txt\"] does not exist", ErrorKind::NotFound),
+            (
+                "failed to serialize metadata [meta]: oops",
+                ErrorKind::InvalidInput,
+            ),
+            ("the redirect limit of 3 was exceeded", ErrorKind::Network),
+            ("too many retries", ErrorKind::Network),
+            ("did not get a redirect when expected", ErrorKind::Server),
+            ("unknown error", ErrorKind::Other),
+            ("failed to deserialize response: eof", ErrorKind::Other),
+        ];
+
+        for (message, expected) in cases {
+            assert_eq!(
+                RequestError::from_client(Message(message)).kind(),
+                expected,
+                "{}",
+                message
+            );
+        }
+    }
+
+    #[derive(Debug)]
+    struct Wrapped(Message);
+
+    impl fmt::Display for Wrapped {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "failed to build: {}", self.0)
+        }
+    }
+
+    impl Error for Wrapped {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[test]
+    fn classify_wrapped_messages() {
+        let err = RequestError::from_client(Wrapped(Message(
+            "server returned an error: invalid credentials",
+        )));
+        assert_eq!(err.kind(), ErrorKind::Unauthorized);
+
+        let err = RequestError::from_client(Wrapped(Message("missing password")));
+        assert_eq!(err.kind(), ErrorKind::Other);
+    }
+
+    #[test]
+    fn retryable_kinds() {
+        assert!(ErrorKind::Timeout.is_retryable());
+        assert!(ErrorKind::Network.is_retryable());
+        assert!(ErrorKind::Server.is_retryable());
+        assert!(!ErrorKind::NotFound.is_retryable());
+        assert!(!ErrorKind::Unauthorized.is_retryable());
+    }
+
+    #[test]
+    fn sdk_errors_compose_into_menmos_error() {
+        fn fs_op() -> Result<(), FsError> {
+            Err(FsError::DirIsNotEmptyError {
+                blob_id: String::from("a"),
+            })
+        }
+
+        fn sdk_op() -> Result<(), crate::MenmosError> {
+            fs_op()?;
+            Ok(())
+        }
+
+        let err = sdk_op().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Conflict);
+        assert!(!err.is_retryable());
+    }
+
+    #[test]
+    fn cause_chain_is_preserved() {
+        let err = RequestError::from_client(Message("server returned an error: boom"));
+        assert_eq!(
+            err.source().unwrap().to_string(),
+            "server returned an error: boom"
+        );
+    }
+}
@@ -0,0 +1,468 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bytes::{Bytes, BytesMut};
+
+use crate::backend;
+use crate::BackendRC;
+
+pub(crate) type BlockCacheRC = Arc<BlockCache>;
+
+/// Statistics about the use of a [`BlockCache`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// The number of blocks served from the cache.
+    pub hits: u64,
+
+    /// The number of blocks that had to be fetched from the cluster.
+    pub misses: u64,
+
+    /// The number of blocks currently in the cache.
+    pub blocks: usize,
+
+    /// The number of bytes currently in the cache.
+    pub bytes: usize,
+}
+
+struct Block {
+    data: Bytes,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    /// The cached blocks of each blob, by block index.
+    blobs: HashMap<String, BTreeMap<u64, Block>>,
+
+    /// The cached blocks by last use, least recently used first.
+    recency: BTreeMap<u64, (String, u64)>,
+
+    tick: u64,
+    bytes: usize,
+
+    /// Bumped on every invalidation, so fetches started before it don't cache stale data.
+    generation: u64,
+}
+
+impl CacheState {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn get(&mut self, blob_id: &str, index: u64) -> Option<Bytes> {
+        let tick = self.next_tick();
+        let block = self.blobs.get_mut(blob_id)?.get_mut(&index)?;
+
+        let key = self.recency.remove(&block.last_used).unwrap();
+        self.recency.insert(tick, key);
+        block.last_used = tick;
+
+        Some(block.data.clone())
+    }
+
+    fn insert(&mut self, blob_id: &str, index: u64, data: Bytes) {
+        let tick = self.next_tick();
+        self.bytes += data.len();
+        self.recency.insert(tick, (String::from(blob_id), index));
+
+        let block = Block {
+            data,
+            last_used: tick,
+        };
+        if let Some(previous) = self
+            .blobs
+            .entry(String::from(blob_id))
+            .or_default()
+            .insert(index, block)
+        {
+            self.bytes -= previous.data.len();
+            self.recency.remove(&previous.last_used);
+        }
+    }
+
+    fn remove(&mut self, blob_id: &str, index: u64) {
+        let blocks = match self.blobs.get_mut(blob_id) {
+            Some(blocks) => blocks,
+            None => return,
+        };
+
+        if let Some(block) = blocks.remove(&index) {
+            self.bytes -= block.data.len();
+            self.recency.remove(&block.last_used);
+        }
+
+        if blocks.is_empty() {
+            self.blobs.remove(blob_id);
+        }
+    }
+
+    fn evict_to(&mut self, capacity: usize) {
+        while self.bytes > capacity {
+            let (blob_id, index) = match self.recency.values().next() {
+                Some(key) => key.clone(),
+                None => break,
+            };
+            self.remove(&blob_id, index);
+        }
+    }
+}
+
+/// An in-memory cache of file contents, split in fixed-size blocks.
+///
+/// Workloads doing many small random reads of the same regions of a file, like databases,
+/// archive indexes or image tiles, can be served from memory instead of sending a request
+/// for every read. Blocks are aligned on multiples of the block size, and the least recently
+/// used blocks are evicted once the cached data exceeds the capacity of the cache.
+///
+/// The cache is opt-in, and is shared by all the file handles of a client. It is used by
+/// [`MenmosFile::read`](crate::fs::MenmosFile::read), [`MenmosFile::read_at`](crate::fs::MenmosFile::read_at)
+/// and the async read traits. Bulk reads like [`MenmosFile::read_to_end`](crate::fs::MenmosFile::read_to_end),
+/// [`MenmosFile::read_ranges`](crate::fs::MenmosFile::read_ranges) and the [`PrefetchReader`](crate::fs::PrefetchReader)
+/// bypass it, so they don't evict the hot blocks.
+///
+/// Writes made through handles sharing the cache invalidate the blocks they touch. Changes made
+/// by other clients are not seen until the blocks are evicted, so the cache should only be
+/// enabled for blobs that aren't modified elsewhere, or cleared with [`BlockCache::clear`].
+///
+/// # Examples
+/// ```
+/// use menmos::{backend::MemoryBackend, fs::BlockCache, FileMetadata, Menmos};
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// // Cache up to 64 MiB of data, in blocks of 64 KiB.
+/// let client = Menmos::from_backend(MemoryBackend::new())
+///     .with_block_cache(BlockCache::new(64 * 1024, 64 * 1024 * 1024));
+///
+/// let file = client.fs.create_file(FileMetadata::new("tiles.bin")).await.unwrap();
+/// file.write_at(0, &[1; 1024]).await.unwrap();
+///
+/// let mut buf = [0; 16];
+/// file.read_at(0, &mut buf).await.unwrap();
+/// file.read_at(512, &mut buf).await.unwrap();
+///
+/// let stats = client.block_cache().unwrap().stats();
+/// assert_eq!((stats.hits, stats.misses), (1, 1));
+/// # }
+/// ```
+pub struct BlockCache {
+    block_size: u64,
+    capacity: usize,
+    state: Mutex<CacheState>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl BlockCache {
+    /// Create a cache storing up to `capacity` bytes, in blocks of `block_size` bytes.
+    ///
+    /// The minimum block size is one byte.
+    pub fn new(block_size: usize, capacity: usize) -> Self {
+        Self {
+            block_size: block_size.max(1) as u64,
+            capacity,
+            state: Mutex::new(CacheState::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the size of the blocks of this cache, in bytes.
+    pub fn block_size(&self) -> usize {
+        self.block_size as usize
+    }
+
+    /// Get the maximum number of bytes stored by this cache.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Get the hit and miss counters of this cache, along with its current size.
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state.lock().unwrap();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            blocks: state.recency.len(),
+            bytes: state.bytes,
+        }
+    }
+
+    /// Remove all blocks from the cache. The counters are kept.
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.blobs.clear();
+        state.recency.clear();
+        state.bytes = 0;
+        state.generation += 1;
+    }
+
+    /// Remove the blocks overlapping `len` bytes at `offset` in a blob.
+    pub(crate) fn invalidate(&self, blob_id: &str, offset: u64, len: u64) {
+        if len == 0 {
+            return;
+        }
+
+        let first = offset / self.block_size;
+        let last = offset.saturating_add(len - 1) / self.block_size;
+
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+
+        let indices: Vec<u64> = match state.blobs.get(blob_id) {
+            Some(blocks) => {
+                let mut indices: Vec<u64> = blocks.range(first..=last).map(|(i, _)| *i).collect();
+
+                // The last block of the blob is partial, and won't be once the blob is extended.
+                if let Some((index, block)) = blocks.range(..first).next_back() {
+                    if (block.data.len() as u64) < self.block_size {
+                        indices.push(*index);
+                    }
+                }
+
+                indices
+            }
+            None => return,
+        };
+
+        for index in indices {
+            state.remove(blob_id, index);
+        }
+    }
+
+    /// Remove all the blocks of a blob.
+    pub(crate) fn invalidate_blob(&self, blob_id: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+
+        if let Some(blocks) = state.blobs.remove(blob_id) {
+            for block in blocks.into_values() {
+                state.bytes -= block.data.len();
+                state.recency.remove(&block.last_used);
+            }
+        }
+    }
+
+    /// Read an end-inclusive range of a blob of `size` bytes, fetching the missing blocks.
+    ///
+    /// Consecutive missing blocks are fetched in a single request. Like
+    /// [`Backend::read_range`](crate::backend::Backend::read_range), fewer bytes than
+    /// requested can be returned.
+    pub(crate) async fn read(
+        &self,
+        backend: &BackendRC,
+        blob_id: &str,
+        range: (u64, u64),
+        size: u64,
+    ) -> backend::Result<Vec<u8>> {
+        let first = range.0 / self.block_size;
+        let last = range.1 / self.block_size;
+
+        let (mut blocks, generation) = {
+            let mut state = self.state.lock().unwrap();
+            let blocks: Vec<Option<Bytes>> = (first..=last)
+                .map(|i| {
+                    // A partial block is stale if the blob was extended since it was cached.
+                    state.get(blob_id, i).filter(|block| {
+                        block.len() as u64 == self.block_size
+                            || i * self.block_size + block.len() as u64 >= size
+                    })
+                })
+                .collect();
+            (blocks, state.generation)
+        };
+
+        let missing = blocks.iter().filter(|b| b.is_none()).count() as u64;
+        self.hits
+            .fetch_add(blocks.len() as u64 - missing, Ordering::Relaxed);
+        self.misses.fetch_add(missing, Ordering::Relaxed);
+
+        let mut start = 0;
+        while start < blocks.len() {
+            if blocks[start].is_some() {
+                start += 1;
+                continue;
+            }
+
+            let mut end = start;
+            while end < blocks.len() && blocks[end].is_none() {
+                end += 1;
+            }
+
+            let fetch_start = (first + start as u64) * self.block_size;
+            let fetch_end = ((first + end as u64) * self.block_size).min(size.max(fetch_start + 1));
+            let data = backend
+                .read_range(blob_id, (fetch_start, fetch_end - 1))
+                .await?;
+
+            let mut fetched = Vec::new();
+            for (i, chunk) in data.chunks(self.block_size as usize).enumerate() {
+                let index = first + (start + i) as u64;
+                let block = Bytes::copy_from_slice(chunk);
+
+                // A partial block can only be cached if it is the end of the blob,
+                // and not a short response.
+                let block_end = index * self.block_size + block.len() as u64;
+                if block.len() as u64 == self.block_size || block_end == size {
+                    fetched.push((index, block.clone()));
+                }
+
+                blocks[start + i] = Some(block);
+            }
+
+            self.insert(blob_id, fetched, generation);
+            start = end;
+        }
+
+        // Assemble the blocks, stopping at the first one that is missing or partial.
+        let mut out = BytesMut::new();
+        for block in blocks.iter() {
+            match block {
+                Some(block) => {
+                    out.extend_from_slice(block);
+                    if (block.len() as u64) < self.block_size {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+
+        let skip = (range.0 - first * self.block_size) as usize;
+        let len = (range.1 - range.0 + 1) as usize;
+        if skip >= out.len() {
+            return Ok(Vec::new());
+        }
+        out.truncate(skip.saturating_add(len).min(out.len()));
+        Ok(out[skip..].to_vec())
+    }
+
+    fn insert(&self, blob_id: &str, blocks: Vec<(u64, Bytes)>, generation: u64) {
+        let mut state = self.state.lock().unwrap();
+
+        // The blob was written to since the blocks were requested.
+        if state.generation != generation {
+            return;
+        }
+
+        for (index, data) in blocks {
+            state.insert(blob_id, index, data);
+        }
+        state.evict_to(self.capacity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::fs::MenmosFile;
+    use crate::FileMetadata;
+
+    async fn blob_with(content: &[u8]) -> (BackendRC, String) {
+        let backend: BackendRC = Arc::new(MemoryBackend::new());
+        let file = MenmosFile::create(backend.clone(), FileMetadata::new("test.bin"))
+            .await
+            .unwrap();
+        file.write_at(0, content).await.unwrap();
+        (backend, String::from(file.id()))
+    }
+
+    fn content() -> Vec<u8> {
+        (0..100_u8).collect()
+    }
+
+    #[tokio::test]
+    async fn reads_are_served_from_blocks() {
+        let content = content();
+        let (backend, id) = blob_with(&content).await;
+        let cache = BlockCache::new(16, 1024);
+
+        let data = cache.read(&backend, &id, (10, 40), 100).await.unwrap();
+        assert_eq!(data, &content[10..=40]);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 0,
+                misses: 3,
+                blocks: 3,
+                bytes: 48
+            }
+        );
+
+        let data = cache.read(&backend, &id, (20, 60), 100).await.unwrap();
+        assert_eq!(data, &content[20..=60]);
+        assert_eq!(cache.stats().hits, 2);
+        assert_eq!(cache.stats().misses, 4);
+
+        // The last block is partial, and is cached since it ends the blob.
+        let data = cache.read(&backend, &id, (90, 120), 100).await.unwrap();
+        assert_eq!(data, &content[90..]);
+        let data = cache.read(&backend, &id, (97, 120), 100).await.unwrap();
+        assert_eq!(data, &content[97..]);
+        assert_eq!(cache.stats().bytes, 16 * 5 + 4);
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_blocks_are_evicted() {
+        let content = content();
+        let (backend, id) = blob_with(&content).await;
+        let cache = BlockCache::new(10, 30);
+
+        cache.read(&backend, &id, (0, 9), 100).await.unwrap();
+        cache.read(&backend, &id, (10, 19), 100).await.unwrap();
+        cache.read(&backend, &id, (20, 29), 100).await.unwrap();
+        cache.read(&backend, &id, (0, 9), 100).await.unwrap();
+        assert_eq!(cache.stats().hits, 1);
+
+        // Block 1 is the least recently used one.
+        cache.read(&backend, &id, (30, 39), 100).await.unwrap();
+        assert_eq!(cache.stats().blocks, 3);
+
+        cache.read(&backend, &id, (0, 9), 100).await.unwrap();
+        cache.read(&backend, &id, (10, 19), 100).await.unwrap();
+        assert_eq!(cache.stats().hits, 2);
+        assert_eq!(cache.stats().misses, 5);
+        assert_eq!(cache.stats().bytes, 30);
+    }
+
+    #[tokio::test]
+    async fn invalidation_removes_overlapping_blocks() {
+        let content = content();
+        let (backend, id) = blob_with(&content).await;
+        let cache = BlockCache::new(16, 1024);
+
+        cache.read(&backend, &id, (0, 99), 100).await.unwrap();
+        assert_eq!(cache.stats().blocks, 7);
+
+        cache.invalidate(&id, 20, 20);
+        assert_eq!(cache.stats().blocks, 5);
+
+        // Writing past the end of the blob removes the partial last block.
+        cache.invalidate(&id, 200, 10);
+        assert_eq!(cache.stats().blocks, 4);
+
+        cache.invalidate_blob(&id);
+        assert_eq!(cache.stats().blocks, 0);
+        assert_eq!(cache.stats().bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn stale_fetches_are_not_cached() {
+        let content = content();
+        let (backend, id) = blob_with(&content).await;
+        let cache = BlockCache::new(16, 1024);
+
+        cache.insert(&id, vec![(0, Bytes::from_static(b"stale"))], 0);
+        assert_eq!(cache.stats().blocks, 1);
+
+        cache.invalidate(&id, 0, 1);
+        cache.insert(&id, vec![(0, Bytes::from_static(b"stale"))], 0);
+        assert_eq!(cache.stats().blocks, 0);
+
+        let data = cache.read(&backend, &id, (0, 3), 100).await.unwrap();
+        assert_eq!(data, &content[..4]);
+    }
+}
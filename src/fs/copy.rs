@@ -0,0 +1,579 @@
+use std::collections::{HashMap, VecDeque};
+
+use futures::{StreamExt, TryStreamExt};
+
+use interface::BlobMeta;
+use menmos_client::{Query, Type};
+
+use snafu::prelude::*;
+
+use super::error::*;
+use super::{MenmosDirectory, MenmosFile, MenmosFs};
+use crate::util;
+use crate::FileMetadata;
+
+const DEFAULT_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Options for copying files and directories.
+#[derive(Clone, Debug)]
+pub struct CopyOptions {
+    chunk_size: usize,
+    concurrency: usize,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+}
+
+impl CopyOptions {
+    /// Set the number of bytes read and written per request. The default is 8 MiB,
+    /// and the minimum is one byte.
+    #[must_use]
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Set the number of files copied concurrently. The default is 4 files,
+    /// and the minimum is one file.
+    #[must_use]
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+}
+
+/// A blob found while walking the tree being copied.
+struct TreeEntry {
+    id: String,
+    meta: BlobMeta,
+
+    /// The parents of the blob that are part of the tree.
+    parents: Vec<String>,
+}
+
+/// Get the metadata of the copy of a blob.
+fn copy_metadata(meta: BlobMeta, parents: Vec<String>) -> FileMetadata {
+    FileMetadata {
+        parents,
+        size: 0,
+        ..FileMetadata::from(meta)
+    }
+}
+
+impl MenmosFs {
+    /// Copy a file to a directory.
+    ///
+    /// The copy keeps the name, tags and metadata of the original file. Its contents
+    /// are streamed from the original, without being buffered in memory.
+    ///
+    /// This function will return a handle to the copy, at offset 0.
+    ///
+    /// # Errors
+    /// If `id` is not a file or `dest_dir` is not a directory, an error variant is returned.
+    ///
+    /// # Examples
+    /// ```
+    /// use menmos::{backend::MemoryBackend, FileMetadata, Menmos};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let client = Menmos::from_backend(MemoryBackend::new());
+    /// let backups = client.fs.create_dir(FileMetadata::new("backups")).await.unwrap();
+    /// let file = client.fs.create_file(FileMetadata::new("db.sqlite")).await.unwrap();
+    ///
+    /// let copy = client.fs.copy_file(file.id(), backups.id()).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn copy_file<S: AsRef<str>, D: AsRef<str>>(
+        &self,
+        id: S,
+        dest_dir: D,
+    ) -> Result<MenmosFile> {
+        self.copy_file_to(id, self, dest_dir, CopyOptions::default())
+            .await
+    }
+
+    /// Copy a file to a directory of another filesystem.
+    ///
+    /// `target` can be the filesystem of another [`Menmos`](crate::Menmos) client, to copy
+    /// files between clusters or profiles. It can also be this filesystem.
+    ///
+    /// The file is copied like with [`MenmosFs::copy_file`], `dest_dir` being a directory
+    /// of `target`.
+    pub async fn copy_file_to<S: AsRef<str>, D: AsRef<str>>(
+        &self,
+        id: S,
+        target: &MenmosFs,
+        dest_dir: D,
+        options: CopyOptions,
+    ) -> Result<MenmosFile> {
+        let id = id.as_ref();
+
+        let meta = self.blob_meta(id).await?;
+        ensure!(
+            meta.blob_type == Type::File,
+            ExpectedFileSnafu { blob_id: id }
+        );
+        target.ensure_directory(dest_dir.as_ref()).await?;
+
+        let metadata = copy_metadata(meta.clone(), vec![String::from(dest_dir.as_ref())]);
+        self.copy_file_content(id, meta, target, metadata, &options)
+            .await
+    }
+
+    /// Recursively copy a directory along with all its children to another directory.
+    ///
+    /// The copies keep the names, tags and metadata of the originals, and the structure of the
+    /// tree is preserved. Blobs linked to several directories of the tree are copied once,
+    /// and the copy is linked to the copies of these directories.
+    ///
+    /// The tree is listed before anything is copied, so a directory can be copied to one of
+    /// its own children. Files are copied concurrently.
+    ///
+    /// This function will return a handle to the copy of the directory.
+    ///
+    /// # Errors
+    /// If `id` or `dest_dir` are not directories, an error variant is returned. If the copy
+    /// fails midway, the blobs copied so far are left in place.
+    ///
+    /// # Examples
+    /// ```
+    /// use menmos::{backend::MemoryBackend, FileMetadata, Menmos};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let client = Menmos::from_backend(MemoryBackend::new());
+    /// let dataset = client.fs.create_dir(FileMetadata::new("dataset")).await.unwrap();
+    /// let archive = client.fs.create_dir(FileMetadata::new("archive")).await.unwrap();
+    /// client
+    ///     .fs
+    ///     .create_file(FileMetadata::new("samples.csv").with_parent(dataset.id()))
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// let copy = client.fs.copy_dir_all(dataset.id(), archive.id()).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn copy_dir_all<S: AsRef<str>, D: AsRef<str>>(
+        &self,
+        id: S,
+        dest_dir: D,
+    ) -> Result<MenmosDirectory> {
+        self.copy_dir_all_to(id, self, dest_dir, CopyOptions::default())
+            .await
+    }
+
+    /// Recursively copy a directory to a directory of another filesystem.
+    ///
+    /// `target` can be the filesystem of another [`Menmos`](crate::Menmos) client, to migrate
+    /// trees between clusters or profiles. It can also be this filesystem.
+    ///
+    /// The directory is copied like with [`MenmosFs::copy_dir_all`], `dest_dir` being a directory
+    /// of `target`.
+    ///
+    /// # Examples
+    /// ```
+    /// use menmos::{backend::MemoryBackend, fs::CopyOptions, FileMetadata, Menmos};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let staging = Menmos::from_backend(MemoryBackend::new());
+    /// let production = Menmos::from_backend(MemoryBackend::new());
+    ///
+    /// let site = staging.fs.create_dir(FileMetadata::new("site")).await.unwrap();
+    /// let releases = production.fs.create_dir(FileMetadata::new("releases")).await.unwrap();
+    ///
+    /// let options = CopyOptions::default().with_concurrency(16);
+    /// staging
+    ///     .fs
+    ///     .copy_dir_all_to(site.id(), &production.fs, releases.id(), options)
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub async fn copy_dir_all_to<S: AsRef<str>, D: AsRef<str>>(
+        &self,
+        id: S,
+        target: &MenmosFs,
+        dest_dir: D,
+        options: CopyOptions,
+    ) -> Result<MenmosDirectory> {
+        let id = id.as_ref();
+        let dest_dir = dest_dir.as_ref();
+
+        let meta = self.blob_meta(id).await?;
+        ensure!(
+            meta.blob_type == Type::Directory,
+            ExpectedDirectorySnafu { blob_id: id }
+        );
+        target.ensure_directory(dest_dir).await?;
+
+        let (dirs, files) = self.list_tree(id, meta).await?;
+
+        // Directories are listed breadth-first, so the first parent of a directory is always
+        // copied before it. The other parents are linked once all directories exist.
+        let mut copies: HashMap<String, String> = HashMap::new();
+        let mut root = None;
+        for dir in dirs.iter() {
+            let parent = dir
+                .parents
+                .first()
+                .and_then(|parent| copies.get(parent))
+                .cloned()
+                .unwrap_or_else(|| String::from(dest_dir));
+
+            let copy = target
+                .create_dir(copy_metadata(dir.meta.clone(), vec![parent]))
+                .await?;
+            copies.insert(dir.id.clone(), String::from(copy.id()));
+
+            if root.is_none() {
+                root = Some(copy);
+            }
+        }
+
+        for dir in dirs.iter().filter(|d| d.parents.len() > 1) {
+            let copy_id = match copies.get(&dir.id) {
+                Some(copy_id) => copy_id,
+                None => continue,
+            };
+            let mut copy_meta = target.blob_meta(copy_id).await?;
+            copy_meta.parents.extend(
+                dir.parents[1..]
+                    .iter()
+                    .filter_map(|parent| copies.get(parent).cloned()),
+            );
+            target.replace_meta(copy_id, copy_meta).await?;
+        }
+
+        let copies = &copies;
+        let options = &options;
+        futures::stream::iter(files)
+            .map(|file| async move {
+                let parents = file
+                    .parents
+                    .iter()
+                    .filter_map(|p| copies.get(p).cloned())
+                    .collect();
+                let metadata = copy_metadata(file.meta.clone(), parents);
+                self.copy_file_content(&file.id, file.meta, target, metadata, options)
+                    .await
+            })
+            .buffer_unordered(options.concurrency)
+            .try_for_each(|_| futures::future::ok(()))
+            .await?;
+
+        // The root is the first directory listed.
+        Ok(root.expect("the copied directory is always listed"))
+    }
+
+    async fn ensure_directory(&self, id: &str) -> Result<()> {
+        let meta = self.blob_meta(id).await?;
+        ensure!(
+            meta.blob_type == Type::Directory,
+            ExpectedDirectorySnafu { blob_id: id }
+        );
+        Ok(())
+    }
+
+    /// List the directories and files of a tree, breadth-first.
+    ///
+    /// The root directory is the first directory returned, and never has parents: if it is also
+    /// linked under one of its descendants, that link is not part of the copy. Blobs found in
+    /// several directories are only returned once.
+    async fn list_tree(
+        &self,
+        id: &str,
+        meta: BlobMeta,
+    ) -> Result<(Vec<TreeEntry>, Vec<TreeEntry>)> {
+        let mut dirs = vec![TreeEntry {
+            id: String::from(id),
+            meta,
+            parents: Vec::new(),
+        }];
+        let mut files = Vec::new();
+
+        // Maps the blobs we've seen to whether they're a directory and their position in its list.
+        let mut seen = HashMap::from([(String::from(id), (true, 0))]);
+        let mut pending = VecDeque::from([String::from(id)]);
+
+        while let Some(dir_id) = pending.pop_front() {
+            let query = Query::default()
+                .and_parent(&dir_id)
+                .with_from(0)
+                .with_size(self.defaults.page_size);
+            let children = util::scroll_query(query, &self.backend)
+                .try_collect::<Vec<_>>()
+                .await
+                .context(DirQuerySnafu)?;
+
+            for hit in children {
+                if let Some((is_dir, index)) = seen.get(&hit.id) {
+                    if *is_dir && *index == 0 {
+                        continue;
+                    }
+
+                    let entry = if *is_dir {
+                        &mut dirs[*index]
+                    } else {
+                        &mut files[*index]
+                    };
+                    entry.parents.push(dir_id.clone());
+                    continue;
+                }
+
+                let entry = TreeEntry {
+                    id: hit.id.clone(),
+                    meta: hit.meta,
+                    parents: vec![dir_id.clone()],
+                };
+
+                if entry.meta.blob_type == Type::Directory {
+                    seen.insert(hit.id.clone(), (true, dirs.len()));
+                    pending.push_back(hit.id);
+                    dirs.push(entry);
+                } else {
+                    seen.insert(hit.id, (false, files.len()));
+                    files.push(entry);
+                }
+            }
+        }
+
+        Ok((dirs, files))
+    }
+
+    async fn copy_file_content(
+        &self,
+        id: &str,
+        meta: BlobMeta,
+        target: &MenmosFs,
+        metadata: FileMetadata,
+        options: &CopyOptions,
+    ) -> Result<MenmosFile> {
+        let source = MenmosFile::open_raw(self.backend.clone(), id, meta)?;
+        let chunks = source.stream_chunks(None, options.chunk_size as u64);
+
+        target
+            .create_file_from_stream_chunked(metadata, chunks, options.chunk_size)
+            .await
+            .map_err(|e| match e {
+                // Errors reading the original are reported as they are.
+                FsError::UploadSourceError { source } => match source.downcast::<FsError>() {
+                    Ok(e) => *e,
+                    Err(source) => FsError::UploadSourceError { source },
+                },
+                e => e,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::backend::{Backend, MemoryBackend};
+    use crate::fs::DirEntry;
+
+    use super::*;
+
+    fn options() -> CopyOptions {
+        CopyOptions::default()
+            .with_chunk_size(100)
+            .with_concurrency(2)
+    }
+
+    async fn read_all(fs: &MenmosFs, id: &str) -> Vec<u8> {
+        let mut file = MenmosFile::open(fs.backend.clone(), id).await.unwrap();
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).await.unwrap();
+        buf
+    }
+
+    /// List a directory as sorted (name, id, is_dir) tuples.
+    async fn list(fs: &MenmosFs, dir: &str) -> Vec<(String, String, bool)> {
+        let dir = MenmosDirectory::open(fs.backend.clone(), dir)
+            .await
+            .unwrap();
+        let entries: Vec<DirEntry> = dir.list().try_collect().await.unwrap();
+
+        let mut listing = Vec::new();
+        for entry in entries {
+            let (id, is_dir) = match entry {
+                DirEntry::File(f) => (String::from(f.id()), false),
+                DirEntry::Directory(d) => (String::from(d.id()), true),
+            };
+            listing.push((fs.metadata(&id).await.unwrap().name, id, is_dir));
+        }
+        listing.sort();
+        listing
+    }
+
+    #[tokio::test]
+    async fn copy_files() {
+        let fs = MenmosFs::new(Arc::new(MemoryBackend::new()));
+        let content: Vec<u8> = (0..1000_u32).map(|i| (i % 251) as u8).collect();
+
+        let src = fs.create_dir(FileMetadata::new("src")).await.unwrap();
+        let dest = fs.create_dir(FileMetadata::new("dest")).await.unwrap();
+        let file = fs
+            .create_file(
+                FileMetadata::new("a.bin")
+                    .with_parent(src.id())
+                    .with_tag("data")
+                    .with_meta("owner", "bob"),
+            )
+            .await
+            .unwrap();
+        file.write_at(0, &content).await.unwrap();
+
+        let copy = fs
+            .copy_file_to(file.id(), &fs, dest.id(), options())
+            .await
+            .unwrap();
+        assert_ne!(copy.id(), file.id());
+        assert_eq!(read_all(&fs, copy.id()).await, content);
+
+        let expected = FileMetadata::new("a.bin")
+            .with_parent(dest.id())
+            .with_tag("data")
+            .with_meta("owner", "bob")
+            .with_size(1000);
+        assert_eq!(fs.metadata(copy.id()).await.unwrap(), expected);
+
+        let err = fs
+            .copy_file(src.id(), dest.id())
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert!(matches!(err, FsError::ExpectedFileError { .. }));
+        let err = fs
+            .copy_file(file.id(), file.id())
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert!(matches!(err, FsError::ExpectedDirectoryError { .. }));
+    }
+
+    #[tokio::test]
+    async fn copy_trees_across_filesystems() {
+        let fs = MenmosFs::new(Arc::new(MemoryBackend::new()));
+        let target_backend = MemoryBackend::new();
+        let target = MenmosFs::new(Arc::new(target_backend.clone()));
+
+        // root/{a.txt, sub/{b.txt, shared.txt}, other/shared.txt}
+        let root = fs.create_dir(FileMetadata::new("root")).await.unwrap();
+        let sub = fs
+            .create_dir(FileMetadata::new("sub").with_parent(root.id()))
+            .await
+            .unwrap();
+        let other = fs
+            .create_dir(FileMetadata::new("other").with_parent(root.id()))
+            .await
+            .unwrap();
+        for (name, parents) in [
+            ("a.txt", vec![root.id()]),
+            ("b.txt", vec![sub.id()]),
+            ("shared.txt", vec![sub.id(), other.id()]),
+        ] {
+            let mut metadata = FileMetadata::new(name);
+            metadata.parents = parents.into_iter().map(String::from).collect();
+            let file = fs.create_file(metadata).await.unwrap();
+            file.write_at(0, name.as_bytes()).await.unwrap();
+        }
+        // Linking `sub` into `other` as well is preserved.
+        fs.link(sub.id(), other.id()).await.unwrap();
+
+        let dest = target.create_dir(FileMetadata::new("dest")).await.unwrap();
+        let copy = fs
+            .copy_dir_all_to(root.id(), &target, dest.id(), options())
+            .await
+            .unwrap();
+
+        let top = list(&target, dest.id()).await;
+        assert_eq!(
+            top,
+            vec![(String::from("root"), String::from(copy.id()), true)]
+        );
+
+        let root_listing = list(&target, copy.id()).await;
+        let names: Vec<&str> = root_listing.iter().map(|(n, _, _)| n.as_str()).collect();
+        assert_eq!(names, ["a.txt", "other", "sub"]);
+        assert_eq!(read_all(&target, &root_listing[0].1).await, b"a.txt");
+
+        let other_listing = list(&target, &root_listing[1].1).await;
+        let sub_listing = list(&target, &root_listing[2].1).await;
+        let names: Vec<&str> = sub_listing.iter().map(|(n, _, _)| n.as_str()).collect();
+        assert_eq!(names, ["b.txt", "shared.txt"]);
+
+        // The shared file and the linked directory are copied once.
+        assert_eq!(
+            other_listing,
+            vec![
+                sub_listing[1].clone(),
+                (String::from("sub"), root_listing[2].1.clone(), true)
+            ]
+        );
+        assert_eq!(read_all(&target, &sub_listing[1].1).await, b"shared.txt");
+
+        // dest, root, sub, other and 3 files.
+        let total = target_backend.query(Query::default()).await.unwrap().total;
+        assert_eq!(total, 7);
+    }
+
+    #[tokio::test]
+    async fn copy_dir_into_itself() {
+        let fs = MenmosFs::new(Arc::new(MemoryBackend::new()));
+
+        let root = fs.create_dir(FileMetadata::new("root")).await.unwrap();
+        let sub = fs
+            .create_dir(FileMetadata::new("sub").with_parent(root.id()))
+            .await
+            .unwrap();
+
+        let copy = fs.copy_dir_all(root.id(), sub.id()).await.unwrap();
+
+        // Only the tree as it was before the copy is copied.
+        let names: Vec<String> = list(&fs, copy.id())
+            .await
+            .into_iter()
+            .map(|(name, _, _)| name)
+            .collect();
+        assert_eq!(names, ["sub"]);
+        let sub_copy = &list(&fs, copy.id()).await[0].1;
+        assert!(list(&fs, sub_copy).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn copy_dir_linked_under_itself() {
+        let fs = MenmosFs::new(Arc::new(MemoryBackend::new()));
+
+        let root = fs.create_dir(FileMetadata::new("root")).await.unwrap();
+        let sub = fs
+            .create_dir(FileMetadata::new("sub").with_parent(root.id()))
+            .await
+            .unwrap();
+        // `link` refuses to create cycles, but other clients may have created one.
+        let mut root_meta = fs.blob_meta(root.id()).await.unwrap();
+        root_meta.parents.push(String::from(sub.id()));
+        fs.replace_meta(root.id(), root_meta).await.unwrap();
+        let dest = fs.create_dir(FileMetadata::new("dest")).await.unwrap();
+
+        let copy = fs.copy_dir_all(root.id(), dest.id()).await.unwrap();
+
+        // The link back to the root is not copied.
+        let copy_meta = fs.metadata(copy.id()).await.unwrap();
+        assert_eq!(copy_meta.parents, vec![String::from(dest.id())]);
+        let children = list(&fs, copy.id()).await;
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].0, "sub");
+        assert!(list(&fs, &children[0].1).await.is_empty());
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use futures::{TryStream, TryStreamExt};
 
 use interface::BlobMeta;
@@ -5,8 +7,9 @@ use menmos_client::{Meta, Query, Type};
 
 use snafu::prelude::*;
 
-use crate::{ClientRC, FileMetadata};
+use crate::{BackendRC, Defaults, FileMetadata};
 
+use super::cache::BlockCacheRC;
 use super::error::*;
 use super::file::MenmosFile;
 use crate::util;
@@ -29,33 +32,120 @@ pub enum DirEntry {
     Directory(MenmosDirectory),
 }
 
+impl DirEntry {
+    /// Open a handle to a file or directory from its metadata.
+    pub(crate) fn open_raw(
+        backend: BackendRC,
+        id: &str,
+        meta: BlobMeta,
+        page_size: usize,
+        cache: Option<BlockCacheRC>,
+    ) -> Result<Self> {
+        let entry = if meta.blob_type == Type::File {
+            DirEntry::File(MenmosFile::open_raw(backend, id, meta)?.with_block_cache(cache))
+        } else {
+            DirEntry::Directory(
+                MenmosDirectory::open_raw(backend, id, meta)?
+                    .with_page_size(page_size)
+                    .with_block_cache(cache),
+            )
+        };
+        Ok(entry)
+    }
+
+    /// Returns the ID of this entry.
+    pub fn id(&self) -> &str {
+        match self {
+            DirEntry::File(f) => f.id(),
+            DirEntry::Directory(d) => d.id(),
+        }
+    }
+
+    /// Returns the name of this entry.
+    pub fn name(&self) -> &str {
+        match self {
+            DirEntry::File(f) => f.name(),
+            DirEntry::Directory(d) => d.name(),
+        }
+    }
+
+    /// Returns the size of this entry, in bytes.
+    pub fn size(&self) -> u64 {
+        match self {
+            DirEntry::File(f) => f.size(),
+            DirEntry::Directory(d) => d.size(),
+        }
+    }
+
+    /// Returns the tags of this entry.
+    pub fn tags(&self) -> &[String] {
+        match self {
+            DirEntry::File(f) => f.tags(),
+            DirEntry::Directory(d) => d.tags(),
+        }
+    }
+
+    /// Returns the key/value pairs of this entry.
+    pub fn metadata(&self) -> &HashMap<String, String> {
+        match self {
+            DirEntry::File(f) => f.metadata(),
+            DirEntry::Directory(d) => d.metadata(),
+        }
+    }
+
+    /// Returns the IDs of the parents of this entry.
+    pub fn parents(&self) -> &[String] {
+        match self {
+            DirEntry::File(f) => f.parents(),
+            DirEntry::Directory(d) => d.parents(),
+        }
+    }
+
+    /// Fetch the metadata of this entry again.
+    pub async fn refresh(&mut self) -> Result<()> {
+        match self {
+            DirEntry::File(f) => f.refresh().await,
+            DirEntry::Directory(d) => d.refresh().await,
+        }
+    }
+}
+
 /// A handle to a directory in a menmos cluster.
 #[derive(Clone)]
 pub struct MenmosDirectory {
     blob_id: String,
-    client: ClientRC,
+    backend: BackendRC,
+    page_size: usize,
+    cache: Option<BlockCacheRC>,
+
+    /// The metadata of the directory, as of when the handle was opened or last refreshed.
+    meta: FileMetadata,
 }
 
 impl MenmosDirectory {
     #[doc(hidden)]
-    pub async fn create(client: ClientRC, metadata: FileMetadata) -> Result<Self> {
-        let metadata = make_dir_meta(metadata);
-
-        let blob_id = client
-            .create_empty(metadata)
+    pub async fn create(backend: BackendRC, metadata: FileMetadata) -> Result<Self> {
+        let blob_id = backend
+            .create_empty(make_dir_meta(metadata.clone()))
             .await
-            .map_err(|_| FsError::DirCreateError)?;
+            .context(DirCreateSnafu)?;
 
-        Ok(Self { blob_id, client })
+        Ok(Self {
+            blob_id,
+            backend,
+            page_size: Defaults::default().page_size,
+            cache: None,
+            meta: metadata,
+        })
     }
 
     #[doc(hidden)]
-    pub async fn open(client: ClientRC, id: &str) -> Result<Self> {
-        let metadata = util::get_meta(&client, id).await.context(DirOpenSnafu)?;
-        Self::open_raw(client, id, metadata)
+    pub async fn open(backend: BackendRC, id: &str) -> Result<Self> {
+        let metadata = util::get_meta(&backend, id).await.context(DirOpenSnafu)?;
+        Self::open_raw(backend, id, metadata)
     }
 
-    pub(crate) fn open_raw(client: ClientRC, id: &str, meta: BlobMeta) -> Result<Self> {
+    pub(crate) fn open_raw(backend: BackendRC, id: &str, meta: BlobMeta) -> Result<Self> {
         ensure!(
             meta.blob_type == Type::Directory,
             ExpectedDirectorySnafu {
@@ -65,38 +155,93 @@ impl MenmosDirectory {
 
         Ok(Self {
             blob_id: String::from(id),
-            client,
+            backend,
+            page_size: Defaults::default().page_size,
+            cache: None,
+            meta: FileMetadata::from(meta),
         })
     }
 
+    /// Set the number of entries fetched per request when listing this directory.
+    pub(crate) fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    /// Set the block cache used by the files opened from this directory.
+    pub(crate) fn with_block_cache(mut self, cache: Option<BlockCacheRC>) -> Self {
+        self.cache = cache;
+        self
+    }
+
     /// Returns the ID of this directory.
     pub fn id(&self) -> &str {
         &self.blob_id
     }
 
+    /// Returns the name of this directory.
+    pub fn name(&self) -> &str {
+        &self.meta.name
+    }
+
+    /// Returns the size of this directory, in bytes.
+    pub fn size(&self) -> u64 {
+        self.meta.size
+    }
+
+    /// Returns the tags of this directory.
+    pub fn tags(&self) -> &[String] {
+        &self.meta.tags
+    }
+
+    /// Returns the key/value pairs of this directory.
+    pub fn metadata(&self) -> &HashMap<String, String> {
+        &self.meta.metadata
+    }
+
+    /// Returns the IDs of the parents of this directory.
+    pub fn parents(&self) -> &[String] {
+        &self.meta.parents
+    }
+
+    /// Fetch the metadata of this directory again.
+    ///
+    /// The metadata returned by [`MenmosDirectory::name`], [`MenmosDirectory::tags`] and the
+    /// other accessors is a snapshot taken when the handle was opened or listed, which is not
+    /// updated when the directory is changed elsewhere.
+    pub async fn refresh(&mut self) -> Result<()> {
+        let meta =
+            util::get_meta(&self.backend, &self.blob_id)
+                .await
+                .context(MetadataReadSnafu {
+                    blob_id: self.blob_id.clone(),
+                })?;
+        self.meta = FileMetadata::from(meta);
+        Ok(())
+    }
+
     /// Get a stream of entries present in this directory.
     pub fn list(&self) -> impl TryStream<Ok = DirEntry, Error = FsError> + Unpin {
         let query = Query::default()
             .and_parent(&self.blob_id)
             .with_from(0)
-            .with_size(50);
+            .with_size(self.page_size);
 
-        let client = self.client.clone();
+        let backend = self.backend.clone();
+        let page_size = self.page_size;
+        let cache = self.cache.clone();
         Box::pin(
-            util::scroll_query(query, &client)
+            util::scroll_query(query, &backend)
                 .map_err(|source| FsError::DirQueryError { source })
                 .and_then(move |hit| {
-                    let client = client.clone();
-                    async move {
-                        let entry = if hit.meta.blob_type == Type::File {
-                            DirEntry::File(MenmosFile::open_raw(client, &hit.id, hit.meta)?)
-                        } else {
-                            DirEntry::Directory(MenmosDirectory::open_raw(
-                                client, &hit.id, hit.meta,
-                            )?)
-                        };
-                        Ok(entry)
-                    }
+                    let entry = DirEntry::open_raw(
+                        backend.clone(),
+                        &hit.id,
+                        hit.meta,
+                        page_size,
+                        cache.clone(),
+                    );
+                    futures::future::ready(entry)
                 }),
         )
     }
@@ -104,11 +249,7 @@ impl MenmosDirectory {
     /// Get whether this directory has any children.
     pub async fn is_empty(&self) -> Result<bool> {
         let query = Query::default().and_parent(&self.blob_id).with_size(0);
-        let results = self
-            .client
-            .query(query)
-            .await
-            .map_err(|_| FsError::DirListError)?;
+        let results = self.backend.query(query).await.context(DirListSnafu)?;
 
         Ok(results.total == 0)
     }
@@ -1,29 +1,27 @@
 use snafu::prelude::*;
+use std::path::PathBuf;
 use std::string::FromUtf8Error;
 
+use crate::error::{ErrorKind, RequestError};
 use crate::util;
 
 #[derive(Debug, Snafu)]
-#[snafu(visibility(pub))]
+#[snafu(visibility(pub(crate)))]
 pub enum FsError {
-    // TODO: add source: ClientError once its exposed in menmos-client >= 0.1.0
-    FileCreateError,
+    #[snafu(display("failed to create file: {}", source))]
+    FileCreateError { source: RequestError },
 
-    // TODO: add source: ClientError once its exposed in menmos-client >= 0.1.0
-    #[snafu(display("failed to delete blob '{}'", blob_id))]
+    #[snafu(display("failed to delete blob '{}': {}", blob_id, source))]
     BlobDeleteError {
+        source: RequestError,
         blob_id: String,
     },
 
     #[snafu(display("expected blob '{}' to be a file, found a directory", blob_id))]
-    ExpectedFileError {
-        blob_id: String,
-    },
+    ExpectedFileError { blob_id: String },
 
     #[snafu(display("expected blob '{}' to be a directory, found a file", blob_id))]
-    ExpectedDirectoryError {
-        blob_id: String,
-    },
+    ExpectedDirectoryError { blob_id: String },
 
     #[snafu(display("failed to open file '{}': {}", blob_id, source))]
     FileOpenError {
@@ -31,13 +29,12 @@ pub enum FsError {
         blob_id: String,
     },
 
-    // TODO: add source: ClientError once its exposed in menmos-client >= 0.1.0
-    #[snafu(display("failed to write to file"))]
-    FileWriteError,
+    #[snafu(display("failed to write to file: {}", source))]
+    FileWriteError { source: RequestError },
 
-    // TODO: add source: ClientError once its exposed in menmos-client >= 0.1.0
-    #[snafu(display("failed to read from file '{}'", blob_id))]
+    #[snafu(display("failed to read from file '{}': {}", blob_id, source))]
     FileReadError {
+        source: RequestError,
         blob_id: String,
     },
 
@@ -47,46 +44,196 @@ pub enum FsError {
         blob_id: String,
     },
 
-    // TODO: add source: ClientError once its exposed in menmos-client >= 0.1.0
-    #[snafu(display("failed to create directory"))]
-    DirCreateError,
+    #[snafu(display("failed to create directory: {}", source))]
+    DirCreateError { source: RequestError },
 
     #[snafu(display("failed to open directory: {}", source))]
-    DirOpenError {
-        source: util::UtilError,
-    },
+    DirOpenError { source: util::UtilError },
 
-    // TODO: add source: ClientError once its exposed in menmos-client >= 0.1.0
-    #[snafu(display("failed to list directory"))]
-    DirListError,
+    #[snafu(display("failed to list directory: {}", source))]
+    DirListError { source: RequestError },
 
-    #[snafu(display("failed to query directory"))]
-    DirQueryError {
-        source: util::UtilError,
-    },
+    #[snafu(display("failed to query directory: {}", source))]
+    DirQueryError { source: util::UtilError },
 
     #[snafu(display("failed to remove directory: {}", source))]
-    DirRemoveError {
-        source: util::UtilError,
-    },
+    DirRemoveError { source: util::UtilError },
 
     #[snafu(display("directory '{}' is not empty", blob_id))]
-    DirIsNotEmptyError {
-        blob_id: String,
-    },
+    DirIsNotEmptyError { blob_id: String },
 
-    #[snafu(display("failed to get blob size for seeking"))]
-    SeekMetaError {
-        source: util::UtilError,
-    },
+    #[snafu(display("failed to get blob size for seeking: {}", source))]
+    SeekMetaError { source: util::UtilError },
+
+    #[snafu(display("reached the end of file '{}' before filling the buffer", blob_id))]
+    UnexpectedEofError { blob_id: String },
+
+    #[snafu(display("a prefetch request for file '{}' was cancelled", blob_id))]
+    PrefetchCancelledError { blob_id: String },
 
     #[snafu(display("seek reached a negative offset"))]
     NegativeOffsetError,
 
     #[snafu(display("buffer value is not valid UTF-8"))]
-    BufferEncodingError {
-        source: FromUtf8Error,
+    BufferEncodingError { source: FromUtf8Error },
+
+    #[snafu(display("failed to update blob '{}': {}", blob_id, source))]
+    BlobUpdateError {
+        source: RequestError,
+        blob_id: String,
+    },
+
+    #[snafu(display("failed to access local file '{}': {}", path.display(), source))]
+    LocalFileError {
+        source: std::io::Error,
+        path: PathBuf,
     },
+
+    #[snafu(display(
+        "downloaded {} bytes of file '{}', expected {}",
+        actual,
+        blob_id,
+        expected
+    ))]
+    DownloadSizeMismatchError {
+        blob_id: String,
+        expected: u64,
+        actual: u64,
+    },
+
+    #[snafu(display("no filesystem root is configured"))]
+    NoRootError,
+
+    #[snafu(display("no root directory is tagged '{}'", tag))]
+    RootNotFoundError { tag: String },
+
+    #[snafu(display("root is ambiguous: {} directories are tagged '{}'", count, tag))]
+    AmbiguousRootError { tag: String, count: usize },
+
+    #[snafu(display("failed to resolve path: {}", source))]
+    PathResolveError { source: util::UtilError },
+
+    #[snafu(display("path '{}' does not exist", path))]
+    PathNotFoundError { path: String },
+
+    #[snafu(display("path '{}' already exists", path))]
+    PathAlreadyExistsError { path: String },
+
+    #[snafu(display("path '{}' is invalid", path))]
+    InvalidPathError { path: String },
+
+    #[snafu(display(
+        "path '{}' is ambiguous: {} blobs are named '{}' in the same directory",
+        path,
+        count,
+        name
+    ))]
+    AmbiguousPathError {
+        path: String,
+        name: String,
+        count: usize,
+    },
+
+    #[snafu(display("blob '{}' can't be reached from the filesystem root", blob_id))]
+    NotUnderRootError { blob_id: String },
+
+    #[snafu(display("failed to get metadata of blob '{}': {}", blob_id, source))]
+    MetadataReadError {
+        source: util::UtilError,
+        blob_id: String,
+    },
+
+    #[snafu(display(
+        "can't move directory '{}' into itself or one of its children",
+        blob_id
+    ))]
+    MoveIntoSelfError { blob_id: String },
+
+    #[snafu(display(
+        "linking directory '{}' into '{}' would create a cycle",
+        blob_id,
+        dir_id
+    ))]
+    LinkCycleError { blob_id: String, dir_id: String },
+
+    #[snafu(display("blob '{}' is not linked to directory '{}'", blob_id, dir_id))]
+    NotLinkedError { blob_id: String, dir_id: String },
+
+    #[snafu(display("failed to read upload source: {}", source))]
+    UploadSourceError {
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+impl FsError {
+    /// Get the kind of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            FsError::FileCreateError { source }
+            | FsError::BlobDeleteError { source, .. }
+            | FsError::FileWriteError { source }
+            | FsError::FileReadError { source, .. }
+            | FsError::DirCreateError { source }
+            | FsError::DirListError { source }
+            | FsError::BlobUpdateError { source, .. } => source.kind(),
+            FsError::FileOpenError { source, .. }
+            | FsError::FileRemoveError { source, .. }
+            | FsError::DirOpenError { source }
+            | FsError::DirQueryError { source }
+            | FsError::DirRemoveError { source }
+            | FsError::SeekMetaError { source }
+            | FsError::PathResolveError { source }
+            | FsError::MetadataReadError { source, .. } => source.kind(),
+            FsError::ExpectedFileError { .. }
+            | FsError::ExpectedDirectoryError { .. }
+            | FsError::UnexpectedEofError { .. }
+            | FsError::NegativeOffsetError
+            | FsError::BufferEncodingError { .. }
+            | FsError::NoRootError
+            | FsError::InvalidPathError { .. }
+            | FsError::MoveIntoSelfError { .. }
+            | FsError::LinkCycleError { .. } => ErrorKind::InvalidInput,
+            FsError::RootNotFoundError { .. }
+            | FsError::PathNotFoundError { .. }
+            | FsError::NotUnderRootError { .. }
+            | FsError::NotLinkedError { .. } => ErrorKind::NotFound,
+            FsError::DirIsNotEmptyError { .. }
+            | FsError::AmbiguousRootError { .. }
+            | FsError::PathAlreadyExistsError { .. }
+            | FsError::AmbiguousPathError { .. } => ErrorKind::Conflict,
+            FsError::LocalFileError { source, .. } => match source.kind() {
+                std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+                std::io::ErrorKind::PermissionDenied => ErrorKind::Unauthorized,
+                _ => ErrorKind::Other,
+            },
+            FsError::DownloadSizeMismatchError { .. } => ErrorKind::Conflict,
+            FsError::UploadSourceError { .. } | FsError::PrefetchCancelledError { .. } => {
+                ErrorKind::Other
+            }
+        }
+    }
+
+    /// Whether the operation that failed with this error could succeed if attempted again.
+    pub fn is_retryable(&self) -> bool {
+        self.kind().is_retryable()
+    }
+}
+
+impl From<FsError> for std::io::Error {
+    fn from(e: FsError) -> Self {
+        let kind = match e.kind() {
+            _ if matches!(e, FsError::UnexpectedEofError { .. }) => {
+                std::io::ErrorKind::UnexpectedEof
+            }
+            ErrorKind::NotFound => std::io::ErrorKind::NotFound,
+            ErrorKind::Unauthorized => std::io::ErrorKind::PermissionDenied,
+            ErrorKind::Network => std::io::ErrorKind::ConnectionAborted,
+            ErrorKind::Timeout => std::io::ErrorKind::TimedOut,
+            ErrorKind::InvalidInput => std::io::ErrorKind::InvalidInput,
+            _ => std::io::ErrorKind::Other,
+        };
+        std::io::Error::new(kind, e)
+    }
 }
 
 pub type Result<T> = std::result::Result<T, FsError>;
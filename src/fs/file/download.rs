@@ -0,0 +1,432 @@
+use std::fmt;
+use std::io::{self, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{StreamExt, TryStreamExt};
+
+use snafu::prelude::*;
+
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+use super::MenmosFile;
+use crate::fs::error::*;
+
+const DEFAULT_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+const DEFAULT_CONCURRENCY: usize = 4;
+
+const DEFAULT_MAX_RETRIES: usize = 3;
+
+const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The progress of a download, reported after each range is written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DownloadProgress {
+    /// The number of bytes written to the local file so far.
+    pub downloaded: u64,
+
+    /// The size of the file being downloaded.
+    pub total: u64,
+}
+
+type ProgressCallback = Arc<dyn Fn(DownloadProgress) + Send + Sync>;
+
+/// Options for [`MenmosFile::download_to`].
+#[derive(Clone)]
+pub struct DownloadOptions {
+    chunk_size: u64,
+    concurrency: usize,
+    max_retries: usize,
+    retry_interval: Duration,
+    progress: Option<ProgressCallback>,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            concurrency: DEFAULT_CONCURRENCY,
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+            progress: None,
+        }
+    }
+}
+
+impl fmt::Debug for DownloadOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DownloadOptions")
+            .field("chunk_size", &self.chunk_size)
+            .field("concurrency", &self.concurrency)
+            .field("max_retries", &self.max_retries)
+            .field("retry_interval", &self.retry_interval)
+            .field("progress", &self.progress.is_some())
+            .finish()
+    }
+}
+
+impl DownloadOptions {
+    /// Set the size of the ranges requested from the cluster. The default is 8 MiB,
+    /// and the minimum is one byte.
+    #[must_use]
+    pub fn with_chunk_size(mut self, chunk_size: u64) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Set the number of ranges fetched concurrently. The default is 4 ranges,
+    /// and the minimum is one range.
+    #[must_use]
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Set the number of times a range is retried after a retryable error. The default is 3 retries.
+    #[must_use]
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Set the time to wait before retrying a range. The default is 500 milliseconds.
+    #[must_use]
+    pub fn with_retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    /// Call `progress` every time a range is written to the local file.
+    ///
+    /// Ranges complete out of order, but the number of bytes downloaded only grows.
+    #[must_use]
+    pub fn with_progress<F>(mut self, progress: F) -> Self
+    where
+        F: Fn(DownloadProgress) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+}
+
+impl MenmosFile {
+    /// Download the file to a local path.
+    ///
+    /// The blob is downloaded to a temporary file in the same directory as `path`, preallocated
+    /// to the size of the blob, which replaces the file at `path` once the download completes.
+    /// The blob is split into ranges, which are fetched concurrently and written in place.
+    /// Ranges failing with a retryable error are retried independently of each other,
+    /// and so is the request for the size of the blob.
+    ///
+    /// Returns the number of bytes downloaded. The offset of the handle is not used or moved.
+    ///
+    /// # Errors
+    /// If a range can't be fetched, or if the downloaded data doesn't match the size of the blob,
+    /// the temporary file is removed, the file at `path` is left untouched, and an error variant
+    /// is returned.
+    ///
+    /// # Examples
+    /// ```
+    /// use menmos::{backend::MemoryBackend, fs::DownloadOptions, FileMetadata, Menmos};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let client = Menmos::from_backend(MemoryBackend::new());
+    /// let file = client.fs.create_file(FileMetadata::new("backup.tar")).await.unwrap();
+    /// file.write_at(0, &[0; 4096]).await.unwrap();
+    ///
+    /// let local = tempfile::tempdir().unwrap();
+    /// let options = DownloadOptions::default()
+    ///     .with_concurrency(8)
+    ///     .with_progress(|p| println!("{}/{} bytes", p.downloaded, p.total));
+    /// file.download_to(local.path().join("backup.tar"), options)
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub async fn download_to<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: DownloadOptions,
+    ) -> Result<u64> {
+        let path = path.as_ref();
+        let total = self.refresh_size_with_retry(&options).await?;
+
+        // The temporary file is removed when dropped, unless it was persisted.
+        let temp_path = create_temp_file(path)
+            .await
+            .context(LocalFileSnafu { path })?;
+        let downloaded = self.download_ranges(&temp_path, total, &options).await?;
+
+        let dest = path.to_path_buf();
+        blocking(move || temp_path.persist(dest).map_err(|e| e.error))
+            .await
+            .context(LocalFileSnafu { path })?;
+
+        Ok(downloaded)
+    }
+
+    async fn download_ranges(
+        &self,
+        path: &Path,
+        total: u64,
+        options: &DownloadOptions,
+    ) -> Result<u64> {
+        let local = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(path)
+            .await
+            .context(LocalFileSnafu { path })?;
+        local
+            .set_len(total)
+            .await
+            .context(LocalFileSnafu { path })?;
+        drop(local);
+
+        let downloaded = AtomicU64::new(0);
+        let ranges = (0..total)
+            .step_by(options.chunk_size as usize)
+            .map(|start| (start, (start + options.chunk_size).min(total)));
+
+        futures::stream::iter(ranges)
+            .map(|(start, end)| {
+                let downloaded = &downloaded;
+                async move {
+                    let data = self.fetch_range_with_retry(start, end, options).await?;
+                    write_local(path, start, &data)
+                        .await
+                        .context(LocalFileSnafu { path })?;
+
+                    let downloaded = downloaded.fetch_add(data.len() as u64, Ordering::AcqRel)
+                        + data.len() as u64;
+                    if let Some(progress) = options.progress.as_ref() {
+                        progress(DownloadProgress { downloaded, total });
+                    }
+                    Ok::<_, FsError>(())
+                }
+            })
+            .buffer_unordered(options.concurrency)
+            .try_collect::<()>()
+            .await?;
+
+        let written = tokio::fs::metadata(path)
+            .await
+            .context(LocalFileSnafu { path })?
+            .len();
+        let downloaded = downloaded.into_inner();
+        ensure!(
+            downloaded == total && written == total,
+            DownloadSizeMismatchSnafu {
+                blob_id: self.blob_id.clone(),
+                expected: total,
+                actual: downloaded,
+            }
+        );
+
+        Ok(downloaded)
+    }
+
+    async fn refresh_size_with_retry(&self, options: &DownloadOptions) -> Result<u64> {
+        let mut attempt = 0;
+        loop {
+            match self.refresh_size().await {
+                Err(e) if e.is_retryable() && attempt < options.max_retries => {
+                    attempt += 1;
+                    tracing::debug!(
+                        "retrying size of file '{}' (attempt {}): {}",
+                        self.blob_id,
+                        attempt,
+                        e
+                    );
+                    tokio::time::sleep(options.retry_interval).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Fetch the bytes from `start` to `end` (exclusive), retrying on retryable errors.
+    ///
+    /// Fewer bytes are returned if the blob ends before `end`.
+    async fn fetch_range_with_retry(
+        &self,
+        start: u64,
+        end: u64,
+        options: &DownloadOptions,
+    ) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity((end - start) as usize);
+        let mut attempt = 0;
+
+        while start + (data.len() as u64) < end {
+            let offset = start + data.len() as u64;
+            match self
+                .backend
+                .read_range(&self.blob_id, (offset, end - 1))
+                .await
+            {
+                // The blob is shorter than we thought, this is caught by the size check.
+                Ok(chunk) if chunk.is_empty() => break,
+
+                // Short responses are continued from where they end.
+                Ok(chunk) => data.extend_from_slice(&chunk),
+                Err(e) if e.is_retryable() && attempt < options.max_retries => {
+                    attempt += 1;
+                    tracing::debug!(
+                        "retrying range {}-{} of file '{}' (attempt {}): {}",
+                        offset,
+                        end - 1,
+                        self.blob_id,
+                        attempt,
+                        e
+                    );
+                    tokio::time::sleep(options.retry_interval).await;
+                }
+                Err(e) => {
+                    return Err(e).context(FileReadSnafu {
+                        blob_id: self.blob_id.clone(),
+                    })
+                }
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+/// Run a blocking filesystem operation on the blocking thread pool, like `tokio::fs` does.
+async fn blocking<T, F>(f: F) -> io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|e| Err(io::Error::other(e)))
+}
+
+/// Create an empty temporary file in the directory of `path`, so it can be renamed over it.
+async fn create_temp_file(path: &Path) -> io::Result<tempfile::TempPath> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => Path::new(".").to_path_buf(),
+    };
+
+    blocking(move || {
+        let mut builder = tempfile::Builder::new();
+        builder.prefix(".menmos-download");
+
+        // Temporary files are owner-only by default, downloads get the usual permissions.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            builder.permissions(std::fs::Permissions::from_mode(0o666));
+        }
+
+        Ok(builder.tempfile_in(dir)?.into_temp_path())
+    })
+    .await
+}
+
+async fn write_local(path: &Path, offset: u64, data: &[u8]) -> std::io::Result<()> {
+    // Each range gets its own handle, so ranges can be written concurrently.
+    let mut file = tokio::fs::OpenOptions::new().write(true).open(path).await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+    file.write_all(data).await?;
+    file.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::fs::file::tests::{file_with, TestBackend};
+
+    fn content() -> Vec<u8> {
+        (0..10_000_u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn options() -> DownloadOptions {
+        DownloadOptions::default()
+            .with_chunk_size(1000)
+            .with_concurrency(3)
+            .with_retry_interval(Duration::from_millis(1))
+    }
+
+    #[tokio::test]
+    async fn downloads_ranges_concurrently() {
+        let content = content();
+        let backend = Arc::new(TestBackend {
+            max_read: usize::MAX,
+            ..Default::default()
+        });
+        let file = file_with(backend.clone(), &content).await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.bin");
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let options = {
+            let reports = reports.clone();
+            options().with_progress(move |p| reports.lock().unwrap().push(p))
+        };
+
+        assert_eq!(file.download_to(&path, options).await.unwrap(), 10_000);
+        assert_eq!(std::fs::read(&path).unwrap(), content);
+        assert_eq!(backend.reads.load(Ordering::SeqCst), 10);
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 10);
+        assert!(reports
+            .windows(2)
+            .all(|w| w[0].downloaded < w[1].downloaded));
+        assert_eq!(
+            reports.last(),
+            Some(&DownloadProgress {
+                downloaded: 10_000,
+                total: 10_000
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_ranges_are_retried() {
+        let content = content();
+        let backend = Arc::new(TestBackend {
+            max_read: 300,
+            ..Default::default()
+        });
+        let file = file_with(backend.clone(), &content).await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.bin");
+
+        backend.failing_reads.store(4, Ordering::SeqCst);
+        file.download_to(&path, options()).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), content);
+
+        // Ranges give up after the configured number of retries.
+        backend.failing_reads.store(u64::MAX, Ordering::SeqCst);
+        let err = file
+            .download_to(&path, options().with_concurrency(1).with_max_retries(2))
+            .await
+            .unwrap_err();
+        assert!(err.is_retryable());
+
+        // The previous download is left untouched, and the partial download is removed.
+        assert_eq!(std::fs::read(&path).unwrap(), content);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn empty_files_are_created() {
+        let file = file_with(Arc::new(MemoryBackend::new()), b"").await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("empty.bin");
+
+        assert_eq!(file.download_to(&path, options()).await.unwrap(), 0);
+        assert!(std::fs::read(&path).unwrap().is_empty());
+    }
+}
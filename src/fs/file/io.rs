@@ -0,0 +1,406 @@
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+
+use futures::future::BoxFuture;
+use futures::{ready, FutureExt};
+
+use snafu::prelude::*;
+
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use super::{clamp_range, read_range_cached, MenmosFile};
+use crate::fs::error::*;
+use crate::util;
+
+/// A request started by one of the async I/O traits, kept until it completes.
+pub(super) enum Operation {
+    Idle,
+    Read(BoxFuture<'static, Result<Vec<u8>>>),
+
+    /// Resolves to the size of the file, fetched when a read reaches the size we know of.
+    Refresh(BoxFuture<'static, Result<u64>>),
+    Write(BoxFuture<'static, Result<usize>>),
+
+    /// Resolves to the size of the file and the new offset.
+    Seek(BoxFuture<'static, Result<(u64, u64)>>),
+}
+
+fn offset_from(base: u64, relative: i64) -> Result<u64> {
+    let new_offset = base as i64 + relative;
+    ensure!(new_offset >= 0, NegativeOffsetSnafu);
+    Ok(new_offset as u64)
+}
+
+impl MenmosFile {
+    #[cfg(feature = "futures-io")]
+    pub(super) fn is_seeking(&mut self) -> bool {
+        matches!(self.operation.get_mut(), Operation::Seek(_))
+    }
+
+    /// Wait for the operation in flight to complete, applying its effect on the offset.
+    ///
+    /// Reads in flight are cancelled instead, since their result can't be handed to anyone.
+    pub(super) fn poll_operation(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let operation = self.operation.get_mut();
+        let result = match operation {
+            Operation::Idle => return Poll::Ready(Ok(())),
+            Operation::Read(_) | Operation::Refresh(_) => {
+                *operation = Operation::Idle;
+                return Poll::Ready(Ok(()));
+            }
+            Operation::Write(future) => ready!(future.poll_unpin(cx)).map(|written| {
+                self.offset += written as u64;
+                self.extend_known_size(self.offset);
+            }),
+            Operation::Seek(future) => ready!(future.poll_unpin(cx)).map(|(size, new_offset)| {
+                self.set_known_size(size);
+                self.offset = new_offset;
+            }),
+        };
+
+        *self.operation.get_mut() = Operation::Idle;
+        Poll::Ready(result.map_err(io::Error::from))
+    }
+
+    /// Read at most `max_len` bytes from the current offset.
+    ///
+    /// An empty buffer is returned at the end of the file.
+    pub(super) fn poll_read_bytes(
+        &mut self,
+        cx: &mut Context<'_>,
+        max_len: usize,
+    ) -> Poll<io::Result<Vec<u8>>> {
+        loop {
+            match self.operation.get_mut() {
+                Operation::Read(future) => {
+                    let result = ready!(future.poll_unpin(cx));
+                    *self.operation.get_mut() = Operation::Idle;
+
+                    // The read may have been started with a larger buffer than the one we were given now.
+                    let mut data = result?;
+                    data.truncate(max_len);
+                    self.offset += data.len() as u64;
+                    return Poll::Ready(Ok(data));
+                }
+                Operation::Refresh(future) => {
+                    let result = ready!(future.poll_unpin(cx));
+                    *self.operation.get_mut() = Operation::Idle;
+
+                    // The size is only refreshed once, so we report the end of the file if it
+                    // still hasn't moved.
+                    let size = result?;
+                    self.set_known_size(size);
+                    match clamp_range(self.offset, max_len, size) {
+                        Some(range) => self.start_read(range),
+                        None => return Poll::Ready(Ok(Vec::new())),
+                    }
+                }
+                Operation::Write(_) | Operation::Seek(_) => ready!(self.poll_operation(cx))?,
+                Operation::Idle => match clamp_range(self.offset, max_len, self.known_size()) {
+                    Some(range) => self.start_read(range),
+                    None if max_len == 0 => return Poll::Ready(Ok(Vec::new())),
+                    None => {
+                        // The file might have been extended elsewhere since we last saw its size.
+                        let backend = self.backend.clone();
+                        let blob_id = self.blob_id.clone();
+                        *self.operation.get_mut() = Operation::Refresh(
+                            async move {
+                                util::get_meta(&backend, &blob_id)
+                                    .await
+                                    .map(|meta| meta.size)
+                                    .context(SeekMetaSnafu)
+                            }
+                            .boxed(),
+                        );
+                    }
+                },
+            }
+        }
+    }
+
+    fn start_read(&mut self, range: (u64, u64)) {
+        let backend = self.backend.clone();
+        let cache = self.cache.clone();
+        let blob_id = self.blob_id.clone();
+        let size = self.known_size();
+        *self.operation.get_mut() =
+            Operation::Read(
+                async move {
+                    read_range_cached(&backend, cache.as_deref(), &blob_id, range, size).await
+                }
+                .boxed(),
+            );
+    }
+
+    pub(super) fn poll_write_bytes(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            match self.operation.get_mut() {
+                Operation::Write(future) => {
+                    let result = ready!(future.poll_unpin(cx));
+                    *self.operation.get_mut() = Operation::Idle;
+
+                    let written = result?;
+                    self.offset += written as u64;
+                    self.extend_known_size(self.offset);
+                    return Poll::Ready(Ok(written));
+                }
+                Operation::Read(_) | Operation::Refresh(_) | Operation::Seek(_) => {
+                    ready!(self.poll_operation(cx))?
+                }
+                Operation::Idle => {
+                    if buf.is_empty() {
+                        return Poll::Ready(Ok(0));
+                    }
+
+                    let backend = self.backend.clone();
+                    let cache = self.cache.clone();
+                    let blob_id = self.blob_id.clone();
+                    let offset = self.offset;
+                    let buf = Bytes::copy_from_slice(buf);
+                    *self.operation.get_mut() = Operation::Write(
+                        async move {
+                            let written = buf.len();
+                            let result = backend.write(&blob_id, offset, buf).await;
+                            if let Some(cache) = cache {
+                                cache.invalidate(&blob_id, offset, written as u64);
+                            }
+                            result.context(FileWriteSnafu)?;
+                            Ok(written)
+                        }
+                        .boxed(),
+                    );
+                }
+            }
+        }
+    }
+
+    pub(super) fn start_seek_inner(&mut self, position: SeekFrom) -> io::Result<()> {
+        if !matches!(self.operation.get_mut(), Operation::Idle) {
+            return Err(io::Error::other(
+                "another operation is pending on this file",
+            ));
+        }
+
+        match position {
+            SeekFrom::Start(offset) => self.offset = offset,
+            SeekFrom::Current(relative) => self.offset = offset_from(self.offset, relative)?,
+            SeekFrom::End(relative) => {
+                let backend = self.backend.clone();
+                let blob_id = self.blob_id.clone();
+                *self.operation.get_mut() = Operation::Seek(
+                    async move {
+                        let metadata = util::get_meta(&backend, &blob_id)
+                            .await
+                            .context(SeekMetaSnafu)?;
+                        Ok((metadata.size, offset_from(metadata.size, relative)?))
+                    }
+                    .boxed(),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn poll_seek_complete(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        ready!(self.poll_operation(cx))?;
+        Poll::Ready(Ok(self.offset))
+    }
+}
+
+impl AsyncRead for MenmosFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let data = ready!(self.get_mut().poll_read_bytes(cx, buf.remaining()))?;
+        buf.put_slice(&data);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for MenmosFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_write_bytes(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Writes are sent as soon as they are issued, so we only need to wait for the one in flight.
+        self.get_mut().poll_operation(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_operation(cx)
+    }
+}
+
+impl AsyncSeek for MenmosFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        self.get_mut().start_seek_inner(position)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        self.get_mut().poll_seek_complete(cx)
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures::io::AsyncRead for MenmosFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let data = ready!(self.get_mut().poll_read_bytes(cx, buf.len()))?;
+        buf[..data.len()].copy_from_slice(&data);
+        Poll::Ready(Ok(data.len()))
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures::io::AsyncWrite for MenmosFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_write_bytes(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_operation(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_operation(cx)
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures::io::AsyncSeek for MenmosFile {
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        position: SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+
+        // We're called again with the same position until the seek completes.
+        if !this.is_seeking() {
+            ready!(this.poll_operation(cx))?;
+            this.start_seek_inner(position)?;
+        }
+
+        this.poll_seek_complete(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::FileMetadata;
+
+    async fn new_file() -> MenmosFile {
+        MenmosFile::create(Arc::new(MemoryBackend::new()), FileMetadata::new("a.txt"))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn tokio_reads_see_writes_from_other_handles() {
+        let writer = new_file().await;
+        let mut reader = writer.clone();
+
+        let mut buf = [0_u8; 8];
+        assert_eq!(AsyncReadExt::read(&mut reader, &mut buf).await.unwrap(), 0);
+
+        writer.write_at(0, b"abc").await.unwrap();
+        assert_eq!(AsyncReadExt::read(&mut reader, &mut buf).await.unwrap(), 3);
+        assert_eq!(&buf[..3], b"abc");
+        assert_eq!(AsyncReadExt::read(&mut reader, &mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn tokio_copy_roundtrip() {
+        let mut file = new_file().await;
+
+        let content = b"hello world".repeat(100);
+        let copied = tokio::io::copy(&mut content.as_slice(), &mut file)
+            .await
+            .unwrap();
+        assert_eq!(copied, content.len() as u64);
+        file.flush().await.unwrap();
+
+        AsyncSeekExt::seek(&mut file, SeekFrom::Start(0))
+            .await
+            .unwrap();
+        let mut buf = Vec::new();
+        AsyncReadExt::read_to_end(&mut file, &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(buf, content);
+    }
+
+    #[tokio::test]
+    async fn tokio_seek_and_partial_reads() {
+        let mut file = new_file().await;
+        AsyncWriteExt::write_all(&mut file, b"0123456789")
+            .await
+            .unwrap();
+
+        let position = AsyncSeekExt::seek(&mut file, SeekFrom::End(-4))
+            .await
+            .unwrap();
+        assert_eq!(position, 6);
+
+        // Only 4 bytes are left, so the read comes back short.
+        let mut buf = [0_u8; 8];
+        let read = AsyncReadExt::read(&mut file, &mut buf).await.unwrap();
+        assert_eq!(&buf[..read], b"6789");
+
+        // We're at the end of the file.
+        assert_eq!(AsyncReadExt::read(&mut file, &mut buf).await.unwrap(), 0);
+
+        let err = AsyncSeekExt::seek(&mut file, SeekFrom::Current(-20))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[cfg(feature = "futures-io")]
+    #[tokio::test]
+    async fn futures_io_roundtrip() {
+        use futures::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+        let mut file = new_file().await;
+        AsyncWriteExt::write_all(&mut file, b"hello futures")
+            .await
+            .unwrap();
+        AsyncSeekExt::seek(&mut file, SeekFrom::Start(6))
+            .await
+            .unwrap();
+
+        let mut buf = String::new();
+        AsyncReadExt::read_to_string(&mut file, &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(buf, "futures");
+    }
+}
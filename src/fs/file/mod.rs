@@ -0,0 +1,905 @@
+mod download;
+mod io;
+mod reader;
+mod stream;
+mod writer;
+
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytes::Bytes;
+
+use futures::{StreamExt, TryStreamExt};
+
+use interface::BlobMeta;
+use menmos_client::{Meta, Type};
+
+use snafu::prelude::*;
+
+use sync_wrapper::SyncWrapper;
+
+use crate::util;
+use crate::{BackendRC, FileMetadata};
+
+use super::cache::{BlockCache, BlockCacheRC};
+use super::error::*;
+
+use io::Operation;
+
+pub use download::{DownloadOptions, DownloadProgress};
+pub use reader::PrefetchReader;
+pub use writer::BufferedWriter;
+
+/// The maximum number of requests sent concurrently by [`MenmosFile::read_ranges`].
+const MAX_CONCURRENT_RANGE_READS: usize = 8;
+
+/// Ranges separated by at most this many bytes are fetched in a single request
+/// by [`MenmosFile::read_ranges`].
+const RANGE_COALESCE_GAP: u64 = 4096;
+
+/// Get the end-inclusive range to request when reading at most `len` bytes at `offset`
+/// from a blob of `size` bytes.
+///
+/// Returns `None` when there is nothing to read.
+fn clamp_range(offset: u64, len: usize, size: u64) -> Option<(u64, u64)> {
+    if len == 0 || offset >= size {
+        None
+    } else {
+        Some((offset, offset.saturating_add(len as u64).min(size) - 1))
+    }
+}
+
+/// Read an end-inclusive range of a blob of `size` bytes, through the block cache if there is one.
+async fn read_range_cached(
+    backend: &BackendRC,
+    cache: Option<&BlockCache>,
+    blob_id: &str,
+    range: (u64, u64),
+    size: u64,
+) -> Result<Vec<u8>> {
+    let data = match cache {
+        Some(cache) => cache.read(backend, blob_id, range, size).await,
+        None => backend.read_range(blob_id, range).await,
+    };
+    data.context(FileReadSnafu { blob_id })
+}
+
+pub(super) fn make_file_meta(m: FileMetadata) -> Meta {
+    Meta {
+        name: m.name,
+        blob_type: Type::File,
+        metadata: m.metadata,
+        tags: m.tags,
+        parents: m.parents,
+        size: m.size,
+    }
+}
+
+/// A handle to a file in a menmos cluster.
+///
+/// Besides its inherent methods, a file implements the tokio [`AsyncRead`](tokio::io::AsyncRead),
+/// [`AsyncWrite`](tokio::io::AsyncWrite) and [`AsyncSeek`](tokio::io::AsyncSeek) traits, so it can be
+/// used with the rest of the async ecosystem. The `futures::io` equivalents are implemented when the
+/// `futures-io` feature is enabled.
+pub struct MenmosFile {
+    blob_id: String,
+    backend: BackendRC,
+    offset: u64,
+
+    /// The size of the file, as last seen by this handle.
+    ///
+    /// Reads are clamped to this size. It is updated by our own writes, and refreshed
+    /// when seeking from the end, reading to the end of the file, or reading past this size.
+    size: AtomicU64,
+
+    cache: Option<BlockCacheRC>,
+
+    /// The metadata of the file, as of when the handle was opened or last refreshed.
+    ///
+    /// The size is tracked separately, in `size`.
+    meta: FileMetadata,
+
+    /// The request in flight for the async I/O traits.
+    operation: SyncWrapper<Operation>,
+}
+
+impl Clone for MenmosFile {
+    // The clone starts at the same offset, but doesn't share operations in flight.
+    fn clone(&self) -> Self {
+        Self {
+            blob_id: self.blob_id.clone(),
+            backend: self.backend.clone(),
+            offset: self.offset,
+            size: AtomicU64::new(self.known_size()),
+            cache: self.cache.clone(),
+            meta: self.meta.clone(),
+            operation: SyncWrapper::new(Operation::Idle),
+        }
+    }
+}
+
+impl MenmosFile {
+    #[doc(hidden)]
+    pub async fn create(backend: BackendRC, metadata: FileMetadata) -> Result<Self> {
+        let size = metadata.size;
+
+        let blob_id = backend
+            .create_empty(make_file_meta(metadata.clone()))
+            .await
+            .context(FileCreateSnafu)?;
+
+        Ok(Self {
+            blob_id,
+            backend,
+            offset: 0,
+            size: AtomicU64::new(size),
+            cache: None,
+            meta: metadata,
+            operation: SyncWrapper::new(Operation::Idle),
+        })
+    }
+
+    #[doc(hidden)]
+    pub async fn open(backend: BackendRC, id: &str) -> Result<Self> {
+        let metadata = util::get_meta(&backend, id).await.context(FileOpenSnafu {
+            blob_id: String::from(id),
+        })?;
+        Self::open_raw(backend, id, metadata)
+    }
+
+    pub(crate) fn open_raw(backend: BackendRC, id: &str, meta: BlobMeta) -> Result<Self> {
+        ensure!(
+            meta.blob_type == Type::File,
+            ExpectedFileSnafu {
+                blob_id: String::from(id)
+            }
+        );
+
+        Ok(Self {
+            blob_id: String::from(id),
+            backend,
+            offset: 0,
+            size: AtomicU64::new(meta.size),
+            cache: None,
+            meta: FileMetadata::from(meta),
+            operation: SyncWrapper::new(Operation::Idle),
+        })
+    }
+
+    /// Read through the provided block cache.
+    pub(crate) fn with_block_cache(mut self, cache: Option<BlockCacheRC>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Returns the ID of this file.
+    pub fn id(&self) -> &str {
+        &self.blob_id
+    }
+
+    /// Returns the name of this file.
+    pub fn name(&self) -> &str {
+        &self.meta.name
+    }
+
+    /// Returns the size of this file, in bytes.
+    ///
+    /// This is the size as last seen by this handle, which includes the writes made through it.
+    pub fn size(&self) -> u64 {
+        self.known_size()
+    }
+
+    /// Returns the tags of this file.
+    pub fn tags(&self) -> &[String] {
+        &self.meta.tags
+    }
+
+    /// Returns the key/value pairs of this file.
+    pub fn metadata(&self) -> &HashMap<String, String> {
+        &self.meta.metadata
+    }
+
+    /// Returns the IDs of the parents of this file.
+    pub fn parents(&self) -> &[String] {
+        &self.meta.parents
+    }
+
+    /// Fetch the metadata of this file again.
+    ///
+    /// The metadata returned by [`MenmosFile::name`], [`MenmosFile::tags`] and the other
+    /// accessors is a snapshot taken when the handle was opened, which is not updated
+    /// when the file is changed elsewhere.
+    pub async fn refresh(&mut self) -> Result<()> {
+        let meta =
+            util::get_meta(&self.backend, &self.blob_id)
+                .await
+                .context(MetadataReadSnafu {
+                    blob_id: self.blob_id.clone(),
+                })?;
+        self.set_known_size(meta.size);
+        self.meta = FileMetadata::from(meta);
+        Ok(())
+    }
+
+    fn known_size(&self) -> u64 {
+        self.size.load(Ordering::Acquire)
+    }
+
+    fn set_known_size(&self, size: u64) {
+        self.size.store(size, Ordering::Release);
+    }
+
+    /// Record that the file extends at least up to `end`.
+    fn extend_known_size(&self, end: u64) {
+        self.size.fetch_max(end, Ordering::AcqRel);
+    }
+
+    /// Get the size to clamp a read at `offset` to.
+    ///
+    /// When the read starts past the size we know of, the size is refreshed once,
+    /// since the file might have been extended elsewhere.
+    async fn size_for_read(&self, offset: u64) -> Result<u64> {
+        let size = self.known_size();
+        if offset < size {
+            Ok(size)
+        } else {
+            self.refresh_size().await
+        }
+    }
+
+    /// Read the bytes from `start` to `end` (exclusive), continuing after short responses.
+    ///
+    /// # Errors
+    /// If the file ends before `end`, an error variant is returned.
+    async fn read_range_exact(&self, start: u64, end: u64) -> Result<Vec<u8>> {
+        let len = (end - start) as usize;
+        let mut data = Vec::with_capacity(len);
+
+        while data.len() < len {
+            let offset = start + data.len() as u64;
+            let chunk = self
+                .backend
+                .read_range(&self.blob_id, (offset, end - 1))
+                .await
+                .context(FileReadSnafu {
+                    blob_id: self.blob_id.clone(),
+                })?;
+            ensure!(
+                !chunk.is_empty(),
+                UnexpectedEofSnafu {
+                    blob_id: self.blob_id.clone()
+                }
+            );
+            data.extend_from_slice(&chunk);
+        }
+
+        data.truncate(len);
+        Ok(data)
+    }
+
+    async fn refresh_size(&self) -> Result<u64> {
+        let metadata = util::get_meta(&self.backend, &self.blob_id)
+            .await
+            .context(SeekMetaSnafu)?;
+        self.set_known_size(metadata.size);
+        Ok(metadata.size)
+    }
+
+    /// Write the contents of the provided buffer to the file, at the current offset.
+    ///
+    /// Returns the number of bytes written. If no errors occured,
+    /// the value returned will always be the length of the provided buffer.
+    pub async fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let written = self.write_at(self.offset, buf).await?;
+        self.offset += written as u64;
+        Ok(written)
+    }
+
+    /// Seek to a new position in the file.
+    ///
+    /// Seeking past the end of the file will not return an error,
+    /// reads from such an offset will simply return no bytes.
+    ///
+    /// # Errors
+    /// Seeking to a negative offset will return an error variant.
+    pub async fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        match pos {
+            SeekFrom::Current(offset) => {
+                let new_offset = self.offset as i64 + offset;
+                ensure!(new_offset >= 0, NegativeOffsetSnafu);
+                self.offset = new_offset as u64;
+            }
+            SeekFrom::Start(new_offset) => {
+                self.offset = new_offset;
+            }
+            SeekFrom::End(relative) => {
+                let end_offset = self.refresh_size().await? as i64;
+                let new_offset = end_offset + relative;
+                ensure!(new_offset >= 0, NegativeOffsetSnafu);
+                self.offset = new_offset as u64;
+            }
+        }
+        Ok(self.offset)
+    }
+
+    /// Read a number of bytes from the file.
+    ///
+    /// Returns the number of bytes read `0 <= n <= buf.len()`.
+    ///
+    /// If the number of bytes read is 0, either the buffer is empty or the current offset
+    /// is at or past the end of the file.
+    ///
+    /// If the number of bytes read is inferior to `buf.len()`, no more bytes
+    /// could be read at this moment.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = self.read_at(self.offset, buf).await?;
+        self.offset += read as u64;
+        Ok(read)
+    }
+
+    /// Read bytes from the current offset to the end of the file, appending them to `buf`.
+    ///
+    /// Returns the number of bytes read.
+    ///
+    /// # Errors
+    /// If the file ends before the size reported by the cluster, an error variant is returned
+    /// and `buf` is left unchanged.
+    pub async fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+        let size = self.refresh_size().await?;
+        if self.offset >= size {
+            return Ok(0);
+        }
+
+        let out = self.read_range_exact(self.offset, size).await?;
+        buf.extend_from_slice(&out);
+        self.offset += out.len() as u64;
+        Ok(out.len())
+    }
+
+    /// Read bytes from the current offset to the end of the file and decode those bytes
+    /// as a UTF-8 string, appending it to `string`.
+    ///
+    /// Returns the number of bytes read. If the bytes are not valid UTF-8, an error
+    /// is returned and `string` is left unchanged.
+    pub async fn read_to_string(&mut self, string: &mut String) -> Result<usize> {
+        let mut v = Vec::new();
+        let buf_read = self.read_to_end(&mut v).await?;
+
+        string.push_str(&String::from_utf8(v).context(BufferEncodingSnafu)?);
+
+        Ok(buf_read)
+    }
+
+    /// Read a number of bytes from the file, starting at `offset`.
+    ///
+    /// This works like [`MenmosFile::read`], but doesn't use or move the current offset
+    /// of the handle. Positional reads and writes can run concurrently on the same handle.
+    pub async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let size = self.size_for_read(offset).await?;
+        let range = match clamp_range(offset, buf.len(), size) {
+            Some(range) => range,
+            None => return Ok(0),
+        };
+
+        let r = read_range_cached(
+            &self.backend,
+            self.cache.as_deref(),
+            &self.blob_id,
+            range,
+            size,
+        )
+        .await?;
+
+        // The cluster can return fewer bytes than requested, e.g. if the file was truncated.
+        let read = r.len().min(buf.len());
+        buf[..read].copy_from_slice(&r[..read]);
+        Ok(read)
+    }
+
+    /// Read exactly `buf.len()` bytes from the file, starting at `offset`.
+    ///
+    /// # Errors
+    /// If the file ends before the buffer is filled, an error variant is returned
+    /// and the contents of the buffer are unspecified.
+    pub async fn read_exact_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let mut filled = 0;
+
+        while filled < buf.len() {
+            // `read_at` refreshes the size before reporting the end of the file.
+            let read = self
+                .read_at(offset + filled as u64, &mut buf[filled..])
+                .await?;
+            ensure!(
+                read > 0,
+                UnexpectedEofSnafu {
+                    blob_id: self.blob_id.clone()
+                }
+            );
+            filled += read;
+        }
+
+        Ok(())
+    }
+
+    /// Write the contents of the provided buffer to the file, starting at `offset`.
+    ///
+    /// This works like [`MenmosFile::write`], but doesn't use or move the current offset
+    /// of the handle. Positional reads and writes can run concurrently on the same handle.
+    pub async fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let buf = Bytes::copy_from_slice(buf);
+        let buf_len = buf.len();
+        let result = self.backend.write(&self.blob_id, offset, buf).await;
+
+        // A failed write may still have modified the blob.
+        if let Some(cache) = self.cache.as_ref() {
+            cache.invalidate(&self.blob_id, offset, buf_len as u64);
+        }
+
+        result.context(FileWriteSnafu)?;
+        self.extend_known_size(offset + buf_len as u64);
+        Ok(buf_len)
+    }
+
+    /// Read several ranges of the file at once.
+    ///
+    /// Returns the contents of each range, in the order the ranges were given. Like with
+    /// [`MenmosFile::read_at`], ranges going past the end of the file are truncated.
+    ///
+    /// # Errors
+    /// If the file ends before the size last seen by this handle, e.g. because it was truncated
+    /// elsewhere, an error variant is returned.
+    ///
+    /// Ranges that overlap or are close to each other are fetched in a single request,
+    /// and requests are sent concurrently. This makes it cheap to read the scattered
+    /// blocks needed by index-driven formats, like a zip central directory.
+    pub async fn read_ranges(&self, ranges: &[Range<u64>]) -> Result<Vec<Bytes>> {
+        // Like reads, ranges going past the size we know of refresh it once.
+        let mut size = self.known_size();
+        if ranges.iter().any(|r| r.start < r.end && r.end > size) {
+            size = self.refresh_size().await?;
+        }
+
+        // Merge the ranges into sorted, non-overlapping spans to fetch.
+        let mut order: Vec<usize> = (0..ranges.len())
+            .filter(|i| ranges[*i].start < ranges[*i].end.min(size))
+            .collect();
+        order.sort_by_key(|i| ranges[*i].start);
+
+        let mut spans: Vec<Range<u64>> = Vec::new();
+        for i in order.iter() {
+            let range = ranges[*i].start..ranges[*i].end.min(size);
+            match spans.last_mut() {
+                Some(span) if range.start <= span.end + RANGE_COALESCE_GAP => {
+                    span.end = span.end.max(range.end);
+                }
+                _ => spans.push(range),
+            }
+        }
+
+        let fetched: Vec<(Range<u64>, Bytes)> = futures::stream::iter(spans)
+            .map(|span| async move {
+                let data = self.read_range_exact(span.start, span.end).await?;
+                Ok::<_, FsError>((span, Bytes::from(data)))
+            })
+            .buffered(MAX_CONCURRENT_RANGE_READS)
+            .try_collect()
+            .await?;
+
+        let results = ranges
+            .iter()
+            .map(|range| {
+                let span = fetched
+                    .iter()
+                    .find(|(span, _)| span.start <= range.start && range.start < span.end);
+
+                match span {
+                    Some((span, data)) if range.start < range.end => {
+                        let start = (range.start - span.start) as usize;
+                        let end = (range.end.min(span.end) - span.start) as usize;
+                        data.slice(start..end)
+                    }
+                    _ => Bytes::new(),
+                }
+            })
+            .collect();
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+
+    use menmos_client::{Query, QueryResponse};
+
+    use super::*;
+    use crate::backend::{self, Backend, MemoryBackend};
+    use crate::{ErrorKind, RequestError};
+
+    /// A backend counting reads and returning at most `max_read` bytes per read,
+    /// like a cluster sending short responses.
+    ///
+    /// The next `failing_reads` reads fail with a server error.
+    #[derive(Default)]
+    pub(in crate::fs::file) struct TestBackend {
+        pub reads: AtomicU64,
+        pub inner: MemoryBackend,
+        pub max_read: usize,
+        pub failing_reads: AtomicU64,
+    }
+
+    #[async_trait]
+    impl Backend for TestBackend {
+        async fn push(&self, path: &Path, meta: Meta) -> backend::Result<String> {
+            self.inner.push(path, meta).await
+        }
+
+        async fn create_empty(&self, meta: Meta) -> backend::Result<String> {
+            self.inner.create_empty(meta).await
+        }
+
+        async fn write(&self, blob_id: &str, offset: u64, buffer: Bytes) -> backend::Result<()> {
+            self.inner.write(blob_id, offset, buffer).await
+        }
+
+        async fn read_range(&self, blob_id: &str, range: (u64, u64)) -> backend::Result<Vec<u8>> {
+            let failing =
+                self.failing_reads
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+            if failing.is_ok() {
+                return Err(RequestError::new(ErrorKind::Server, "injected failure"));
+            }
+
+            let mut data = self.inner.read_range(blob_id, range).await?;
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            data.truncate(self.max_read);
+            Ok(data)
+        }
+
+        async fn get_meta(&self, blob_id: &str) -> backend::Result<Option<BlobMeta>> {
+            self.inner.get_meta(blob_id).await
+        }
+
+        async fn query(&self, query: Query) -> backend::Result<QueryResponse> {
+            self.inner.query(query).await
+        }
+
+        async fn delete(&self, blob_id: &str) -> backend::Result<()> {
+            self.inner.delete(blob_id).await
+        }
+
+        async fn update_meta(&self, blob_id: &str, meta: Meta) -> backend::Result<()> {
+            self.inner.update_meta(blob_id, meta).await
+        }
+    }
+
+    pub(in crate::fs::file) async fn file_with(backend: BackendRC, content: &[u8]) -> MenmosFile {
+        let mut file = MenmosFile::create(backend, FileMetadata::new("test.txt"))
+            .await
+            .unwrap();
+        file.write(content).await.unwrap();
+        file
+    }
+
+    #[test]
+    fn clamp_range_to_size() {
+        assert_eq!(clamp_range(0, 0, 10), None);
+        assert_eq!(clamp_range(10, 5, 10), None);
+        assert_eq!(clamp_range(20, 5, 10), None);
+        assert_eq!(clamp_range(0, 5, 10), Some((0, 4)));
+        assert_eq!(clamp_range(8, 5, 10), Some((8, 9)));
+        assert_eq!(clamp_range(3, usize::MAX, 10), Some((3, 9)));
+    }
+
+    #[tokio::test]
+    async fn read_matrix() {
+        // (file content, start offset, buffer length, expected bytes)
+        let cases: [(&[u8], u64, usize, &[u8]); 8] = [
+            (b"0123456789", 0, 0, b""),
+            (b"0123456789", 0, 4, b"0123"),
+            (b"0123456789", 0, 10, b"0123456789"),
+            (b"0123456789", 0, 32, b"0123456789"),
+            (b"0123456789", 7, 8, b"789"),
+            (b"0123456789", 10, 8, b""),
+            (b"0123456789", 25, 8, b""),
+            (b"", 0, 8, b""),
+        ];
+
+        for (content, offset, len, expected) in cases {
+            let mut file = file_with(Arc::new(MemoryBackend::new()), content).await;
+            file.seek(SeekFrom::Start(offset)).await.unwrap();
+
+            let mut buf = vec![0xff_u8; len];
+            let read = file.read(&mut buf).await.unwrap();
+
+            assert_eq!(&buf[..read], expected, "offset {}, len {}", offset, len);
+            assert!(buf[read..].iter().all(|b| *b == 0xff));
+            assert_eq!(file.offset, offset + read as u64);
+        }
+    }
+
+    #[tokio::test]
+    async fn short_responses_fill_buffers_partially() {
+        let backend = Arc::new(TestBackend {
+            max_read: 3,
+            ..Default::default()
+        });
+        let mut file = file_with(backend, b"0123456789").await;
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+
+        let mut buf = [0_u8; 8];
+        assert_eq!(file.read(&mut buf).await.unwrap(), 3);
+        assert_eq!(&buf[..3], b"012");
+        assert_eq!(file.read(&mut buf).await.unwrap(), 3);
+        assert_eq!(&buf[..3], b"345");
+    }
+
+    #[tokio::test]
+    async fn read_to_end_appends() {
+        let mut file = file_with(Arc::new(MemoryBackend::new()), b"Hello world!").await;
+        file.seek(SeekFrom::Start(6)).await.unwrap();
+
+        let mut buf = b"prefix ".to_vec();
+        assert_eq!(file.read_to_end(&mut buf).await.unwrap(), 6);
+        assert_eq!(buf, b"prefix world!");
+
+        // We're at the end of the file now.
+        assert_eq!(file.read_to_end(&mut buf).await.unwrap(), 0);
+        assert_eq!(buf, b"prefix world!");
+    }
+
+    #[tokio::test]
+    async fn read_to_string_appends() {
+        let mut file = file_with(Arc::new(MemoryBackend::new()), b"world").await;
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+
+        let mut string = String::from("hello ");
+        assert_eq!(file.read_to_string(&mut string).await.unwrap(), 5);
+        assert_eq!(string, "hello world");
+
+        let mut invalid = file_with(Arc::new(MemoryBackend::new()), &[0xc3, 0x28]).await;
+        invalid.seek(SeekFrom::Start(0)).await.unwrap();
+        assert!(invalid.read_to_string(&mut string).await.is_err());
+        assert_eq!(string, "hello world");
+    }
+
+    #[tokio::test]
+    async fn read_to_end_sees_writes_from_other_handles() {
+        let backend: BackendRC = Arc::new(MemoryBackend::new());
+        let mut writer = file_with(backend.clone(), b"abc").await;
+        let mut reader = MenmosFile::open(backend, writer.id()).await.unwrap();
+
+        writer.write(b"def").await.unwrap();
+
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"abcdef");
+    }
+
+    #[tokio::test]
+    async fn read_to_end_continues_after_short_responses() {
+        let content: Vec<u8> = (0..1000_u32).map(|i| (i % 251) as u8).collect();
+        let backend = Arc::new(TestBackend {
+            max_read: 300,
+            ..Default::default()
+        });
+        let mut file = file_with(backend.clone(), &content).await;
+        file.seek(SeekFrom::Start(100)).await.unwrap();
+
+        let mut buf = Vec::new();
+        assert_eq!(file.read_to_end(&mut buf).await.unwrap(), 900);
+        assert_eq!(buf, &content[100..]);
+        assert_eq!(backend.reads.load(Ordering::SeqCst), 3);
+
+        // A cluster sending no bytes before the end of the file is an error.
+        let backend = Arc::new(TestBackend::default());
+        let mut file = file_with(backend, &content).await;
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        let err = file.read_to_end(&mut buf).await.unwrap_err();
+        assert!(matches!(err, FsError::UnexpectedEofError { .. }));
+        assert_eq!(buf.len(), 900);
+        assert_eq!(file.offset, 0);
+    }
+
+    #[tokio::test]
+    async fn reads_see_writes_from_other_handles() {
+        let backend: BackendRC = Arc::new(MemoryBackend::new());
+        let writer = file_with(backend.clone(), b"abc").await;
+        let mut reader = MenmosFile::open(backend, writer.id()).await.unwrap();
+
+        let mut buf = [0_u8; 8];
+        reader.seek(SeekFrom::Start(3)).await.unwrap();
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 0);
+
+        writer.write_at(3, b"def").await.unwrap();
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 3);
+        assert_eq!(&buf[..3], b"def");
+
+        writer.write_at(6, b"gh").await.unwrap();
+        let ranges = reader.read_ranges(&[4..8, 8..8]).await.unwrap();
+        assert_eq!(&ranges[0][..], b"efgh");
+    }
+
+    #[tokio::test]
+    async fn positional_io_keeps_the_offset() {
+        let file = file_with(Arc::new(MemoryBackend::new()), b"0123456789").await;
+        assert_eq!(file.offset, 10);
+
+        file.write_at(2, b"ab").await.unwrap();
+
+        let mut first = [0_u8; 4];
+        let mut second = [0_u8; 4];
+        let (a, b) = futures::join!(file.read_at(0, &mut first), file.read_at(8, &mut second));
+        assert_eq!(&first[..a.unwrap()], b"01ab");
+        assert_eq!(&second[..b.unwrap()], b"89");
+        assert_eq!(file.offset, 10);
+
+        // Writing past the end extends the file.
+        file.write_at(12, b"xy").await.unwrap();
+        let mut buf = [0_u8; 4];
+        assert_eq!(file.read_at(11, &mut buf).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn read_exact_at() {
+        let backend = Arc::new(TestBackend {
+            max_read: 3,
+            ..Default::default()
+        });
+        let file = file_with(backend.clone(), b"0123456789").await;
+
+        let mut buf = [0_u8; 8];
+        file.read_exact_at(1, &mut buf).await.unwrap();
+        assert_eq!(&buf, b"12345678");
+
+        let err = file.read_exact_at(4, &mut buf).await.unwrap_err();
+        assert!(matches!(err, FsError::UnexpectedEofError { .. }));
+
+        // Data written through another handle is picked up.
+        let reader = MenmosFile::open(backend, file.id()).await.unwrap();
+        file.write_at(10, b"abcd").await.unwrap();
+        reader.read_exact_at(6, &mut buf).await.unwrap();
+        assert_eq!(&buf, b"6789abcd");
+    }
+
+    #[tokio::test]
+    async fn read_ranges_coalesces_requests() {
+        let backend = Arc::new(TestBackend {
+            max_read: usize::MAX,
+            ..Default::default()
+        });
+
+        let content: Vec<u8> = (0..100_000_u32).map(|i| (i % 251) as u8).collect();
+        let file = file_with(backend.clone(), &content).await;
+
+        let ranges = [
+            90_000..90_010,
+            10..20,
+            0..15,
+            30..40,
+            99_990..100_050,
+            200_000..200_010,
+            50..50,
+        ];
+        let results = file.read_ranges(&ranges).await.unwrap();
+
+        assert_eq!(results.len(), ranges.len());
+        assert_eq!(&results[0][..], &content[90_000..90_010]);
+        assert_eq!(&results[1][..], &content[10..20]);
+        assert_eq!(&results[2][..], &content[0..15]);
+        assert_eq!(&results[3][..], &content[30..40]);
+        assert_eq!(&results[4][..], &content[99_990..]);
+        assert!(results[5].is_empty());
+        assert!(results[6].is_empty());
+
+        // The ranges at the start of the file are close enough to be fetched together.
+        assert_eq!(backend.reads.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn read_ranges_continues_after_short_responses() {
+        let content: Vec<u8> = (0..10_000_u32).map(|i| (i % 251) as u8).collect();
+        let backend = Arc::new(TestBackend {
+            max_read: 100,
+            ..Default::default()
+        });
+        let file = file_with(backend.clone(), &content).await;
+
+        let results = file.read_ranges(&[0..250, 6000..6400]).await.unwrap();
+        assert_eq!(&results[0][..], &content[0..250]);
+        assert_eq!(&results[1][..], &content[6000..6400]);
+        assert_eq!(backend.reads.load(Ordering::SeqCst), 7);
+
+        let backend = Arc::new(TestBackend::default());
+        let file = file_with(backend, &content).await;
+        let err = file.read_ranges(&[0..10, 20..30]).await.unwrap_err();
+        assert!(matches!(err, FsError::UnexpectedEofError { .. }));
+    }
+
+    #[tokio::test]
+    async fn block_cache_serves_repeated_reads() {
+        let backend = Arc::new(TestBackend {
+            max_read: usize::MAX,
+            ..Default::default()
+        });
+        let content: Vec<u8> = (0..10_000_u32).map(|i| (i % 251) as u8).collect();
+        let cache = Arc::new(BlockCache::new(1024, 64 * 1024));
+        let file = file_with(backend.clone(), &content)
+            .await
+            .with_block_cache(Some(cache.clone()));
+
+        let mut buf = [0_u8; 100];
+        for offset in [1000, 1100, 1024, 2000, 9_950] {
+            let read = file.read_at(offset, &mut buf).await.unwrap();
+            assert_eq!(&buf[..read], &content[offset as usize..][..read]);
+        }
+        assert_eq!(backend.reads.load(Ordering::SeqCst), 3);
+        assert_eq!(cache.stats().hits, 3);
+        assert_eq!(cache.stats().misses, 4);
+
+        // Other handles to the blob share the cache.
+        let other = MenmosFile::open(backend.clone(), file.id())
+            .await
+            .unwrap()
+            .with_block_cache(Some(cache.clone()));
+        other.read_at(1500, &mut buf).await.unwrap();
+        assert_eq!(backend.reads.load(Ordering::SeqCst), 3);
+
+        // Writes invalidate the blocks they touch.
+        file.write_at(1020, b"abcdefgh").await.unwrap();
+        other.read_at(1020, &mut buf[..8]).await.unwrap();
+        assert_eq!(&buf[..8], b"abcdefgh");
+        assert_eq!(backend.reads.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn metadata_snapshot_and_refresh() {
+        let backend: BackendRC = Arc::new(MemoryBackend::new());
+        let file = MenmosFile::create(
+            backend.clone(),
+            FileMetadata::new("a.txt")
+                .with_tag("draft")
+                .with_meta("owner", "bob")
+                .with_parent("dir"),
+        )
+        .await
+        .unwrap();
+        file.write_at(0, b"hello").await.unwrap();
+
+        let mut other = MenmosFile::open(backend.clone(), file.id()).await.unwrap();
+        assert_eq!(other.name(), "a.txt");
+        assert_eq!(other.size(), 5);
+        assert_eq!(other.tags(), ["draft"]);
+        assert_eq!(other.metadata()["owner"], "bob");
+        assert_eq!(other.parents(), ["dir"]);
+
+        // Handles keep their snapshot until they are refreshed.
+        file.write_at(5, b" world").await.unwrap();
+        let mut meta = make_file_meta(FileMetadata::new("b.txt").with_tag("final"));
+        meta.size = 11;
+        backend.update_meta(file.id(), meta).await.unwrap();
+        assert_eq!(other.name(), "a.txt");
+        assert_eq!(file.size(), 11);
+
+        other.refresh().await.unwrap();
+        assert_eq!(other.name(), "b.txt");
+        assert_eq!(other.size(), 11);
+        assert_eq!(other.tags(), ["final"]);
+        assert!(other.metadata().is_empty());
+        assert!(other.parents().is_empty());
+    }
+}
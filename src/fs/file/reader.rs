@@ -0,0 +1,476 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+
+use futures::ready;
+
+use snafu::prelude::*;
+
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+use tokio::task::JoinHandle;
+
+use super::MenmosFile;
+use crate::fs::error::*;
+
+const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+
+const DEFAULT_PREFETCH_DEPTH: usize = 4;
+
+/// A chunk request running in the background.
+struct Chunk {
+    offset: u64,
+    len: usize,
+    handle: JoinHandle<Result<Vec<u8>>>,
+}
+
+/// A file reader fetching the chunks ahead of its cursor in the background.
+///
+/// Up to `prefetch_depth` chunk requests are kept in flight, so reads are served from
+/// memory while the next chunks are downloaded. This gives good throughput when processing
+/// a large blob sequentially, at the cost of memory. Seeking discards the prefetched chunks.
+///
+/// Chunk requests are spawned on the current tokio runtime, so reads must happen within one.
+///
+/// Like [`MenmosFile`], the reader implements the tokio [`AsyncRead`] and [`AsyncSeek`] traits,
+/// and their `futures::io` equivalents when the `futures-io` feature is enabled.
+///
+/// # Examples
+/// ```
+/// use menmos::{backend::MemoryBackend, FileMetadata, Menmos};
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let client = Menmos::from_backend(MemoryBackend::new());
+/// let file = client.fs.create_file(FileMetadata::new("video.mp4")).await.unwrap();
+///
+/// let mut reader = file
+///     .reader()
+///     .with_chunk_size(4 * 1024 * 1024)
+///     .with_prefetch_depth(8);
+///
+/// let mut buf = vec![0; 64 * 1024];
+/// while reader.read(&mut buf).await.unwrap() > 0 {
+///     // Process the data.
+/// }
+/// # }
+/// ```
+pub struct PrefetchReader {
+    /// The offset of the file is the cursor of the reader.
+    file: MenmosFile,
+
+    chunk_size: usize,
+    prefetch_depth: usize,
+
+    /// The data of the current chunk that wasn't read yet.
+    current: Bytes,
+
+    /// Chunk requests in flight, in file order.
+    in_flight: VecDeque<Chunk>,
+
+    /// The offset of the next chunk to request.
+    next_fetch: u64,
+
+    /// The cursor before the seek started through the async seek traits, if one is in progress.
+    seek_origin: Option<u64>,
+}
+
+impl MenmosFile {
+    /// Wrap this file in a reader prefetching the data ahead of the current offset.
+    ///
+    /// See [`PrefetchReader`].
+    pub fn reader(self) -> PrefetchReader {
+        let next_fetch = self.offset;
+        PrefetchReader {
+            file: self,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            prefetch_depth: DEFAULT_PREFETCH_DEPTH,
+            current: Bytes::new(),
+            in_flight: VecDeque::new(),
+            next_fetch,
+            seek_origin: None,
+        }
+    }
+}
+
+impl PrefetchReader {
+    /// Set the size of the chunks requested by the reader. The default is 1 MiB,
+    /// and the minimum is one byte.
+    ///
+    /// Chunks already in flight are not affected.
+    #[must_use]
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Set the number of chunks kept in flight by the reader. The default is 4 chunks.
+    ///
+    /// The minimum is one chunk, which disables prefetching.
+    #[must_use]
+    pub fn with_prefetch_depth(mut self, prefetch_depth: usize) -> Self {
+        self.prefetch_depth = prefetch_depth.max(1);
+        self
+    }
+
+    /// Get a reference to the underlying file, positioned at the cursor of the reader.
+    pub fn get_ref(&self) -> &MenmosFile {
+        &self.file
+    }
+
+    /// Get the underlying file back, positioned at the cursor of the reader.
+    pub fn into_inner(self) -> MenmosFile {
+        // Chunks in flight are cancelled when `self` is dropped.
+        self.file.clone()
+    }
+
+    /// Read a number of bytes from the file.
+    ///
+    /// This works like [`MenmosFile::read`].
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let data = futures::future::poll_fn(|cx| self.poll_next_bytes(cx, buf.len())).await?;
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+
+    /// Seek to a new position in the file, discarding the prefetched data.
+    ///
+    /// See [`MenmosFile::seek`].
+    pub async fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let previous_offset = self.file.offset;
+        let new_offset = self.file.seek(pos).await?;
+        if new_offset != previous_offset {
+            self.discard();
+        }
+        Ok(new_offset)
+    }
+
+    fn discard(&mut self) {
+        for chunk in self.in_flight.drain(..) {
+            chunk.handle.abort();
+        }
+        self.current = Bytes::new();
+        self.next_fetch = self.file.offset;
+    }
+
+    /// Start chunk requests until `prefetch_depth` of them are in flight.
+    fn schedule(&mut self) {
+        let size = self.file.known_size();
+
+        while self.in_flight.len() < self.prefetch_depth && self.next_fetch < size {
+            let offset = self.next_fetch;
+            let len = (size - offset).min(self.chunk_size as u64) as usize;
+
+            let backend = self.file.backend.clone();
+            let blob_id = self.file.blob_id.clone();
+            let handle = tokio::spawn(async move {
+                backend
+                    .read_range(&blob_id, (offset, offset + len as u64 - 1))
+                    .await
+                    .context(FileReadSnafu { blob_id })
+            });
+
+            self.in_flight.push_back(Chunk {
+                offset,
+                len,
+                handle,
+            });
+            self.next_fetch += len as u64;
+        }
+    }
+
+    /// Read at most `max_len` bytes from the cursor.
+    ///
+    /// An empty buffer is returned at the end of the file.
+    fn poll_next_bytes(&mut self, cx: &mut Context<'_>, max_len: usize) -> Poll<Result<Bytes>> {
+        if max_len == 0 {
+            return Poll::Ready(Ok(Bytes::new()));
+        }
+
+        while self.current.is_empty() {
+            self.schedule();
+
+            let chunk = match self.in_flight.front_mut() {
+                Some(chunk) => chunk,
+                None => return Poll::Ready(Ok(Bytes::new())),
+            };
+
+            let result = match ready!(Pin::new(&mut chunk.handle).poll(cx)) {
+                Ok(result) => result,
+                Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+                // The runtime is shutting down.
+                Err(_) => Err(FsError::PrefetchCancelledError {
+                    blob_id: self.file.blob_id.clone(),
+                }),
+            };
+            let chunk = self.in_flight.pop_front().unwrap();
+
+            let data = match result {
+                Ok(data) => data,
+                Err(e) => {
+                    // Start over from the cursor on the next read.
+                    self.discard();
+                    return Poll::Ready(Err(e));
+                }
+            };
+
+            if data.is_empty() {
+                // The file is shorter than we thought.
+                self.discard();
+                return Poll::Ready(Ok(Bytes::new()));
+            }
+
+            if data.len() < chunk.len {
+                // The chunks after this one don't start where this one ends, refetch them.
+                self.discard();
+                self.next_fetch = chunk.offset + data.len() as u64;
+            }
+
+            self.current = Bytes::from(data);
+        }
+
+        let data = self.current.split_to(max_len.min(self.current.len()));
+        self.file.offset += data.len() as u64;
+        Poll::Ready(Ok(data))
+    }
+
+    fn start_seek(&mut self, position: SeekFrom) -> io::Result<()> {
+        // The file moves its offset as soon as the seek starts, so we keep the previous one.
+        let previous_offset = self.file.offset;
+        self.file.start_seek_inner(position)?;
+        self.seek_origin = Some(previous_offset);
+        Ok(())
+    }
+
+    fn poll_seek_complete(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let result = ready!(self.file.poll_seek_complete(cx));
+        let previous_offset = self.seek_origin.take().unwrap_or(self.file.offset);
+        let new_offset = result?;
+        if new_offset != previous_offset {
+            self.discard();
+        }
+        Poll::Ready(Ok(new_offset))
+    }
+}
+
+impl Drop for PrefetchReader {
+    fn drop(&mut self) {
+        for chunk in self.in_flight.iter() {
+            chunk.handle.abort();
+        }
+    }
+}
+
+impl AsyncRead for PrefetchReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let data = ready!(self.get_mut().poll_next_bytes(cx, buf.remaining()))?;
+        buf.put_slice(&data);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for PrefetchReader {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        self.get_mut().start_seek(position)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        self.get_mut().poll_seek_complete(cx)
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures::io::AsyncRead for PrefetchReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let data = ready!(self.get_mut().poll_next_bytes(cx, buf.len()))?;
+        buf[..data.len()].copy_from_slice(&data);
+        Poll::Ready(Ok(data.len()))
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures::io::AsyncSeek for PrefetchReader {
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        position: SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+
+        // We're called again with the same position until the seek completes.
+        if !this.file.is_seeking() {
+            this.start_seek(position)?;
+        }
+
+        this.poll_seek_complete(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::fs::file::tests::{file_with, TestBackend};
+
+    fn content() -> Vec<u8> {
+        (0..10_000_u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn sequential_reads() {
+        let content = content();
+        let backend = Arc::new(TestBackend {
+            max_read: usize::MAX,
+            ..Default::default()
+        });
+        let mut file = file_with(backend.clone(), &content).await;
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+
+        let mut reader = file.reader().with_chunk_size(1000).with_prefetch_depth(3);
+
+        let mut output = Vec::new();
+        let mut buf = [0_u8; 300];
+        loop {
+            let read = reader.read(&mut buf).await.unwrap();
+            if read == 0 {
+                break;
+            }
+            output.extend_from_slice(&buf[..read]);
+        }
+
+        assert_eq!(output, content);
+        assert_eq!(backend.reads.load(Ordering::SeqCst), 10);
+        assert_eq!(reader.get_ref().offset, 10_000);
+    }
+
+    #[tokio::test]
+    async fn short_responses_are_refetched() {
+        let content = content();
+        let backend = Arc::new(TestBackend {
+            max_read: 700,
+            ..Default::default()
+        });
+        let mut file = file_with(backend, &content).await;
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+
+        let mut reader = file.reader().with_chunk_size(1000);
+        let mut output = Vec::new();
+        AsyncReadExt::read_to_end(&mut reader, &mut output)
+            .await
+            .unwrap();
+        assert_eq!(output, content);
+    }
+
+    #[tokio::test]
+    async fn seek_discards_prefetched_data() {
+        let content = content();
+        let file = file_with(Arc::new(MemoryBackend::new()), &content).await;
+        let mut reader = file.reader().with_chunk_size(512);
+
+        let mut buf = [0_u8; 16];
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 0);
+
+        reader.seek(SeekFrom::Start(100)).await.unwrap();
+        reader.read(&mut buf).await.unwrap();
+        assert_eq!(&buf, &content[100..116]);
+
+        AsyncSeekExt::seek(&mut reader, SeekFrom::End(-10))
+            .await
+            .unwrap();
+        let mut rest = Vec::new();
+        AsyncReadExt::read_to_end(&mut reader, &mut rest)
+            .await
+            .unwrap();
+        assert_eq!(rest, &content[9_990..]);
+
+        let file = reader.into_inner();
+        assert_eq!(file.offset, 10_000);
+    }
+
+    #[tokio::test]
+    async fn seek_traits_discard_prefetched_data() {
+        let content = content();
+        let mut file = file_with(Arc::new(MemoryBackend::new()), &content).await;
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        let mut reader = file.reader().with_chunk_size(512);
+
+        let mut buf = [0_u8; 16];
+        AsyncReadExt::read_exact(&mut reader, &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(&buf, &content[..16]);
+
+        let offset = AsyncSeekExt::seek(&mut reader, SeekFrom::Start(5000))
+            .await
+            .unwrap();
+        assert_eq!(offset, 5000);
+        AsyncReadExt::read_exact(&mut reader, &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(&buf, &content[5000..5016]);
+
+        let offset = AsyncSeekExt::seek(&mut reader, SeekFrom::Current(-1016))
+            .await
+            .unwrap();
+        assert_eq!(offset, 4000);
+        AsyncReadExt::read_exact(&mut reader, &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(&buf, &content[4000..4016]);
+
+        // Seeking to the cursor keeps the prefetched data.
+        AsyncSeekExt::seek(&mut reader, SeekFrom::Current(0))
+            .await
+            .unwrap();
+        AsyncReadExt::read_exact(&mut reader, &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(&buf, &content[4016..4032]);
+    }
+
+    #[cfg(feature = "futures-io")]
+    #[tokio::test]
+    async fn futures_seek_discards_prefetched_data() {
+        let content = content();
+        let mut file = file_with(Arc::new(MemoryBackend::new()), &content).await;
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        let mut reader = file.reader().with_chunk_size(512);
+
+        let mut buf = [0_u8; 16];
+        futures::io::AsyncReadExt::read_exact(&mut reader, &mut buf)
+            .await
+            .unwrap();
+
+        futures::io::AsyncSeekExt::seek(&mut reader, SeekFrom::Start(5000))
+            .await
+            .unwrap();
+        futures::io::AsyncReadExt::read_exact(&mut reader, &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(&buf, &content[5000..5016]);
+
+        futures::io::AsyncSeekExt::seek(&mut reader, SeekFrom::Current(100))
+            .await
+            .unwrap();
+        futures::io::AsyncReadExt::read_exact(&mut reader, &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(&buf, &content[5116..5132]);
+    }
+}
@@ -0,0 +1,166 @@
+use std::ops::Range;
+
+use async_stream::try_stream;
+
+use bytes::Bytes;
+
+use futures::Stream;
+
+use snafu::prelude::*;
+
+use super::MenmosFile;
+use crate::fs::error::*;
+
+/// The number of bytes requested at a time by [`MenmosFile::stream`].
+const STREAM_CHUNK_SIZE: u64 = 1024 * 1024;
+
+impl MenmosFile {
+    /// Get a stream of the contents of the file, fetched in chunks.
+    ///
+    /// When a range is given, only the bytes in that range are streamed. Ranges going past
+    /// the end of the file are truncated. The size of the file is refreshed when the stream
+    /// is first polled, and the offset of the handle is not used or moved.
+    ///
+    /// Only one chunk is held in memory at a time, so the stream can be used to send large
+    /// files as an HTTP response body or to hash them without buffering the whole file.
+    ///
+    /// # Examples
+    /// ```
+    /// use futures::TryStreamExt;
+    /// use menmos::{backend::MemoryBackend, FileMetadata, Menmos};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let client = Menmos::from_backend(MemoryBackend::new());
+    /// let file = client.fs.create_file(FileMetadata::new("a.txt")).await.unwrap();
+    /// file.write_at(0, b"hello world").await.unwrap();
+    ///
+    /// let mut total = 0;
+    /// let mut chunks = file.stream(Some(6..11));
+    /// while let Some(chunk) = chunks.try_next().await.unwrap() {
+    ///     total += chunk.len();
+    /// }
+    /// assert_eq!(total, 5);
+    /// # }
+    /// ```
+    pub fn stream(
+        &self,
+        range: Option<Range<u64>>,
+    ) -> impl Stream<Item = Result<Bytes>> + Send + Unpin + 'static {
+        self.stream_chunks(range, STREAM_CHUNK_SIZE)
+    }
+
+    pub(crate) fn stream_chunks(
+        &self,
+        range: Option<Range<u64>>,
+        chunk_size: u64,
+    ) -> impl Stream<Item = Result<Bytes>> + Send + Unpin + 'static {
+        let file = self.clone();
+
+        Box::pin(try_stream! {
+            let size = file.refresh_size().await?;
+            let range = range.unwrap_or(0..size);
+            let end = range.end.min(size);
+
+            let mut offset = range.start;
+            while offset < end {
+                let chunk_end = offset.saturating_add(chunk_size).min(end);
+                let data = file
+                    .backend
+                    .read_range(&file.blob_id, (offset, chunk_end - 1))
+                    .await
+                    .context(FileReadSnafu {
+                        blob_id: file.blob_id.clone(),
+                    })?;
+
+                if data.is_empty() {
+                    // The file was truncated since we got its size.
+                    break;
+                }
+
+                // Short responses are fine, the next chunk starts where this one ends.
+                offset += data.len() as u64;
+                yield Bytes::from(data);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::fs::file::tests::{file_with, TestBackend};
+
+    fn content() -> Vec<u8> {
+        (0..10_000_u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    async fn collect(stream: impl Stream<Item = Result<Bytes>> + Unpin) -> Vec<Bytes> {
+        stream.try_collect().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn streams_the_whole_file_in_chunks() {
+        let content = content();
+        let backend = Arc::new(TestBackend {
+            max_read: usize::MAX,
+            ..Default::default()
+        });
+        let file = file_with(backend.clone(), &content).await;
+
+        let chunks = collect(file.stream_chunks(None, 3000)).await;
+        let lengths: Vec<usize> = chunks.iter().map(|c| c.len()).collect();
+        assert_eq!(lengths, [3000, 3000, 3000, 1000]);
+        assert_eq!(chunks.concat(), content);
+        assert_eq!(backend.reads.load(Ordering::SeqCst), 4);
+
+        let chunks = collect(file.stream(None)).await;
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks.concat(), content);
+    }
+
+    #[tokio::test]
+    async fn streams_ranges() {
+        let content = content();
+        let file = file_with(Arc::new(MemoryBackend::new()), &content).await;
+
+        let chunks = collect(file.stream_chunks(Some(100..2_600), 1000)).await;
+        assert_eq!(chunks.concat(), &content[100..2_600]);
+
+        let chunks = collect(file.stream_chunks(Some(9_500..20_000), 1000)).await;
+        assert_eq!(chunks.concat(), &content[9_500..]);
+
+        assert!(collect(file.stream(Some(20_000..30_000))).await.is_empty());
+        assert!(collect(file.stream(Some(50..50))).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn short_responses_are_continued() {
+        let content = content();
+        let backend = Arc::new(TestBackend {
+            max_read: 700,
+            ..Default::default()
+        });
+        let file = file_with(backend, &content).await;
+
+        let chunks = collect(file.stream_chunks(None, 1000)).await;
+        assert_eq!(chunks.concat(), content);
+    }
+
+    #[tokio::test]
+    async fn streams_files_extended_by_other_handles() {
+        let backend: crate::BackendRC = Arc::new(MemoryBackend::new());
+        let writer = file_with(backend.clone(), b"abc").await;
+        let reader = MenmosFile::open(backend, writer.id()).await.unwrap();
+
+        writer.write_at(3, b"def").await.unwrap();
+        let chunks = collect(reader.stream(None)).await;
+        assert_eq!(chunks.concat(), b"abcdef");
+    }
+}
@@ -0,0 +1,347 @@
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::ready;
+
+use tokio::io::{AsyncSeek, AsyncWrite};
+
+use super::MenmosFile;
+use crate::fs::error::*;
+
+/// A file handle that coalesces small writes into larger ranged writes.
+///
+/// Writes are accumulated in memory and sent to the cluster when the buffer is full,
+/// before seeking, and on [`flush`](BufferedWriter::flush). Writes larger than the buffer
+/// capacity are sent directly.
+///
+/// Like [`MenmosFile`], the writer implements the tokio [`AsyncWrite`] and [`AsyncSeek`] traits,
+/// and their `futures::io` equivalents when the `futures-io` feature is enabled.
+///
+/// # Dropping
+/// Buffered data can't be written synchronously, so it is discarded, with an error logged, when
+/// the writer is dropped. Call [`flush`](BufferedWriter::flush) or
+/// [`into_inner`](BufferedWriter::into_inner) before dropping the writer to write it.
+///
+/// # Examples
+/// ```
+/// use menmos::{backend::MemoryBackend, FileMetadata, Menmos};
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let client = Menmos::from_backend(MemoryBackend::new());
+/// let file = client.fs.create_file(FileMetadata::new("log.txt")).await.unwrap();
+///
+/// let mut writer = file.buffered(64 * 1024);
+/// for i in 0..1000 {
+///     writer.write(format!("line {}\n", i).as_bytes()).await.unwrap();
+/// }
+/// let file = writer.into_inner().await.unwrap();
+/// # }
+/// ```
+pub struct BufferedWriter {
+    file: MenmosFile,
+    buffer: Vec<u8>,
+    capacity: usize,
+
+    /// A seek started by [`AsyncSeek::start_seek`], waiting for the buffer to be flushed.
+    pending_seek: Option<SeekFrom>,
+}
+
+impl MenmosFile {
+    /// Wrap this file in a writer buffering up to `capacity` bytes.
+    ///
+    /// Buffered data is written at the current offset of the file.
+    pub fn buffered(self, capacity: usize) -> BufferedWriter {
+        BufferedWriter {
+            file: self,
+            buffer: Vec::with_capacity(capacity),
+            capacity,
+            pending_seek: None,
+        }
+    }
+}
+
+impl BufferedWriter {
+    /// Get a reference to the underlying file.
+    ///
+    /// The offset of the file doesn't include the data that is still buffered.
+    pub fn get_ref(&self) -> &MenmosFile {
+        &self.file
+    }
+
+    /// Get the data that is waiting to be written.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Get the number of bytes that can be buffered before the buffer is written.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Write the contents of the provided buffer to the file, at the current offset.
+    ///
+    /// Returns the number of bytes written, which is always the length of the provided buffer.
+    ///
+    /// # Errors
+    /// When the buffer has to be written to make room, errors are returned here.
+    /// The buffered data is kept, so the operation can be attempted again.
+    pub async fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.buffer.len() + buf.len() > self.capacity {
+            self.flush().await?;
+        }
+
+        if buf.len() >= self.capacity {
+            self.file.write(buf).await
+        } else {
+            self.buffer.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    /// Write all buffered data to the file.
+    ///
+    /// If the write fails, the buffered data is kept, so the operation can be attempted again.
+    pub async fn flush(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            self.file.write(&self.buffer).await?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    /// Write all buffered data, then seek to a new position in the file.
+    ///
+    /// See [`MenmosFile::seek`].
+    pub async fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.flush().await?;
+        self.file.seek(pos).await
+    }
+
+    /// Write all buffered data, and get the underlying file back.
+    pub async fn into_inner(mut self) -> Result<MenmosFile> {
+        self.flush().await?;
+
+        // The buffer is empty, so dropping `self` is a no-op.
+        Ok(self.file.clone())
+    }
+
+    fn poll_flush_buffer(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.buffer.is_empty() {
+            let written = ready!(self.file.poll_write_bytes(cx, &self.buffer))?;
+            self.buffer.drain(..written);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_write_buffered(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        if self.buffer.len() + buf.len() > self.capacity {
+            ready!(self.poll_flush_buffer(cx))?;
+        }
+
+        if buf.len() >= self.capacity {
+            self.file.poll_write_bytes(cx, buf)
+        } else {
+            self.buffer.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+    }
+
+    fn poll_flush_all(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_flush_buffer(cx))?;
+        self.file.poll_operation(cx)
+    }
+
+    fn poll_seek_buffered(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        ready!(self.poll_flush_all(cx))?;
+        if let Some(position) = self.pending_seek.take() {
+            self.file.start_seek_inner(position)?;
+        }
+        self.file.poll_seek_complete(cx)
+    }
+}
+
+impl Drop for BufferedWriter {
+    fn drop(&mut self) {
+        if !self.buffer.is_empty() {
+            tracing::error!(
+                "discarded {} buffered bytes of file '{}': writer dropped without being flushed",
+                self.buffer.len(),
+                self.file.id()
+            );
+        }
+    }
+}
+
+impl AsyncWrite for BufferedWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_write_buffered(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush_all(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush_all(cx)
+    }
+}
+
+impl AsyncSeek for BufferedWriter {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        // The buffer can only be flushed asynchronously, so the seek is started in `poll_complete`.
+        self.get_mut().pending_seek = Some(position);
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        self.get_mut().poll_seek_buffered(cx)
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures::io::AsyncWrite for BufferedWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_write_buffered(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush_all(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush_all(cx)
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures::io::AsyncSeek for BufferedWriter {
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        position: SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+
+        // We're called again with the same position until the seek completes.
+        if this.pending_seek.is_none() && !this.file.is_seeking() {
+            this.pending_seek = Some(position);
+        }
+
+        this.poll_seek_buffered(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::backend::{Backend, MemoryBackend};
+    use crate::FileMetadata;
+
+    async fn new_file(backend: &MemoryBackend) -> MenmosFile {
+        MenmosFile::create(Arc::new(backend.clone()), FileMetadata::new("a.txt"))
+            .await
+            .unwrap()
+    }
+
+    async fn content(backend: &MemoryBackend, file: &MenmosFile) -> Vec<u8> {
+        backend
+            .read_range(file.id(), (0, u64::MAX - 1))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn writes_are_coalesced() {
+        let backend = MemoryBackend::new();
+        let file = new_file(&backend).await;
+        let mut writer = file.buffered(8);
+
+        writer.write(b"abc").await.unwrap();
+        writer.write(b"def").await.unwrap();
+        assert_eq!(writer.buffer(), b"abcdef");
+        assert!(content(&backend, writer.get_ref()).await.is_empty());
+
+        // This doesn't fit in the buffer, so the buffer is written first.
+        writer.write(b"ghi").await.unwrap();
+        assert_eq!(content(&backend, writer.get_ref()).await, b"abcdef");
+        assert_eq!(writer.buffer(), b"ghi");
+
+        // Large writes bypass the buffer.
+        writer.write(b"0123456789").await.unwrap();
+        assert!(writer.buffer().is_empty());
+        assert_eq!(
+            content(&backend, writer.get_ref()).await,
+            b"abcdefghi0123456789"
+        );
+    }
+
+    #[tokio::test]
+    async fn seek_flushes_the_buffer() {
+        let backend = MemoryBackend::new();
+        let file = new_file(&backend).await;
+        let mut writer = file.buffered(1024);
+
+        writer.write(b"hello world").await.unwrap();
+        writer.seek(SeekFrom::Start(6)).await.unwrap();
+        writer.write(b"there").await.unwrap();
+
+        let file = writer.into_inner().await.unwrap();
+        assert_eq!(content(&backend, &file).await, b"hello there");
+    }
+
+    #[tokio::test]
+    async fn tokio_traits() {
+        let backend = MemoryBackend::new();
+        let file = new_file(&backend).await;
+        let mut writer = file.buffered(16);
+
+        for i in 0..10 {
+            writer
+                .write_all(format!("line {}\n", i).as_bytes())
+                .await
+                .unwrap();
+        }
+        AsyncSeekExt::seek(&mut writer, SeekFrom::Start(0))
+            .await
+            .unwrap();
+        writer.write_all(b"LINE").await.unwrap();
+        AsyncWriteExt::flush(&mut writer).await.unwrap();
+
+        let expected: String = (0..10).map(|i| format!("line {}\n", i)).collect();
+        let expected = expected.replacen("line", "LINE", 1);
+        assert_eq!(
+            content(&backend, writer.get_ref()).await,
+            expected.as_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn dropped_writers_discard_buffered_data() {
+        let backend = MemoryBackend::new();
+        let file = new_file(&backend).await;
+        let id = String::from(file.id());
+
+        let mut writer = file.buffered(1024);
+        writer.write(b"flushed").await.unwrap();
+        writer.flush().await.unwrap();
+        writer.write(b" pending").await.unwrap();
+        drop(writer);
+
+        assert_eq!(backend.get_meta(&id).await.unwrap().unwrap().size, 7);
+        assert_eq!(backend.read_range(&id, (0, 6)).await.unwrap(), b"flushed");
+    }
+}
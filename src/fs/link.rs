@@ -0,0 +1,376 @@
+use menmos_client::Type;
+
+use snafu::prelude::*;
+
+use super::dir::MenmosDirectory;
+use super::error::*;
+use super::MenmosFs;
+use crate::util;
+use crate::FileMetadata;
+
+impl MenmosFs {
+    /// Link a file or directory to an additional parent directory.
+    ///
+    /// The blob stays in all of its current directories, like a hard link. Linking a blob
+    /// to a directory it is already in does nothing.
+    ///
+    /// Returns the metadata of the blob after the update.
+    ///
+    /// # Errors
+    /// If `dir_id` is not a directory, or if linking a directory to `dir_id` would make it
+    /// its own descendant, an error variant is returned.
+    ///
+    /// # Examples
+    /// ```
+    /// use menmos::{backend::MemoryBackend, FileMetadata, Menmos};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let client = Menmos::from_backend(MemoryBackend::new());
+    /// let photos = client.fs.create_dir(FileMetadata::new("photos")).await.unwrap();
+    /// let favorites = client.fs.create_dir(FileMetadata::new("favorites")).await.unwrap();
+    /// let photo = client
+    ///     .fs
+    ///     .create_file(FileMetadata::new("cat.jpg").with_parent(photos.id()))
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// let metadata = client.fs.link(photo.id(), favorites.id()).await.unwrap();
+    /// assert_eq!(metadata.parents.len(), 2);
+    /// # }
+    /// ```
+    pub async fn link<S: AsRef<str>, D: AsRef<str>>(
+        &self,
+        id: S,
+        dir_id: D,
+    ) -> Result<FileMetadata> {
+        let id = id.as_ref();
+        let dir_id = dir_id.as_ref();
+
+        let mut meta = self.blob_meta(id).await?;
+        if meta.parents.iter().any(|p| p == dir_id) {
+            return Ok(FileMetadata::from(meta));
+        }
+
+        let dir_meta = self.blob_meta(dir_id).await?;
+        ensure!(
+            dir_meta.blob_type == Type::Directory,
+            ExpectedDirectorySnafu { blob_id: dir_id }
+        );
+        ensure!(
+            meta.blob_type != Type::Directory || !self.is_under(dir_id, dir_meta, id).await?,
+            LinkCycleSnafu {
+                blob_id: id,
+                dir_id
+            }
+        );
+
+        meta.parents.push(String::from(dir_id));
+        self.replace_meta(id, meta).await
+    }
+
+    /// Unlink a file or directory from one of its parent directories.
+    ///
+    /// When the last parent of a blob is unlinked, the blob itself is removed, like a hard link.
+    ///
+    /// Returns the metadata of the blob after the update, or `None` if it was removed.
+    ///
+    /// # Errors
+    /// If the blob is not in `dir_id`, an error variant is returned. Unlinking the last parent
+    /// of a directory that is _not_ empty also returns an error variant, use
+    /// [`MenmosFs::remove_dir_all`] to remove a directory along with its children.
+    ///
+    /// # Examples
+    /// ```
+    /// use menmos::{backend::MemoryBackend, FileMetadata, Menmos};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let client = Menmos::from_backend(MemoryBackend::new());
+    /// let inbox = client.fs.create_dir(FileMetadata::new("inbox")).await.unwrap();
+    /// let mail = client
+    ///     .fs
+    ///     .create_file(FileMetadata::new("mail.eml").with_parent(inbox.id()))
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// // The file was only in the inbox, so it is removed.
+    /// let metadata = client.fs.unlink(mail.id(), inbox.id()).await.unwrap();
+    /// assert!(metadata.is_none());
+    /// # }
+    /// ```
+    pub async fn unlink<S: AsRef<str>, D: AsRef<str>>(
+        &self,
+        id: S,
+        dir_id: D,
+    ) -> Result<Option<FileMetadata>> {
+        let id = id.as_ref();
+        let dir_id = dir_id.as_ref();
+
+        let mut meta = self.blob_meta(id).await?;
+        ensure!(
+            meta.parents.iter().any(|p| p == dir_id),
+            NotLinkedSnafu {
+                blob_id: id,
+                dir_id
+            }
+        );
+
+        meta.parents.retain(|p| p != dir_id);
+        if !meta.parents.is_empty() {
+            return self.replace_meta(id, meta).await.map(Some);
+        }
+
+        if meta.blob_type == Type::Directory {
+            let dir = MenmosDirectory::open_raw(self.backend.clone(), id, meta)?;
+            ensure!(dir.is_empty().await?, DirIsNotEmptySnafu { blob_id: id });
+        }
+
+        self.remove_blob_unchecked(id).await?;
+        Ok(None)
+    }
+
+    /// Get handles to the directories containing a file or directory.
+    ///
+    /// Parents that no longer exist are skipped.
+    ///
+    /// # Examples
+    /// ```
+    /// use menmos::{backend::MemoryBackend, FileMetadata, Menmos};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let client = Menmos::from_backend(MemoryBackend::new());
+    /// let docs = client.fs.create_dir(FileMetadata::new("docs")).await.unwrap();
+    /// let file = client
+    ///     .fs
+    ///     .create_file(FileMetadata::new("readme.md").with_parent(docs.id()))
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// let parents = client.fs.parents(file.id()).await.unwrap();
+    /// assert_eq!(parents[0].id(), docs.id());
+    /// # }
+    /// ```
+    pub async fn parents<S: AsRef<str>>(&self, id: S) -> Result<Vec<MenmosDirectory>> {
+        let meta = self.blob_meta(id.as_ref()).await?;
+
+        let mut parents = Vec::with_capacity(meta.parents.len());
+        for parent_id in meta.parents.iter() {
+            let parent_meta = util::get_meta_if_exists(&self.backend, parent_id)
+                .await
+                .context(MetadataReadSnafu { blob_id: parent_id })?;
+
+            if let Some(parent_meta) = parent_meta {
+                let dir = MenmosDirectory::open_raw(self.backend.clone(), parent_id, parent_meta)?
+                    .with_page_size(self.defaults.page_size)
+                    .with_block_cache(self.cache.clone());
+                parents.push(dir);
+            }
+        }
+
+        Ok(parents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::TryStreamExt;
+    use menmos_client::Query;
+
+    use super::*;
+    use crate::backend::{Backend, MemoryBackend};
+    use crate::fs::DirEntry;
+
+    async fn count_blobs(backend: &MemoryBackend) -> usize {
+        backend.query(Query::default()).await.unwrap().total
+    }
+
+    async fn list_ids(dir: &MenmosDirectory) -> Vec<String> {
+        let mut ids: Vec<String> = dir
+            .list()
+            .map_ok(|entry| match entry {
+                DirEntry::File(f) => String::from(f.id()),
+                DirEntry::Directory(d) => String::from(d.id()),
+            })
+            .try_collect()
+            .await
+            .unwrap();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn link_and_unlink_files() {
+        let backend = MemoryBackend::new();
+        let fs = MenmosFs::new(Arc::new(backend.clone()));
+
+        let a = fs.create_dir(FileMetadata::new("a")).await.unwrap();
+        let b = fs.create_dir(FileMetadata::new("b")).await.unwrap();
+        let file = fs
+            .create_file(FileMetadata::new("f").with_parent(a.id()))
+            .await
+            .unwrap();
+
+        fs.link(file.id(), b.id()).await.unwrap();
+        let metadata = fs.link(file.id(), b.id()).await.unwrap();
+        assert_eq!(
+            metadata.parents,
+            vec![String::from(a.id()), String::from(b.id())]
+        );
+        assert_eq!(list_ids(&b).await, vec![String::from(file.id())]);
+
+        let parents: Vec<String> = fs
+            .parents(file.id())
+            .await
+            .unwrap()
+            .iter()
+            .map(|d| String::from(d.id()))
+            .collect();
+        assert_eq!(parents, metadata.parents);
+
+        let metadata = fs.unlink(file.id(), a.id()).await.unwrap().unwrap();
+        assert_eq!(metadata.parents, vec![String::from(b.id())]);
+        assert!(list_ids(&a).await.is_empty());
+
+        let err = fs.unlink(file.id(), a.id()).await.unwrap_err();
+        assert!(matches!(err, FsError::NotLinkedError { .. }));
+
+        // Removing the last link removes the file.
+        assert_eq!(fs.unlink(file.id(), b.id()).await.unwrap(), None);
+        assert_eq!(count_blobs(&backend).await, 2);
+    }
+
+    #[tokio::test]
+    async fn links_are_checked() {
+        let fs = MenmosFs::new(Arc::new(MemoryBackend::new()));
+
+        let a = fs.create_dir(FileMetadata::new("a")).await.unwrap();
+        let b = fs
+            .create_dir(FileMetadata::new("b").with_parent(a.id()))
+            .await
+            .unwrap();
+        let file = fs
+            .create_file(FileMetadata::new("f").with_parent(b.id()))
+            .await
+            .unwrap();
+
+        let err = fs.link(a.id(), file.id()).await.unwrap_err();
+        assert!(matches!(err, FsError::ExpectedDirectoryError { .. }));
+
+        let err = fs.link(a.id(), b.id()).await.unwrap_err();
+        assert!(matches!(err, FsError::LinkCycleError { .. }));
+
+        // Directories can only lose their last link when they are empty.
+        let err = fs.unlink(b.id(), a.id()).await.unwrap_err();
+        assert!(matches!(err, FsError::DirIsNotEmptyError { .. }));
+        fs.unlink(file.id(), b.id()).await.unwrap();
+        assert_eq!(fs.unlink(b.id(), a.id()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn parents_skip_missing_directories() {
+        let fs = MenmosFs::new(Arc::new(MemoryBackend::new()));
+
+        let a = fs.create_dir(FileMetadata::new("a")).await.unwrap();
+        let file = fs
+            .create_file(
+                FileMetadata::new("f")
+                    .with_parent(a.id())
+                    .with_parent("missing"),
+            )
+            .await
+            .unwrap();
+
+        let parents = fs.parents(file.id()).await.unwrap();
+        assert_eq!(parents.len(), 1);
+        assert_eq!(parents[0].id(), a.id());
+    }
+
+    #[tokio::test]
+    async fn remove_dir_all_keeps_blobs_linked_elsewhere() {
+        let backend = MemoryBackend::new();
+        let fs = MenmosFs::new(Arc::new(backend.clone()));
+
+        // root/{sub/{shared, only_sub}, shared}, and other/{shared, sub}
+        let root = fs.create_dir(FileMetadata::new("root")).await.unwrap();
+        let other = fs.create_dir(FileMetadata::new("other")).await.unwrap();
+        let sub = fs
+            .create_dir(FileMetadata::new("sub").with_parent(root.id()))
+            .await
+            .unwrap();
+        let shared_file = fs
+            .create_file(
+                FileMetadata::new("shared")
+                    .with_parent(root.id())
+                    .with_parent(sub.id())
+                    .with_parent(other.id()),
+            )
+            .await
+            .unwrap();
+        let in_root_only = fs
+            .create_file(
+                FileMetadata::new("in_root_only")
+                    .with_parent(root.id())
+                    .with_parent(sub.id()),
+            )
+            .await
+            .unwrap();
+        fs.create_file(FileMetadata::new("only_sub").with_parent(sub.id()))
+            .await
+            .unwrap();
+
+        fs.remove_dir_all(root.id()).await.unwrap();
+
+        // Only the blobs that were reachable from outside the tree are left.
+        assert_eq!(count_blobs(&backend).await, 2);
+        assert!(backend.get_meta(in_root_only.id()).await.unwrap().is_none());
+        assert_eq!(
+            fs.metadata(shared_file.id()).await.unwrap().parents,
+            vec![String::from(other.id())]
+        );
+
+        // Directories linked elsewhere are kept along with their children.
+        let root = fs.create_dir(FileMetadata::new("root")).await.unwrap();
+        let sub = fs
+            .create_dir(FileMetadata::new("sub").with_parent(root.id()))
+            .await
+            .unwrap();
+        fs.link(sub.id(), other.id()).await.unwrap();
+        let child = fs
+            .create_file(FileMetadata::new("child").with_parent(sub.id()))
+            .await
+            .unwrap();
+        fs.remove_dir_all(root.id()).await.unwrap();
+        assert_eq!(list_ids(&sub).await, vec![String::from(child.id())]);
+        assert_eq!(
+            fs.metadata(sub.id()).await.unwrap().parents,
+            vec![String::from(other.id())]
+        );
+    }
+
+    #[tokio::test]
+    async fn remove_dir_all_handles_cycles() {
+        let backend = MemoryBackend::new();
+        let fs = MenmosFs::new(Arc::new(backend.clone()));
+
+        let a = fs.create_dir(FileMetadata::new("a")).await.unwrap();
+        let b = fs
+            .create_dir(FileMetadata::new("b").with_parent(a.id()))
+            .await
+            .unwrap();
+        fs.create_file(FileMetadata::new("f").with_parent(b.id()))
+            .await
+            .unwrap();
+
+        // Cycles can't be created through `link`, but other clients can create them.
+        let mut meta = fs.blob_meta(a.id()).await.unwrap();
+        meta.parents.push(String::from(b.id()));
+        fs.replace_meta(a.id(), meta).await.unwrap();
+
+        fs.remove_dir_all(a.id()).await.unwrap();
+        assert_eq!(count_blobs(&backend).await, 0);
+    }
+}
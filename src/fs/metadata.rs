@@ -0,0 +1,365 @@
+use std::collections::{HashMap, HashSet};
+
+use interface::BlobMeta;
+use menmos_client::{Meta, Type};
+
+use snafu::prelude::*;
+
+use super::error::*;
+use super::MenmosFs;
+use crate::util;
+use crate::FileMetadata;
+
+/// A pending change to the metadata of a blob.
+///
+/// Created by [`MenmosFs::update`]. Changes are sent when calling [`MetadataUpdate::apply`].
+pub struct MetadataUpdate<'a> {
+    fs: &'a MenmosFs,
+    blob_id: String,
+    name: Option<String>,
+    added_tags: Vec<String>,
+    removed_tags: Vec<String>,
+    set_meta: HashMap<String, String>,
+    removed_meta: Vec<String>,
+}
+
+impl<'a> MetadataUpdate<'a> {
+    /// Change the name of the blob.
+    #[must_use]
+    pub fn rename<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Add a tag to the blob, if it doesn't have it already.
+    #[must_use]
+    pub fn add_tag<S: Into<String>>(mut self, tag: S) -> Self {
+        let tag = tag.into();
+        self.removed_tags.retain(|t| t != &tag);
+        self.added_tags.push(tag);
+        self
+    }
+
+    /// Remove a tag from the blob, if it has it.
+    #[must_use]
+    pub fn remove_tag<S: Into<String>>(mut self, tag: S) -> Self {
+        let tag = tag.into();
+        self.added_tags.retain(|t| t != &tag);
+        self.removed_tags.push(tag);
+        self
+    }
+
+    /// Set the value of a key/value pair of the blob.
+    #[must_use]
+    pub fn set_meta<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        let key = key.into();
+        self.removed_meta.retain(|k| k != &key);
+        self.set_meta.insert(key, value.into());
+        self
+    }
+
+    /// Remove a key/value pair from the blob, if it has it.
+    #[must_use]
+    pub fn remove_meta<K: Into<String>>(mut self, key: K) -> Self {
+        let key = key.into();
+        self.set_meta.remove(&key);
+        self.removed_meta.push(key);
+        self
+    }
+
+    /// Send the changes to the cluster.
+    ///
+    /// Returns the metadata of the blob after the update.
+    ///
+    /// # Concurrency
+    /// The metadata is read, modified, then written back as a whole, so concurrent updates
+    /// of the same blob can overwrite each other.
+    pub async fn apply(self) -> Result<FileMetadata> {
+        let mut meta = self.fs.blob_meta(&self.blob_id).await?;
+
+        if let Some(name) = self.name {
+            meta.name = name;
+        }
+
+        meta.tags.retain(|t| !self.removed_tags.contains(t));
+        for tag in self.added_tags {
+            if !meta.tags.contains(&tag) {
+                meta.tags.push(tag);
+            }
+        }
+
+        for key in self.removed_meta.iter() {
+            meta.metadata.remove(key);
+        }
+        meta.metadata.extend(self.set_meta);
+
+        self.fs.replace_meta(&self.blob_id, meta).await
+    }
+}
+
+impl MenmosFs {
+    pub(super) async fn blob_meta(&self, id: &str) -> Result<BlobMeta> {
+        util::get_meta(&self.backend, id)
+            .await
+            .context(MetadataReadSnafu { blob_id: id })
+    }
+
+    /// Overwrite the metadata of a blob, returning it.
+    pub(super) async fn replace_meta(&self, id: &str, meta: BlobMeta) -> Result<FileMetadata> {
+        let request = Meta {
+            name: meta.name.clone(),
+            blob_type: meta.blob_type.clone(),
+            metadata: meta.metadata.clone(),
+            tags: meta.tags.clone(),
+            parents: meta.parents.clone(),
+            size: meta.size,
+        };
+
+        self.backend
+            .update_meta(id, request)
+            .await
+            .context(BlobUpdateSnafu { blob_id: id })?;
+
+        Ok(FileMetadata::from(meta))
+    }
+
+    /// Get the metadata of a file or directory.
+    ///
+    /// # Examples
+    /// ```
+    /// use menmos::{backend::MemoryBackend, FileMetadata, Menmos};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let client = Menmos::from_backend(MemoryBackend::new());
+    /// let file = client.fs.create_file(FileMetadata::new("a.txt")).await.unwrap();
+    ///
+    /// let metadata = client.fs.metadata(file.id()).await.unwrap();
+    /// assert_eq!(metadata.name, "a.txt");
+    /// # }
+    /// ```
+    pub async fn metadata<S: AsRef<str>>(&self, id: S) -> Result<FileMetadata> {
+        self.blob_meta(id.as_ref()).await.map(FileMetadata::from)
+    }
+
+    /// Start an update of the metadata of a file or directory.
+    ///
+    /// # Examples
+    /// ```
+    /// use menmos::{backend::MemoryBackend, FileMetadata, Menmos};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let client = Menmos::from_backend(MemoryBackend::new());
+    /// let file = client
+    ///     .fs
+    ///     .create_file(FileMetadata::new("draft.txt").with_tag("draft"))
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// let metadata = client
+    ///     .fs
+    ///     .update(file.id())
+    ///     .rename("final.txt")
+    ///     .remove_tag("draft")
+    ///     .add_tag("published")
+    ///     .set_meta("reviewer", "alice")
+    ///     .apply()
+    ///     .await
+    ///     .unwrap();
+    /// assert_eq!(metadata.tags, vec![String::from("published")]);
+    /// # }
+    /// ```
+    pub fn update<S: AsRef<str>>(&self, id: S) -> MetadataUpdate<'_> {
+        MetadataUpdate {
+            fs: self,
+            blob_id: String::from(id.as_ref()),
+            name: None,
+            added_tags: Vec::new(),
+            removed_tags: Vec::new(),
+            set_meta: HashMap::new(),
+            removed_meta: Vec::new(),
+        }
+    }
+
+    /// Move a file or directory to another directory.
+    ///
+    /// All the parents of the blob are replaced by the new parent.
+    ///
+    /// # Errors
+    /// If `new_parent` is not a directory, or if a directory is moved into itself or one
+    /// of its children, an error variant is returned.
+    pub async fn move_to<S: AsRef<str>, P: AsRef<str>>(
+        &self,
+        id: S,
+        new_parent: P,
+    ) -> Result<FileMetadata> {
+        let id = id.as_ref();
+        let new_parent = new_parent.as_ref();
+
+        let mut meta = self.blob_meta(id).await?;
+        let parent_meta = self.blob_meta(new_parent).await?;
+        ensure!(
+            parent_meta.blob_type == Type::Directory,
+            ExpectedDirectorySnafu {
+                blob_id: new_parent
+            }
+        );
+
+        ensure!(
+            meta.blob_type != Type::Directory
+                || !self.is_under(new_parent, parent_meta, id).await?,
+            MoveIntoSelfSnafu { blob_id: id }
+        );
+
+        meta.parents = vec![String::from(new_parent)];
+        self.replace_meta(id, meta).await
+    }
+
+    /// Get whether `dir_id` is `ancestor` or one of its descendants.
+    pub(super) async fn is_under(
+        &self,
+        dir_id: &str,
+        dir_meta: BlobMeta,
+        ancestor: &str,
+    ) -> Result<bool> {
+        let mut visited = HashSet::new();
+        let mut stack = vec![(String::from(dir_id), Some(dir_meta))];
+
+        while let Some((blob_id, meta)) = stack.pop() {
+            if blob_id == ancestor {
+                return Ok(true);
+            }
+
+            if !visited.insert(blob_id.clone()) {
+                continue;
+            }
+
+            let meta = match meta {
+                Some(meta) => meta,
+                None => match util::get_meta_if_exists(&self.backend, &blob_id)
+                    .await
+                    .context(MetadataReadSnafu { blob_id: &blob_id })?
+                {
+                    Some(meta) => meta,
+                    None => continue,
+                },
+            };
+
+            stack.extend(meta.parents.into_iter().map(|p| (p, None)));
+        }
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::ErrorKind;
+
+    fn new_fs() -> MenmosFs {
+        MenmosFs::new(Arc::new(MemoryBackend::new()))
+    }
+
+    #[tokio::test]
+    async fn update_metadata() {
+        let fs = new_fs();
+        let file = fs
+            .create_file(
+                FileMetadata::new("a.txt")
+                    .with_tag("draft")
+                    .with_tag("text")
+                    .with_meta("owner", "bob")
+                    .with_meta("stale", "yes"),
+            )
+            .await
+            .unwrap();
+        file.write_at(0, b"content").await.unwrap();
+
+        let updated = fs
+            .update(file.id())
+            .rename("b.txt")
+            .add_tag("published")
+            .add_tag("text")
+            .remove_tag("draft")
+            .set_meta("owner", "alice")
+            .remove_meta("stale")
+            .apply()
+            .await
+            .unwrap();
+
+        assert_eq!(fs.metadata(file.id()).await.unwrap(), updated);
+        assert_eq!(updated.name, "b.txt");
+        assert_eq!(
+            updated.tags,
+            vec![String::from("text"), String::from("published")]
+        );
+        assert_eq!(
+            updated.metadata,
+            HashMap::from([(String::from("owner"), String::from("alice"))])
+        );
+        assert_eq!(updated.size, 7);
+
+        // The last change to a tag or key wins.
+        let updated = fs
+            .update(file.id())
+            .remove_tag("text")
+            .add_tag("text")
+            .set_meta("stale", "again")
+            .remove_meta("stale")
+            .apply()
+            .await
+            .unwrap();
+        assert!(updated.tags.contains(&String::from("text")));
+        assert!(!updated.metadata.contains_key("stale"));
+    }
+
+    #[tokio::test]
+    async fn missing_blobs_are_not_found() {
+        let fs = new_fs();
+        let err = fs.metadata("missing").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+
+        let err = fs.update("missing").rename("a").apply().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn move_between_directories() {
+        let fs = new_fs();
+        let a = fs.create_dir(FileMetadata::new("a")).await.unwrap();
+        let b = fs
+            .create_dir(FileMetadata::new("b").with_parent(a.id()))
+            .await
+            .unwrap();
+        let c = fs.create_dir(FileMetadata::new("c")).await.unwrap();
+        let file = fs
+            .create_file(FileMetadata::new("f").with_parent(a.id()))
+            .await
+            .unwrap();
+
+        let moved = fs.move_to(file.id(), c.id()).await.unwrap();
+        assert_eq!(moved.parents, vec![String::from(c.id())]);
+        assert_eq!(
+            fs.metadata(file.id()).await.unwrap().parents,
+            vec![String::from(c.id())]
+        );
+
+        // Files can't be parents.
+        let err = fs.move_to(b.id(), file.id()).await.unwrap_err();
+        assert!(matches!(err, FsError::ExpectedDirectoryError { .. }));
+
+        // Directories can't be moved under themselves.
+        let err = fs.move_to(a.id(), b.id()).await.unwrap_err();
+        assert!(matches!(err, FsError::MoveIntoSelfError { .. }));
+        let err = fs.move_to(a.id(), a.id()).await.unwrap_err();
+        assert!(matches!(err, FsError::MoveIntoSelfError { .. }));
+
+        fs.move_to(b.id(), c.id()).await.unwrap();
+        fs.move_to(a.id(), b.id()).await.unwrap();
+    }
+}
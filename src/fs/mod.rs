@@ -1,34 +1,121 @@
 //! The filesystem SDK module.
 
+mod cache;
+mod copy;
 mod dir;
 mod error;
 mod file;
+mod link;
+mod metadata;
+mod path;
+mod upload;
+mod walk;
 
+pub use cache::{BlockCache, CacheStats};
+pub use copy::CopyOptions;
 pub use dir::{DirEntry, MenmosDirectory};
-pub use file::MenmosFile;
+pub use file::{BufferedWriter, DownloadOptions, DownloadProgress, MenmosFile, PrefetchReader};
+
+use std::collections::HashSet;
 
 use futures::TryStreamExt;
 
-use menmos_client::Type;
+use interface::Hit;
+
+use menmos_client::{Query, Type};
 
 use snafu::prelude::*;
 
 use crate::util;
-use crate::{ClientRC, FileMetadata};
+use crate::{BackendRC, Defaults, DefaultsRC, FileMetadata};
 
+use cache::BlockCacheRC;
 pub use error::FsError;
 use error::*;
+pub use metadata::MetadataUpdate;
+pub use path::FsRoot;
+pub use walk::{WalkEntry, WalkOptions, WalkOrder};
 
 /// The entrypoint structure of the filesystem SDK.
 #[derive(Clone)]
 pub struct MenmosFs {
-    client: ClientRC,
+    backend: BackendRC,
+    defaults: DefaultsRC,
+    cache: Option<BlockCacheRC>,
+    root: Option<FsRoot>,
 }
 
 impl MenmosFs {
     #[doc(hidden)]
-    pub fn new(client: ClientRC) -> Self {
-        Self { client }
+    pub fn new(backend: BackendRC) -> Self {
+        Self::new_with_defaults(backend, DefaultsRC::new(Defaults::default()))
+    }
+
+    pub(crate) fn new_with_defaults(backend: BackendRC, defaults: DefaultsRC) -> Self {
+        Self {
+            backend,
+            defaults,
+            cache: None,
+            root: None,
+        }
+    }
+
+    /// Read the files opened through this interface through the provided block cache.
+    pub(crate) fn with_block_cache(mut self, cache: BlockCacheRC) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Resolve paths from the provided root.
+    pub(crate) fn with_root(mut self, root: FsRoot) -> Self {
+        self.root = Some(root);
+        self
+    }
+
+    /// Get the block cache shared by the files opened through this interface, if there is one.
+    pub fn block_cache(&self) -> Option<&BlockCache> {
+        self.cache.as_deref()
+    }
+
+    fn with_defaults(&self, mut metadata: FileMetadata) -> FileMetadata {
+        self.defaults
+            .apply(&mut metadata.tags, &mut metadata.metadata);
+        metadata
+    }
+
+    /// Open a handle to a file or directory found by a query.
+    ///
+    /// The handle carries the metadata of the hit, so no request is sent.
+    ///
+    /// # Examples
+    /// ```
+    /// use futures::TryStreamExt;
+    /// use menmos::{backend::MemoryBackend, FileMetadata, Menmos, Query};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let client = Menmos::from_backend(MemoryBackend::new());
+    /// client
+    ///     .fs
+    ///     .create_file(FileMetadata::new("report.pdf").with_tag("report"))
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// let mut hits = client.query(Query::default().and_tag("report"));
+    /// while let Some(hit) = hits.try_next().await.unwrap() {
+    ///     let entry = client.fs.open_hit(hit).unwrap();
+    ///     println!("{} ({} bytes)", entry.name(), entry.size());
+    /// }
+    /// # }
+    /// ```
+    pub fn open_hit(&self, hit: Hit) -> Result<DirEntry> {
+        DirEntry::open_raw(
+            self.backend.clone(),
+            &hit.id,
+            hit.meta,
+            self.defaults.page_size,
+            self.cache.clone(),
+        )
     }
 
     /// Create a new file with the provided metadata.
@@ -42,24 +129,28 @@ impl MenmosFs {
     ///
     /// # #[tokio::main]
     /// # async fn main() {
-    /// # let client = menmos_client::Client::new("a", "b", "c").await.unwrap();
-    /// # let fs = MenmosFs::new(std::sync::Arc::new(client));
+    /// # let backend = menmos_client::Client::new("a", "b", "c").await.unwrap();
+    /// # let fs = MenmosFs::new(std::sync::Arc::new(backend));
     /// let handle = fs.create_file(FileMetadata::new("test.txt").with_tag("sdk_file"))
     ///     .await
     ///     .unwrap();
     /// # }
     /// ```
     pub async fn create_file(&self, metadata: FileMetadata) -> Result<MenmosFile> {
-        MenmosFile::create(self.client.clone(), metadata).await
+        let file = MenmosFile::create(self.backend.clone(), self.with_defaults(metadata)).await?;
+        Ok(file.with_block_cache(self.cache.clone()))
     }
 
     async fn remove_blob_unchecked<S: AsRef<str>>(&self, id: S) -> Result<()> {
-        // TODO: Update the menmos client so that Client::delete takes a ref.
-        self.client
-            .delete(String::from(id.as_ref()))
+        if let Some(cache) = self.cache.as_ref() {
+            cache.invalidate_blob(id.as_ref());
+        }
+
+        self.backend
+            .delete(id.as_ref())
             .await
-            .map_err(|_| FsError::BlobDeleteError {
-                blob_id: id.as_ref().into(),
+            .context(BlobDeleteSnafu {
+                blob_id: id.as_ref(),
             })
     }
 
@@ -78,13 +169,13 @@ impl MenmosFs {
     /// # use menmos::fs::MenmosFs;
     /// # #[tokio::main]
     /// # async fn main() {
-    /// # let client = menmos_client::Client::new("a", "b", "c").await.unwrap();
-    /// # let fs = MenmosFs::new(std::sync::Arc::new(client));
+    /// # let backend = menmos_client::Client::new("a", "b", "c").await.unwrap();
+    /// # let fs = MenmosFs::new(std::sync::Arc::new(backend));
     /// fs.remove_file("<a file blob ID>").await.unwrap();
     /// # }
     /// ```
     pub async fn remove_file<S: AsRef<str>>(&self, id: S) -> Result<()> {
-        match util::get_meta_if_exists(&self.client, id.as_ref())
+        match util::get_meta_if_exists(&self.backend, id.as_ref())
             .await
             .context(FileRemoveSnafu {
                 blob_id: String::from(id.as_ref()),
@@ -112,15 +203,19 @@ impl MenmosFs {
     ///
     /// # #[tokio::main]
     /// # async fn main() {
-    /// # let client = menmos_client::Client::new("a", "b", "c").await.unwrap();
-    /// # let fs = MenmosFs::new(std::sync::Arc::new(client));
+    /// # let backend = menmos_client::Client::new("a", "b", "c").await.unwrap();
+    /// # let fs = MenmosFs::new(std::sync::Arc::new(backend));
     /// let handle = fs.create_dir(FileMetadata::new("my_directory").with_tag("sdk_dir"))
     ///     .await
     ///     .unwrap();
     /// # }
     /// ```
     pub async fn create_dir(&self, metadata: FileMetadata) -> Result<MenmosDirectory> {
-        MenmosDirectory::create(self.client.clone(), metadata).await
+        let dir =
+            MenmosDirectory::create(self.backend.clone(), self.with_defaults(metadata)).await?;
+        Ok(dir
+            .with_page_size(self.defaults.page_size)
+            .with_block_cache(self.cache.clone()))
     }
 
     /// Remove a directory by its ID.
@@ -140,13 +235,13 @@ impl MenmosFs {
     /// # use menmos::fs::MenmosFs;
     /// # #[tokio::main]
     /// # async fn main() {
-    /// # let client = menmos_client::Client::new("a", "b", "c").await.unwrap();
-    /// # let fs = MenmosFs::new(std::sync::Arc::new(client));
+    /// # let backend = menmos_client::Client::new("a", "b", "c").await.unwrap();
+    /// # let fs = MenmosFs::new(std::sync::Arc::new(backend));
     /// fs.remove_dir("<a dir blob ID>").await.unwrap();
     /// # }
     /// ```
     pub async fn remove_dir<S: AsRef<str>>(&self, id: S) -> Result<()> {
-        match util::get_meta_if_exists(&self.client, id.as_ref())
+        match util::get_meta_if_exists(&self.backend, id.as_ref())
             .await
             .context(DirRemoveSnafu)?
         {
@@ -158,7 +253,7 @@ impl MenmosFs {
                     }
                 );
 
-                let dir = MenmosDirectory::open_raw(self.client.clone(), id.as_ref(), meta)?;
+                let dir = MenmosDirectory::open_raw(self.backend.clone(), id.as_ref(), meta)?;
 
                 ensure!(
                     dir.is_empty().await?,
@@ -174,6 +269,9 @@ impl MenmosFs {
 
     /// Recursively remove a directory along with all its children.
     ///
+    /// Children that are also linked to directories outside of the removed tree are
+    /// unlinked from it instead of being removed (see [`MenmosFs::link`]).
+    ///
     /// If the specified blob ID does not exist, no error is returned and no operation
     /// is performed.
     ///
@@ -187,13 +285,13 @@ impl MenmosFs {
     /// # use menmos::fs::MenmosFs;
     /// # #[tokio::main]
     /// # async fn main() {
-    /// # let client = menmos_client::Client::new("a", "b", "c").await.unwrap();
-    /// # let fs = MenmosFs::new(std::sync::Arc::new(client));
+    /// # let backend = menmos_client::Client::new("a", "b", "c").await.unwrap();
+    /// # let fs = MenmosFs::new(std::sync::Arc::new(backend));
     /// fs.remove_dir_all("<a dir blob ID>").await.unwrap();
     /// # }
     /// ```
     pub async fn remove_dir_all<S: AsRef<str>>(&self, id: S) -> Result<()> {
-        match util::get_meta_if_exists(&self.client, id.as_ref())
+        match util::get_meta_if_exists(&self.backend, id.as_ref())
             .await
             .context(DirRemoveSnafu)?
         {
@@ -205,20 +303,39 @@ impl MenmosFs {
                     }
                 );
 
-                let dir = MenmosDirectory::open_raw(self.client.clone(), id.as_ref(), meta)?;
-
                 // We don't do the deletion recursively because recursivity + async requires a lot of indirection.
-                let mut delete_stack: Vec<DirEntry> = vec![DirEntry::Directory(dir)];
-                while let Some(target) = delete_stack.pop() {
-                    match target {
-                        DirEntry::File(file) => self.remove_blob_unchecked(file.id()).await?,
-                        DirEntry::Directory(dir) => {
-                            let children = dir.list().try_collect::<Vec<_>>().await?;
-
-                            delete_stack.extend(children.into_iter());
-                            self.remove_blob_unchecked(dir.id()).await?;
+                // Children are unlinked from each removed directory, and only removed along with it
+                // when they have no parent left outside of the removed blobs.
+                let mut removed = HashSet::new();
+                let mut delete_stack = vec![(String::from(id.as_ref()), meta)];
+                while let Some((blob_id, meta)) = delete_stack.pop() {
+                    if !removed.insert(blob_id.clone()) {
+                        continue;
+                    }
+
+                    if meta.blob_type == Type::Directory {
+                        let query = Query::default()
+                            .and_parent(&blob_id)
+                            .with_from(0)
+                            .with_size(self.defaults.page_size);
+                        let children = util::scroll_query(query, &self.backend)
+                            .try_collect::<Vec<_>>()
+                            .await
+                            .context(DirQuerySnafu)?;
+
+                        for child in children {
+                            let mut child_meta = child.meta;
+                            child_meta.parents.retain(|p| !removed.contains(p));
+
+                            if child_meta.parents.is_empty() {
+                                delete_stack.push((child.id, child_meta));
+                            } else if !removed.contains(&child.id) {
+                                self.replace_meta(&child.id, child_meta).await?;
+                            }
                         }
                     }
+
+                    self.remove_blob_unchecked(&blob_id).await?;
                 }
 
                 Ok(())
@@ -0,0 +1,419 @@
+use std::collections::HashSet;
+
+use futures::TryStreamExt;
+
+use interface::Hit;
+use menmos_client::{Query, Type};
+
+use snafu::prelude::*;
+
+use super::error::*;
+use super::{DirEntry, MenmosDirectory, MenmosFile, MenmosFs};
+use crate::util;
+use crate::FileMetadata;
+
+/// The directory at the root of the paths used by [`MenmosFs`].
+///
+/// Menmos has no built-in directory hierarchy, so one directory has to be designated as the
+/// root before blobs can be addressed by path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FsRoot {
+    /// The root is the directory with this ID.
+    Id(String),
+
+    /// The root is the only directory with this tag.
+    Tag(String),
+}
+
+/// Split a path into its components, ignoring empty components and `.`.
+fn components(path: &str) -> Result<Vec<&str>> {
+    let components: Vec<&str> = path
+        .split('/')
+        .filter(|c| !c.is_empty() && *c != ".")
+        .collect();
+
+    ensure!(
+        !components.contains(&".."),
+        InvalidPathSnafu {
+            path: String::from(path)
+        }
+    );
+
+    Ok(components)
+}
+
+impl MenmosFs {
+    async fn root_id(&self) -> Result<String> {
+        match self.root.as_ref().context(NoRootSnafu)? {
+            FsRoot::Id(id) => Ok(id.clone()),
+            FsRoot::Tag(tag) => {
+                let mut hits: Vec<Hit> =
+                    util::scroll_query(Query::default().and_tag(tag), &self.backend)
+                        .try_filter(|hit| {
+                            futures::future::ready(hit.meta.blob_type == Type::Directory)
+                        })
+                        .try_collect()
+                        .await
+                        .context(PathResolveSnafu)?;
+
+                ensure!(
+                    hits.len() <= 1,
+                    AmbiguousRootSnafu {
+                        tag: tag.clone(),
+                        count: hits.len()
+                    }
+                );
+                let hit = hits.pop().context(RootNotFoundSnafu { tag: tag.clone() })?;
+                Ok(hit.id)
+            }
+        }
+    }
+
+    /// Find the child of a directory with the given name.
+    async fn find_child(&self, dir_id: &str, name: &str, path: &str) -> Result<Option<Hit>> {
+        let query = Query::default()
+            .and_parent(dir_id)
+            .with_from(0)
+            .with_size(self.defaults.page_size);
+
+        let mut hits: Vec<Hit> = util::scroll_query(query, &self.backend)
+            .try_filter(|hit| futures::future::ready(hit.meta.name == name))
+            .try_collect()
+            .await
+            .context(PathResolveSnafu)?;
+
+        ensure!(
+            hits.len() <= 1,
+            AmbiguousPathSnafu {
+                path: String::from(path),
+                name: String::from(name),
+                count: hits.len(),
+            }
+        );
+        Ok(hits.pop())
+    }
+
+    /// Resolve the components of a path, starting from the root.
+    ///
+    /// Returns the ID and metadata of the blob at the path, or `None` for the root.
+    async fn resolve_hit(&self, path: &str) -> Result<(String, Option<Hit>)> {
+        let mut current_id = self.root_id().await?;
+        let mut current: Option<Hit> = None;
+
+        for name in components(path)? {
+            if let Some(hit) = current.as_ref() {
+                ensure!(
+                    hit.meta.blob_type == Type::Directory,
+                    ExpectedDirectorySnafu {
+                        blob_id: hit.id.clone()
+                    }
+                );
+            }
+
+            let hit =
+                self.find_child(&current_id, name, path)
+                    .await?
+                    .context(PathNotFoundSnafu {
+                        path: String::from(path),
+                    })?;
+            current_id = hit.id.clone();
+            current = Some(hit);
+        }
+
+        Ok((current_id, current))
+    }
+
+    /// Get the ID of the blob at the given path.
+    ///
+    /// Paths are made of names separated by `/`, starting from the configured [`FsRoot`].
+    /// Each name is looked up among the children of the previous directory.
+    ///
+    /// # Errors
+    /// If no root is configured, if the path doesn't exist, or if a directory contains
+    /// several children with one of the names of the path, an error variant is returned.
+    ///
+    /// # Examples
+    /// ```
+    /// use menmos::{backend::MemoryBackend, fs::FsRoot, FileMetadata, Menmos};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let client = Menmos::from_backend(MemoryBackend::new());
+    /// let root = client.fs.create_dir(FileMetadata::new("root")).await.unwrap();
+    /// let client = client.with_fs_root(FsRoot::Id(root.id().to_string()));
+    ///
+    /// let dir = client.fs.create_dir_all("/projects/2024").await.unwrap();
+    /// let file = client
+    ///     .fs
+    ///     .create_file_at_path("/projects/2024/report.pdf", FileMetadata::default())
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// assert_eq!(client.fs.resolve("/projects/2024").await.unwrap(), dir.id());
+    /// assert_eq!(client.fs.path_of(file.id()).await.unwrap(), "/projects/2024/report.pdf");
+    /// # }
+    /// ```
+    pub async fn resolve<P: AsRef<str>>(&self, path: P) -> Result<String> {
+        let (id, _) = self.resolve_hit(path.as_ref()).await?;
+        Ok(id)
+    }
+
+    /// Open the file or directory at the given path.
+    ///
+    /// See [`MenmosFs::resolve`] for how paths are resolved.
+    pub async fn open_path<P: AsRef<str>>(&self, path: P) -> Result<DirEntry> {
+        let (id, hit) = self.resolve_hit(path.as_ref()).await?;
+
+        let meta = match hit {
+            Some(hit) => hit.meta,
+            None => util::get_meta(&self.backend, &id)
+                .await
+                .context(PathResolveSnafu)?,
+        };
+
+        DirEntry::open_raw(
+            self.backend.clone(),
+            &id,
+            meta,
+            self.defaults.page_size,
+            self.cache.clone(),
+        )
+    }
+
+    /// Create a new file at the given path.
+    ///
+    /// The name of the file is the last component of the path, and its parent directory
+    /// must already exist. The other fields of `metadata` are used as-is.
+    ///
+    /// # Errors
+    /// If something already exists at the path, an error variant is returned.
+    pub async fn create_file_at_path<P: AsRef<str>>(
+        &self,
+        path: P,
+        mut metadata: FileMetadata,
+    ) -> Result<MenmosFile> {
+        let path = path.as_ref();
+        let mut components = components(path)?;
+        let name = components.pop().context(InvalidPathSnafu { path })?;
+
+        let (parent_id, parent) = self.resolve_hit(&components.join("/")).await?;
+        if let Some(parent) = parent {
+            ensure!(
+                parent.meta.blob_type == Type::Directory,
+                ExpectedDirectorySnafu { blob_id: parent_id }
+            );
+        }
+
+        ensure!(
+            self.find_child(&parent_id, name, path).await?.is_none(),
+            PathAlreadyExistsSnafu { path }
+        );
+
+        metadata.name = String::from(name);
+        if !metadata.parents.contains(&parent_id) {
+            metadata.parents.push(parent_id);
+        }
+        self.create_file(metadata).await
+    }
+
+    /// Create a directory at the given path, along with its missing parents.
+    ///
+    /// Directories that already exist are left untouched, and the directory at the path
+    /// is returned whether it was created or not.
+    ///
+    /// # Errors
+    /// If one of the components of the path is a file, an error variant is returned.
+    pub async fn create_dir_all<P: AsRef<str>>(&self, path: P) -> Result<MenmosDirectory> {
+        let path = path.as_ref();
+        let mut current_id = self.root_id().await?;
+
+        for name in components(path)? {
+            current_id = match self.find_child(&current_id, name, path).await? {
+                Some(hit) => {
+                    ensure!(
+                        hit.meta.blob_type == Type::Directory,
+                        ExpectedDirectorySnafu { blob_id: hit.id }
+                    );
+                    hit.id
+                }
+                None => {
+                    let dir = self
+                        .create_dir(FileMetadata::new(name).with_parent(&current_id))
+                        .await?;
+                    String::from(dir.id())
+                }
+            };
+        }
+
+        let dir = MenmosDirectory::open(self.backend.clone(), &current_id).await?;
+        Ok(dir
+            .with_page_size(self.defaults.page_size)
+            .with_block_cache(self.cache.clone()))
+    }
+
+    /// Get the path of a blob, from the configured [`FsRoot`].
+    ///
+    /// When a blob has several parents, the path going through the first parent that
+    /// leads to the root is returned.
+    ///
+    /// # Errors
+    /// If the blob can't be reached from the root, an error variant is returned.
+    pub async fn path_of<S: AsRef<str>>(&self, id: S) -> Result<String> {
+        let root_id = self.root_id().await?;
+        let id = id.as_ref();
+
+        // Depth-first search up the parent links, keeping the names along the way.
+        let mut visited = HashSet::new();
+        let mut stack = vec![(String::from(id), Vec::<String>::new())];
+        while let Some((blob_id, names)) = stack.pop() {
+            if blob_id == root_id {
+                let path: Vec<&str> = names.iter().rev().map(|n| n.as_str()).collect();
+                return Ok(format!("/{}", path.join("/")));
+            }
+
+            if !visited.insert(blob_id.clone()) {
+                continue;
+            }
+
+            let meta = match util::get_meta_if_exists(&self.backend, &blob_id)
+                .await
+                .context(PathResolveSnafu)?
+            {
+                Some(meta) => meta,
+                None => continue,
+            };
+
+            // Parents are pushed in reverse, so the first one is explored first.
+            for parent in meta.parents.iter().rev() {
+                let mut names = names.clone();
+                names.push(meta.name.clone());
+                stack.push((parent.clone(), names));
+            }
+        }
+
+        NotUnderRootSnafu {
+            blob_id: String::from(id),
+        }
+        .fail()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::ErrorKind;
+
+    async fn fs_with_root() -> MenmosFs {
+        let fs = MenmosFs::new(Arc::new(MemoryBackend::new()));
+        let root = fs
+            .create_dir(FileMetadata::new("root").with_tag("fs_root"))
+            .await
+            .unwrap();
+        fs.with_root(FsRoot::Id(String::from(root.id())))
+    }
+
+    #[test]
+    fn path_components() {
+        assert_eq!(components("/a/b/c").unwrap(), vec!["a", "b", "c"]);
+        assert_eq!(components("a//b/./c/").unwrap(), vec!["a", "b", "c"]);
+        assert!(components("/").unwrap().is_empty());
+        assert!(components("/a/../b").is_err());
+    }
+
+    #[tokio::test]
+    async fn create_and_resolve_paths() {
+        let fs = fs_with_root().await;
+
+        let dir = fs.create_dir_all("/projects/2024").await.unwrap();
+        let again = fs.create_dir_all("projects/2024/").await.unwrap();
+        assert_eq!(dir.id(), again.id());
+
+        let file = fs
+            .create_file_at_path(
+                "/projects/2024/report.pdf",
+                FileMetadata::default().with_tag("report"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            fs.resolve("/projects/2024/report.pdf").await.unwrap(),
+            file.id()
+        );
+        assert_eq!(
+            fs.path_of(file.id()).await.unwrap(),
+            "/projects/2024/report.pdf"
+        );
+        assert_eq!(fs.path_of(dir.id()).await.unwrap(), "/projects/2024");
+
+        match fs.open_path("/projects/2024/report.pdf").await.unwrap() {
+            DirEntry::File(f) => assert_eq!(f.id(), file.id()),
+            DirEntry::Directory(_) => panic!("expected a file"),
+        }
+        assert!(matches!(
+            fs.open_path("/").await.unwrap(),
+            DirEntry::Directory(_)
+        ));
+
+        let err = fs
+            .create_file_at_path("/projects/2024/report.pdf", FileMetadata::default())
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Conflict);
+
+        let err = fs
+            .create_dir_all("/projects/2024/report.pdf/attachments")
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert!(matches!(err, FsError::ExpectedDirectoryError { .. }));
+    }
+
+    #[tokio::test]
+    async fn missing_and_ambiguous_paths() {
+        let fs = fs_with_root().await;
+        let root_id = fs.resolve("/").await.unwrap();
+
+        let err = fs.resolve("/missing").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+
+        for _ in 0..2 {
+            fs.create_file(FileMetadata::new("dup.txt").with_parent(&root_id))
+                .await
+                .unwrap();
+        }
+        let err = fs.resolve("/dup.txt").await.unwrap_err();
+        assert!(matches!(err, FsError::AmbiguousPathError { count: 2, .. }));
+        assert!(err.to_string().contains("dup.txt"));
+
+        let orphan = fs.create_file(FileMetadata::new("orphan")).await.unwrap();
+        let err = fs.path_of(orphan.id()).await.unwrap_err();
+        assert!(matches!(err, FsError::NotUnderRootError { .. }));
+    }
+
+    #[tokio::test]
+    async fn roots_are_found_by_tag() {
+        let fs = fs_with_root().await;
+        let root_id = fs.resolve("/").await.unwrap();
+
+        let fs = fs.with_root(FsRoot::Tag(String::from("fs_root")));
+        assert_eq!(fs.resolve("/").await.unwrap(), root_id);
+
+        let err = fs
+            .clone()
+            .with_root(FsRoot::Tag(String::from("missing")))
+            .resolve("/")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, FsError::RootNotFoundError { .. }));
+
+        let err = MenmosFs::new(Arc::new(MemoryBackend::new()))
+            .resolve("/")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, FsError::NoRootError));
+    }
+}
@@ -0,0 +1,301 @@
+use bytes::{Bytes, BytesMut};
+
+use futures::{Stream, TryStreamExt};
+
+use snafu::prelude::*;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::error::*;
+use super::file::{make_file_meta, MenmosFile};
+use super::MenmosFs;
+use crate::FileMetadata;
+
+/// The number of bytes sent per request when uploading a file.
+const UPLOAD_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+impl MenmosFs {
+    /// Create a new file with the contents of the provided reader.
+    ///
+    /// The contents are uploaded in chunks as they are read, so they don't need to fit
+    /// in memory or to be written to a local file first.
+    ///
+    /// This function will return a handle to the created file, at offset 0.
+    ///
+    /// # Errors
+    /// If reading from the reader or uploading a chunk fails, the partially uploaded
+    /// file is deleted before the error is returned.
+    ///
+    /// # Examples
+    /// ```
+    /// use menmos::{backend::MemoryBackend, FileMetadata, Menmos};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let client = Menmos::from_backend(MemoryBackend::new());
+    /// let report = b"generated content".to_vec();
+    /// let file = client
+    ///     .fs
+    ///     .create_file_from_reader(FileMetadata::new("report.txt"), report.as_slice())
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub async fn create_file_from_reader<R>(
+        &self,
+        metadata: FileMetadata,
+        reader: R,
+    ) -> Result<MenmosFile>
+    where
+        R: AsyncRead + Send + Unpin,
+    {
+        self.create_file_from_reader_chunked(metadata, reader, UPLOAD_CHUNK_SIZE)
+            .await
+    }
+
+    /// Create a new file with the contents of the provided stream of buffers.
+    ///
+    /// This works like [`MenmosFs::create_file_from_reader`], and can be used to store
+    /// HTTP request bodies and other generated content without a temporary file.
+    ///
+    /// # Errors
+    /// If the stream returns an error or uploading a chunk fails, the partially uploaded
+    /// file is deleted before the error is returned.
+    ///
+    /// # Examples
+    /// ```
+    /// use bytes::Bytes;
+    /// use menmos::{backend::MemoryBackend, FileMetadata, Menmos};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let client = Menmos::from_backend(MemoryBackend::new());
+    /// let body = futures::stream::iter(vec![
+    ///     Ok::<_, std::io::Error>(Bytes::from("hello ")),
+    ///     Ok(Bytes::from("world")),
+    /// ]);
+    /// let file = client
+    ///     .fs
+    ///     .create_file_from_stream(FileMetadata::new("body.txt"), body)
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub async fn create_file_from_stream<S, E>(
+        &self,
+        metadata: FileMetadata,
+        stream: S,
+    ) -> Result<MenmosFile>
+    where
+        S: Stream<Item = std::result::Result<Bytes, E>> + Send,
+        E: Into<BoxError>,
+    {
+        self.create_file_from_stream_chunked(metadata, stream, UPLOAD_CHUNK_SIZE)
+            .await
+    }
+
+    async fn create_file_from_reader_chunked<R>(
+        &self,
+        metadata: FileMetadata,
+        mut reader: R,
+        chunk_size: usize,
+    ) -> Result<MenmosFile>
+    where
+        R: AsyncRead + Send + Unpin,
+    {
+        let stream = async_stream::try_stream! {
+            loop {
+                let mut buf = BytesMut::with_capacity(chunk_size);
+                while buf.len() < chunk_size {
+                    if reader.read_buf(&mut buf).await? == 0 {
+                        break;
+                    }
+                }
+
+                if buf.is_empty() {
+                    break;
+                }
+                yield buf.freeze();
+            }
+        };
+
+        self.create_file_from_stream_chunked::<_, std::io::Error>(metadata, stream, chunk_size)
+            .await
+    }
+
+    pub(super) async fn create_file_from_stream_chunked<S, E>(
+        &self,
+        metadata: FileMetadata,
+        stream: S,
+        chunk_size: usize,
+    ) -> Result<MenmosFile>
+    where
+        S: Stream<Item = std::result::Result<Bytes, E>> + Send,
+        E: Into<BoxError>,
+    {
+        let metadata = self.with_defaults(metadata);
+        let file = MenmosFile::create(self.backend.clone(), metadata.clone())
+            .await?
+            .with_block_cache(self.cache.clone());
+
+        let result = async {
+            let size = upload_chunks(&file, stream, chunk_size).await?;
+
+            let mut meta = make_file_meta(metadata);
+            meta.size = size;
+            self.backend
+                .update_meta(file.id(), meta)
+                .await
+                .context(BlobUpdateSnafu { blob_id: file.id() })
+        }
+        .await;
+
+        if let Err(e) = result {
+            if let Err(delete_err) = self.remove_blob_unchecked(file.id()).await {
+                tracing::warn!(
+                    "failed to delete partially uploaded file '{}': {}",
+                    file.id(),
+                    delete_err
+                );
+            }
+            return Err(e);
+        }
+
+        Ok(file)
+    }
+}
+
+/// Write the contents of a stream to a file in chunks of `chunk_size` bytes.
+///
+/// Returns the number of bytes written.
+async fn upload_chunks<S, E>(file: &MenmosFile, stream: S, chunk_size: usize) -> Result<u64>
+where
+    S: Stream<Item = std::result::Result<Bytes, E>> + Send,
+    E: Into<BoxError>,
+{
+    futures::pin_mut!(stream);
+
+    let mut offset = 0;
+    let mut buffer = BytesMut::new();
+    loop {
+        let next = stream
+            .try_next()
+            .await
+            .map_err(|e| FsError::UploadSourceError { source: e.into() })?;
+
+        let done = match next {
+            Some(data) => {
+                buffer.extend_from_slice(&data);
+                false
+            }
+            None => true,
+        };
+
+        while buffer.len() >= chunk_size || (done && !buffer.is_empty()) {
+            let chunk = buffer.split_to(chunk_size.min(buffer.len()));
+            offset += file.write_at(offset, &chunk).await? as u64;
+        }
+
+        if done {
+            return Ok(offset);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use menmos_client::Query;
+
+    use super::*;
+    use crate::backend::{Backend, MemoryBackend};
+
+    fn content() -> Vec<u8> {
+        (0..10_000_u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    async fn read_back(backend: &MemoryBackend, file: &MenmosFile) -> (u64, Vec<u8>) {
+        let meta = backend.get_meta(file.id()).await.unwrap().unwrap();
+        let data = backend
+            .read_range(file.id(), (0, u64::MAX - 1))
+            .await
+            .unwrap();
+        (meta.size, data)
+    }
+
+    #[tokio::test]
+    async fn uploads_streams_in_chunks() {
+        let backend = MemoryBackend::new();
+        let fs = MenmosFs::new(Arc::new(backend.clone()));
+
+        let content = content();
+        let items: Vec<std::result::Result<Bytes, std::io::Error>> = content
+            .chunks(700)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect();
+
+        let file = fs
+            .create_file_from_stream_chunked(
+                FileMetadata::new("a.bin").with_tag("uploaded"),
+                futures::stream::iter(items),
+                1024,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(read_back(&backend, &file).await, (10_000, content));
+        let meta = backend.get_meta(file.id()).await.unwrap().unwrap();
+        assert_eq!(meta.name, "a.bin");
+        assert_eq!(meta.tags, vec![String::from("uploaded")]);
+    }
+
+    #[tokio::test]
+    async fn uploads_readers_in_chunks() {
+        let backend = MemoryBackend::new();
+        let fs = MenmosFs::new(Arc::new(backend.clone()));
+
+        let content = content();
+        let file = fs
+            .create_file_from_reader_chunked(FileMetadata::new("a.bin"), content.as_slice(), 3000)
+            .await
+            .unwrap();
+        assert_eq!(read_back(&backend, &file).await, (10_000, content));
+
+        let empty = fs
+            .create_file_from_reader(FileMetadata::new("empty.bin"), tokio::io::empty())
+            .await
+            .unwrap();
+        assert_eq!(read_back(&backend, &empty).await, (0, Vec::new()));
+    }
+
+    #[tokio::test]
+    async fn failed_uploads_are_deleted() {
+        let backend = MemoryBackend::new();
+        let fs = MenmosFs::new(Arc::new(backend.clone()));
+
+        let items = vec![
+            Ok(Bytes::from_static(b"partial content")),
+            Err(std::io::Error::new(
+                std::io::ErrorKind::ConnectionReset,
+                "client went away",
+            )),
+        ];
+        let err = fs
+            .create_file_from_stream_chunked(
+                FileMetadata::new("a.bin"),
+                futures::stream::iter(items),
+                4,
+            )
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert!(matches!(err, FsError::UploadSourceError { .. }));
+        assert!(err.to_string().contains("client went away"));
+
+        let remaining = backend.query(Query::default()).await.unwrap();
+        assert_eq!(remaining.total, 0);
+    }
+}
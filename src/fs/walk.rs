@@ -0,0 +1,685 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+
+use async_stream::try_stream;
+
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, Stream, StreamExt, TryStreamExt};
+
+use interface::Hit;
+use menmos_client::{Query, Type};
+
+use snafu::prelude::*;
+
+use super::error::*;
+use super::{DirEntry, MenmosFs};
+use crate::util;
+
+const DEFAULT_CONCURRENCY: usize = 4;
+
+/// The order in which [`MenmosFs::walk`] yields entries.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WalkOrder {
+    /// Yield each directory followed by all of its descendants, before moving on to its siblings.
+    #[default]
+    DepthFirst,
+
+    /// Yield all the entries at one depth before moving on to the next depth.
+    BreadthFirst,
+}
+
+/// An entry found by [`MenmosFs::walk`].
+#[derive(Clone)]
+pub struct WalkEntry {
+    entry: DirEntry,
+    path: String,
+    depth: usize,
+}
+
+impl WalkEntry {
+    /// Returns the ID of the file or directory.
+    pub fn id(&self) -> &str {
+        self.entry.id()
+    }
+
+    /// Returns the name of the file or directory.
+    pub fn name(&self) -> &str {
+        self.entry.name()
+    }
+
+    /// Returns the path of the entry, relative to the walked directory.
+    ///
+    /// Paths are made of the names of the directories leading to the entry, separated by `/`.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the depth of the entry. Children of the walked directory have a depth of 1.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns whether the entry is a directory.
+    pub fn is_dir(&self) -> bool {
+        matches!(self.entry, DirEntry::Directory(_))
+    }
+
+    /// Returns a handle to the file or directory.
+    ///
+    /// The handle carries the metadata the entry was listed with.
+    pub fn entry(&self) -> &DirEntry {
+        &self.entry
+    }
+
+    /// Convert the entry into a handle to the file or directory.
+    pub fn into_entry(self) -> DirEntry {
+        self.entry
+    }
+}
+
+type PrunePredicate = Arc<dyn Fn(&WalkEntry) -> bool + Send + Sync>;
+
+/// Options for [`MenmosFs::walk`].
+#[derive(Clone)]
+pub struct WalkOptions {
+    max_depth: usize,
+    order: WalkOrder,
+    concurrency: usize,
+    prune: Option<PrunePredicate>,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: usize::MAX,
+            order: WalkOrder::default(),
+            concurrency: DEFAULT_CONCURRENCY,
+            prune: None,
+        }
+    }
+}
+
+impl fmt::Debug for WalkOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WalkOptions")
+            .field("max_depth", &self.max_depth)
+            .field("order", &self.order)
+            .field("concurrency", &self.concurrency)
+            .field("prune", &self.prune.is_some())
+            .finish()
+    }
+}
+
+impl WalkOptions {
+    /// Set the maximum depth of the entries yielded. By default, there is no maximum depth.
+    ///
+    /// A maximum depth of 1 only yields the children of the walked directory.
+    #[must_use]
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Set the order in which entries are yielded. The default is [`WalkOrder::DepthFirst`].
+    #[must_use]
+    pub fn with_order(mut self, order: WalkOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Set the number of directories listed concurrently. The default is 4 directories,
+    /// and the minimum is one directory.
+    ///
+    /// Directories are listed ahead of the entries being yielded, but entries are
+    /// always yielded in the configured order.
+    #[must_use]
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Skip the entries for which `prune` returns `true`.
+    ///
+    /// Pruned entries are not yielded, and pruned directories are not walked.
+    #[must_use]
+    pub fn with_prune<F>(mut self, prune: F) -> Self
+    where
+        F: Fn(&WalkEntry) -> bool + Send + Sync + 'static,
+    {
+        self.prune = Some(Arc::new(prune));
+        self
+    }
+}
+
+/// A directory waiting to be walked.
+struct PendingDir {
+    /// The key of the listing of this directory.
+    key: usize,
+    path: String,
+    depth: usize,
+
+    /// The IDs of the directories leading to this directory, including itself.
+    ancestors: Vec<String>,
+}
+
+/// An entry waiting to be yielded.
+struct Pending {
+    entry: Option<WalkEntry>,
+    dir: Option<PendingDir>,
+}
+
+type Listing = (usize, Result<Vec<Hit>>);
+
+/// Lists directories ahead of the walk, up to a fixed number at a time.
+struct Lister {
+    fs: MenmosFs,
+    concurrency: usize,
+
+    /// The directories to list, in the order they will be walked.
+    queue: VecDeque<(usize, String)>,
+    in_flight: FuturesUnordered<BoxFuture<'static, Listing>>,
+    done: HashMap<usize, Result<Vec<Hit>>>,
+}
+
+impl Lister {
+    fn new(fs: MenmosFs, concurrency: usize) -> Self {
+        Self {
+            fs,
+            concurrency,
+            queue: VecDeque::new(),
+            in_flight: FuturesUnordered::new(),
+            done: HashMap::new(),
+        }
+    }
+
+    fn start(&mut self, key: usize, id: String) {
+        let fs = self.fs.clone();
+        self.in_flight.push(
+            async move {
+                let query = Query::default()
+                    .and_parent(&id)
+                    .with_from(0)
+                    .with_size(fs.defaults.page_size);
+                let hits = util::scroll_query(query, &fs.backend)
+                    .try_collect::<Vec<_>>()
+                    .await
+                    .context(DirQuerySnafu);
+                (key, hits)
+            }
+            .boxed(),
+        );
+    }
+
+    /// Start listing queued directories, while there are less than `concurrency` listings
+    /// in flight or waiting to be consumed.
+    fn fill(&mut self) {
+        while self.in_flight.len() + self.done.len() < self.concurrency {
+            match self.queue.pop_front() {
+                Some((key, id)) => self.start(key, id),
+                None => break,
+            }
+        }
+    }
+
+    /// Wait for the listing of a queued directory.
+    ///
+    /// If the directory isn't being listed yet, it is listed as soon as less than `concurrency`
+    /// listings are in flight.
+    async fn take(&mut self, key: usize) -> Result<Vec<Hit>> {
+        if let Some(position) = self.queue.iter().position(|(k, _)| *k == key) {
+            let (key, id) = self
+                .queue
+                .remove(position)
+                .expect("position is in the queue");
+            while self.in_flight.len() >= self.concurrency {
+                let (done_key, listing) =
+                    self.in_flight.next().await.expect("listings are in flight");
+                self.done.insert(done_key, listing);
+            }
+            self.start(key, id);
+        }
+
+        loop {
+            if let Some(listing) = self.done.remove(&key) {
+                self.fill();
+                return listing;
+            }
+
+            let (done_key, listing) = self
+                .in_flight
+                .next()
+                .await
+                .expect("the listing is in flight");
+            self.done.insert(done_key, listing);
+        }
+    }
+}
+
+impl MenmosFs {
+    /// Get a stream of all the files and directories under a directory.
+    ///
+    /// Each entry comes with its depth and its path relative to the walked directory,
+    /// which is not yielded itself.
+    ///
+    /// Blobs linked to several directories are yielded once per path leading to them.
+    /// Directories linking back to one of their ancestors are skipped, so walking a tree
+    /// with cycles always ends.
+    ///
+    /// # Errors
+    /// If `dir_id` is not a directory or a directory can't be listed, the stream returns
+    /// an error variant and ends.
+    ///
+    /// # Examples
+    /// ```
+    /// use futures::TryStreamExt;
+    /// use menmos::{backend::MemoryBackend, fs::WalkOptions, FileMetadata, Menmos};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let client = Menmos::from_backend(MemoryBackend::new());
+    /// let project = client.fs.create_dir(FileMetadata::new("project")).await.unwrap();
+    /// let src = client
+    ///     .fs
+    ///     .create_dir(FileMetadata::new("src").with_parent(project.id()))
+    ///     .await
+    ///     .unwrap();
+    /// client
+    ///     .fs
+    ///     .create_file(FileMetadata::new("main.rs").with_parent(src.id()))
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// let options = WalkOptions::default().with_prune(|entry| entry.name() == "target");
+    /// let mut entries = client.fs.walk(project.id(), options);
+    /// while let Some(entry) = entries.try_next().await.unwrap() {
+    ///     println!("{} (depth {})", entry.path(), entry.depth());
+    /// }
+    /// # }
+    /// ```
+    pub fn walk<S: AsRef<str>>(
+        &self,
+        dir_id: S,
+        options: WalkOptions,
+    ) -> impl Stream<Item = Result<WalkEntry>> + Send + Unpin + 'static {
+        let fs = self.clone();
+        let dir_id = String::from(dir_id.as_ref());
+
+        Box::pin(try_stream! {
+            let meta = fs.blob_meta(&dir_id).await?;
+            if meta.blob_type != Type::Directory {
+                Err(FsError::ExpectedDirectoryError { blob_id: dir_id.clone() })?;
+            }
+
+            let mut lister = Lister::new(fs.clone(), options.concurrency);
+            let mut next_key = 0;
+            let mut pending = VecDeque::new();
+
+            if options.max_depth > 0 {
+                lister.queue.push_back((next_key, dir_id.clone()));
+                pending.push_back(Pending {
+                    entry: None,
+                    dir: Some(PendingDir {
+                        key: next_key,
+                        ancestors: vec![dir_id.clone()],
+                        path: String::new(),
+                        depth: 0,
+                    }),
+                });
+                next_key += 1;
+            }
+
+            while let Some(Pending { entry, dir }) = pending.pop_front() {
+                if let Some(entry) = entry {
+                    yield entry;
+                }
+
+                let dir = match dir {
+                    Some(dir) => dir,
+                    None => continue,
+                };
+
+                let mut children = Vec::new();
+                let mut child_dirs = Vec::new();
+                for hit in lister.take(dir.key).await? {
+                    let is_dir = hit.meta.blob_type == Type::Directory;
+                    if is_dir && dir.ancestors.contains(&hit.id) {
+                        tracing::debug!("skipping cycle to directory '{}' at '{}'", hit.id, dir.path);
+                        continue;
+                    }
+
+                    let path = if dir.path.is_empty() {
+                        hit.meta.name.clone()
+                    } else {
+                        format!("{}/{}", dir.path, hit.meta.name)
+                    };
+                    let entry = WalkEntry {
+                        entry: DirEntry::open_raw(
+                            fs.backend.clone(),
+                            &hit.id,
+                            hit.meta,
+                            fs.defaults.page_size,
+                            fs.cache.clone(),
+                        )?,
+                        path,
+                        depth: dir.depth + 1,
+                    };
+
+                    if let Some(prune) = options.prune.as_ref() {
+                        if prune(&entry) {
+                            continue;
+                        }
+                    }
+
+                    let child_dir = if is_dir && entry.depth < options.max_depth {
+                        let mut ancestors = dir.ancestors.clone();
+                        ancestors.push(hit.id.clone());
+
+                        child_dirs.push((next_key, hit.id.clone()));
+                        let child_dir = PendingDir {
+                            key: next_key,
+                            path: entry.path.clone(),
+                            depth: entry.depth,
+                            ancestors,
+                        };
+                        next_key += 1;
+                        Some(child_dir)
+                    } else {
+                        None
+                    };
+
+                    children.push(Pending {
+                        entry: Some(entry),
+                        dir: child_dir,
+                    });
+                }
+
+                // The listings are queued in the same order as the directories are walked.
+                match options.order {
+                    WalkOrder::DepthFirst => {
+                        for child in children.into_iter().rev() {
+                            pending.push_front(child);
+                        }
+                        for child_dir in child_dirs.into_iter().rev() {
+                            lister.queue.push_front(child_dir);
+                        }
+                    }
+                    WalkOrder::BreadthFirst => {
+                        pending.extend(children);
+                        lister.queue.extend(child_dirs);
+                    }
+                }
+                lister.fill();
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use interface::{BlobMeta, QueryResponse};
+    use menmos_client::Meta;
+
+    use super::*;
+    use crate::backend::{self, Backend, MemoryBackend};
+    use crate::FileMetadata;
+
+    /// Tracks the number of queries running at once.
+    #[derive(Default)]
+    struct SlowBackend {
+        inner: MemoryBackend,
+        running: AtomicUsize,
+        max_running: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Backend for SlowBackend {
+        async fn push(&self, path: &std::path::Path, meta: Meta) -> backend::Result<String> {
+            self.inner.push(path, meta).await
+        }
+
+        async fn create_empty(&self, meta: Meta) -> backend::Result<String> {
+            self.inner.create_empty(meta).await
+        }
+
+        async fn write(&self, blob_id: &str, offset: u64, buffer: Bytes) -> backend::Result<()> {
+            self.inner.write(blob_id, offset, buffer).await
+        }
+
+        async fn read_range(&self, blob_id: &str, range: (u64, u64)) -> backend::Result<Vec<u8>> {
+            self.inner.read_range(blob_id, range).await
+        }
+
+        async fn get_meta(&self, blob_id: &str) -> backend::Result<Option<BlobMeta>> {
+            self.inner.get_meta(blob_id).await
+        }
+
+        async fn query(&self, query: Query) -> backend::Result<QueryResponse> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            let response = self.inner.query(query).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            response
+        }
+
+        async fn delete(&self, blob_id: &str) -> backend::Result<()> {
+            self.inner.delete(blob_id).await
+        }
+
+        async fn update_meta(&self, blob_id: &str, meta: Meta) -> backend::Result<()> {
+            self.inner.update_meta(blob_id, meta).await
+        }
+    }
+
+    /// Create the following tree, returning the ID of `root`.
+    ///
+    /// ```text
+    /// root/
+    ///   a/
+    ///     a1/
+    ///       deep.txt
+    ///     a.txt
+    ///   b/
+    ///     b.txt
+    ///   top.txt
+    /// ```
+    async fn make_tree(fs: &MenmosFs) -> String {
+        let root = fs.create_dir(FileMetadata::new("root")).await.unwrap();
+        let a = fs
+            .create_dir(FileMetadata::new("a").with_parent(root.id()))
+            .await
+            .unwrap();
+        let a1 = fs
+            .create_dir(FileMetadata::new("a1").with_parent(a.id()))
+            .await
+            .unwrap();
+        let b = fs
+            .create_dir(FileMetadata::new("b").with_parent(root.id()))
+            .await
+            .unwrap();
+        for (name, parent) in [
+            ("deep.txt", a1.id()),
+            ("a.txt", a.id()),
+            ("b.txt", b.id()),
+            ("top.txt", root.id()),
+        ] {
+            fs.create_file(FileMetadata::new(name).with_parent(parent))
+                .await
+                .unwrap();
+        }
+        String::from(root.id())
+    }
+
+    async fn walk_paths(fs: &MenmosFs, id: &str, options: WalkOptions) -> Vec<(String, usize)> {
+        fs.walk(id, options)
+            .map_ok(|e| (String::from(e.path()), e.depth()))
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    fn paths(entries: &[(String, usize)]) -> Vec<&str> {
+        entries.iter().map(|(p, _)| p.as_str()).collect()
+    }
+
+    fn is_parent(parent: &str, child: &str) -> bool {
+        child.starts_with(&format!("{}/", parent))
+    }
+
+    #[tokio::test]
+    async fn walk_depth_first() {
+        let fs = MenmosFs::new(Arc::new(MemoryBackend::new()));
+        let root = make_tree(&fs).await;
+
+        let entries = walk_paths(&fs, &root, WalkOptions::default()).await;
+        let mut sorted = paths(&entries);
+        sorted.sort_unstable();
+        assert_eq!(
+            sorted,
+            [
+                "a",
+                "a/a.txt",
+                "a/a1",
+                "a/a1/deep.txt",
+                "b",
+                "b/b.txt",
+                "top.txt"
+            ]
+        );
+        for (path, depth) in entries.iter() {
+            assert_eq!(*depth, path.split('/').count());
+        }
+
+        // Every directory is directly followed by its descendants.
+        let paths = paths(&entries);
+        for (i, path) in paths.iter().enumerate() {
+            let descendants = paths.iter().filter(|p| is_parent(path, p)).count();
+            assert!(paths[i + 1..=i + descendants]
+                .iter()
+                .all(|p| is_parent(path, p)));
+        }
+    }
+
+    #[tokio::test]
+    async fn walk_breadth_first() {
+        let fs = MenmosFs::new(Arc::new(MemoryBackend::new()));
+        let root = make_tree(&fs).await;
+
+        let options = WalkOptions::default().with_order(WalkOrder::BreadthFirst);
+        let entries = walk_paths(&fs, &root, options).await;
+        assert_eq!(entries.len(), 7);
+        assert!(entries.windows(2).all(|w| w[0].1 <= w[1].1));
+    }
+
+    #[tokio::test]
+    async fn walk_with_max_depth_and_prune() {
+        let fs = MenmosFs::new(Arc::new(MemoryBackend::new()));
+        let root = make_tree(&fs).await;
+
+        let entries = walk_paths(&fs, &root, WalkOptions::default().with_max_depth(2)).await;
+        let mut sorted = paths(&entries);
+        sorted.sort_unstable();
+        assert_eq!(sorted, ["a", "a/a.txt", "a/a1", "b", "b/b.txt", "top.txt"]);
+
+        assert!(
+            walk_paths(&fs, &root, WalkOptions::default().with_max_depth(0))
+                .await
+                .is_empty()
+        );
+
+        let options = WalkOptions::default().with_prune(|e| e.is_dir() && e.name() == "a");
+        let entries = walk_paths(&fs, &root, options).await;
+        let mut sorted = paths(&entries);
+        sorted.sort_unstable();
+        assert_eq!(sorted, ["b", "b/b.txt", "top.txt"]);
+    }
+
+    #[tokio::test]
+    async fn walk_skips_cycles() {
+        let fs = MenmosFs::new(Arc::new(MemoryBackend::new()));
+        let root = make_tree(&fs).await;
+
+        // Link `b` into `a/a1`, and `root` into `b`.
+        let a1 = fs
+            .walk(&root, WalkOptions::default())
+            .try_filter(|e| futures::future::ready(e.path() == "a/a1"))
+            .try_next()
+            .await
+            .unwrap()
+            .unwrap();
+        let b = fs
+            .walk(&root, WalkOptions::default().with_max_depth(1))
+            .try_filter(|e| futures::future::ready(e.path() == "b"))
+            .try_next()
+            .await
+            .unwrap()
+            .unwrap();
+        fs.link(b.id(), a1.id()).await.unwrap();
+        let mut meta = fs.blob_meta(&root).await.unwrap();
+        meta.parents.push(String::from(b.id()));
+        fs.replace_meta(&root, meta).await.unwrap();
+
+        let entries = walk_paths(&fs, &root, WalkOptions::default()).await;
+        let mut sorted = paths(&entries);
+        sorted.sort_unstable();
+        assert_eq!(
+            sorted,
+            [
+                "a",
+                "a/a.txt",
+                "a/a1",
+                "a/a1/b",
+                "a/a1/b/b.txt",
+                "a/a1/deep.txt",
+                "b",
+                "b/b.txt",
+                "top.txt"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn walk_lists_directories_concurrently() {
+        let backend = Arc::new(SlowBackend::default());
+        let fs = MenmosFs::new(backend.clone());
+
+        let root = fs.create_dir(FileMetadata::new("root")).await.unwrap();
+        for i in 0..8 {
+            fs.create_dir(FileMetadata::new(format!("dir{}", i)).with_parent(root.id()))
+                .await
+                .unwrap();
+        }
+
+        for order in [WalkOrder::DepthFirst, WalkOrder::BreadthFirst] {
+            backend.max_running.store(0, Ordering::SeqCst);
+            let options = WalkOptions::default().with_order(order).with_concurrency(3);
+            assert_eq!(walk_paths(&fs, root.id(), options).await.len(), 8);
+            assert_eq!(backend.max_running.load(Ordering::SeqCst), 3);
+        }
+
+        backend.max_running.store(0, Ordering::SeqCst);
+        let options = WalkOptions::default().with_concurrency(1);
+        walk_paths(&fs, root.id(), options).await;
+        assert_eq!(backend.max_running.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn walk_requires_a_directory() {
+        let fs = MenmosFs::new(Arc::new(MemoryBackend::new()));
+        let file = fs.create_file(FileMetadata::new("f")).await.unwrap();
+
+        let err = fs
+            .walk(file.id(), WalkOptions::default())
+            .try_next()
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert!(matches!(err, FsError::ExpectedDirectoryError { .. }));
+    }
+}
@@ -1,17 +1,25 @@
+pub mod backend;
+mod credential;
+mod error;
 pub mod fs;
 mod metadata_detector;
 mod profile;
 pub mod push;
+#[cfg(feature = "testing")]
+pub mod testing;
 mod typing;
 mod util;
 
+pub use credential::{Credential, Secret};
+pub use error::{CredentialError, ErrorKind, ProfileError, RequestError};
 pub use menmos_client::Query;
-pub use profile::{Config, Profile};
+pub use profile::{ClientSettings, Config, Profile};
 pub use typing::{FileMetadata, UploadRequest};
 
 use metadata_detector::{MetadataDetector, MetadataDetectorRC};
 use typing::*;
 
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time;
 
@@ -24,57 +32,66 @@ pub use interface;
 
 use menmos_client::{Client, Type};
 
+use backend::Backend;
+
 use snafu::prelude::*;
 
 #[derive(Debug, Snafu)]
 pub enum MenmosError {
-    ConfigLoad {
-        source: error::ProfileError,
-    },
+    #[snafu(display("failed to load profile: {}", source))]
+    ConfigLoad { source: error::ProfileError },
 
+    /// Kept for compatibility, this is no longer returned: a missing profile is reported as
+    /// [`MenmosError::ConfigLoad`].
     #[snafu(display("profile '{}' does not exist", profile))]
-    ProfileLoad {
-        profile: String,
-    },
+    ProfileLoad { profile: String },
 
-    // TODO: add source: ClientError once its exposed in menmos-client >= 0.1.0
-    #[snafu(display("failed to build client"))]
-    ClientBuild,
+    #[snafu(display("failed to get credentials: {}", source))]
+    Credential { source: error::CredentialError },
 
-    FilePush {
-        source: error::PushError,
-    },
+    #[snafu(display("failed to build client: {}", source))]
+    ClientBuild { source: RequestError },
 
-    DirectoryRead {
-        source: std::io::Error,
-    },
+    #[snafu(context(false), display("{}", source))]
+    FilePush { source: error::PushError },
 
-    Query {
-        source: util::UtilError,
-    },
-}
+    #[snafu(context(false), display("{}", source))]
+    Fs { source: error::FsError },
 
-type Result<T> = std::result::Result<T, MenmosError>;
+    #[snafu(display("failed to read directory: {}", source))]
+    DirectoryRead { source: std::io::Error },
 
-mod error {
-    pub use super::MenmosError;
-    pub use crate::fs::FsError;
-    pub use crate::metadata_detector::MetadataDetectorError;
-    pub use crate::profile::ProfileError;
-    pub use crate::push::PushError;
+    #[snafu(display("{}", source))]
+    Query { source: util::UtilError },
 }
 
-fn load_profile_from_config(profile: &str) -> Result<Profile> {
-    let config = Config::load().context(ConfigLoadSnafu)?;
-    config
-        .profiles
-        .get(profile)
-        .cloned()
-        .context(ProfileLoadSnafu {
-            profile: String::from(profile),
-        })
+impl MenmosError {
+    /// Get the kind of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            MenmosError::ConfigLoad { source } => source.kind(),
+            MenmosError::ProfileLoad { .. } => ErrorKind::NotFound,
+            MenmosError::Credential { source } => source.kind(),
+            MenmosError::ClientBuild { source } => source.kind(),
+            MenmosError::FilePush { source } => source.kind(),
+            MenmosError::Fs { source } => source.kind(),
+            MenmosError::DirectoryRead { source } => match source.kind() {
+                std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+                std::io::ErrorKind::PermissionDenied => ErrorKind::Unauthorized,
+                _ => ErrorKind::Other,
+            },
+            MenmosError::Query { source } => source.kind(),
+        }
+    }
+
+    /// Whether the operation that failed with this error could succeed if attempted again.
+    pub fn is_retryable(&self) -> bool {
+        self.kind().is_retryable()
+    }
 }
 
+type Result<T> = std::result::Result<T, MenmosError>;
+
 /// The menmos client.
 #[derive(Clone)]
 pub struct Menmos {
@@ -83,51 +100,139 @@ pub struct Menmos {
     /// This interface should be used when manipulating concepts that are similar to files and folders.
     pub fs: fs::MenmosFs,
 
-    client: ClientRC,
+    backend: BackendRC,
+
+    client: Option<Arc<Client>>,
 
     metadata_detector: MetadataDetectorRC,
+
+    defaults: DefaultsRC,
+
+    chunked_upload: Option<push::ChunkedUpload>,
 }
 
 impl Menmos {
-    fn new_with_client(client: Client) -> Self {
-        let client_rc = Arc::new(client);
-        let fs = fs::MenmosFs::new(client_rc.clone());
+    fn new_with_client(client: Client, defaults: Defaults) -> Self {
+        let client = Arc::new(client);
+        let mut menmos = Self::new_with_backend(client.clone(), defaults);
+        menmos.client = Some(client);
+        menmos
+    }
+
+    fn new_with_backend(backend: BackendRC, defaults: Defaults) -> Self {
+        let defaults = Arc::new(defaults);
+        let fs = fs::MenmosFs::new_with_defaults(backend.clone(), defaults.clone());
 
         // If this fails we shipped a bad library.
         let metadata_detector = Arc::new(MetadataDetector::new().unwrap());
 
         Self {
             fs,
-            client: client_rc,
+            backend,
+            client: None,
             metadata_detector,
+            defaults,
+            chunked_upload: None,
         }
     }
 
-    pub async fn new(profile: &str) -> Result<Self> {
-        let profile = load_profile_from_config(profile)?;
-        let client = Client::builder()
-            .with_host(profile.host)
-            .with_username(profile.username)
-            .with_password(profile.password)
-            .build()
-            .await
-            .map_err(|_| MenmosError::ClientBuild)?;
-        Ok(Self::new_with_client(client))
+    /// Create a client running on top of the provided backend.
+    ///
+    /// This is mostly useful for testing applications against a
+    /// [`MemoryBackend`](backend::MemoryBackend) instead of a live cluster.
+    pub fn from_backend<B: Backend + 'static>(backend: B) -> Self {
+        Self::new_with_backend(Arc::new(backend), Defaults::default())
+    }
+
+    /// Create a client using the named profile.
+    ///
+    /// When `None` is passed, the profile is selected from the environment, falling back
+    /// to the default profile of the config file. See [`Menmos::from_env`].
+    ///
+    /// Environment overrides are applied as described in [`Menmos::from_env`].
+    pub async fn new<'a, P: Into<Option<&'a str>>>(profile: P) -> Result<Self> {
+        Self::builder(profile).build().await
+    }
+
+    /// Create a client from the process environment.
+    ///
+    /// The profile named by `MENMOS_PROFILE` is used if set. Otherwise, if `MENMOS_HOST`,
+    /// `MENMOS_USERNAME` and `MENMOS_PASSWORD` are all set, they are used as inline credentials.
+    /// If neither is set, the default profile of the config file is used.
+    ///
+    /// Profiles are read from the file at `MENMOS_CONFIG` when set, and `MENMOS_HOST`,
+    /// `MENMOS_USERNAME` and `MENMOS_PASSWORD` override the fields of a configured profile when present.
+    pub async fn from_env() -> Result<Self> {
+        MenmosBuilder::new(ProfileSource::Named(None)).build().await
     }
 
     /// Get a builder to configure the client.
-    pub fn builder(profile: &str) -> MenmosBuilder {
-        MenmosBuilder::new(profile.into())
+    ///
+    /// Profiles are selected the same way as in [`Menmos::new`].
+    pub fn builder<'a, P: Into<Option<&'a str>>>(profile: P) -> MenmosBuilder {
+        MenmosBuilder::new(ProfileSource::Named(profile.into().map(String::from)))
+    }
+
+    /// Read files through the provided block cache.
+    ///
+    /// The cache is shared by all the file handles of this client and its clones.
+    /// See [`BlockCache`](fs::BlockCache).
+    #[must_use]
+    pub fn with_block_cache(mut self, cache: fs::BlockCache) -> Self {
+        self.fs = self.fs.with_block_cache(Arc::new(cache));
+        self
+    }
+
+    /// Upload files in resumable chunks when pushing them.
+    ///
+    /// See [`ChunkedUpload`](push::ChunkedUpload).
+    #[must_use]
+    pub fn with_chunked_upload(mut self, settings: push::ChunkedUpload) -> Self {
+        self.chunked_upload = Some(settings);
+        self
+    }
+
+    /// Resolve the paths used by the filesystem interface from the provided root.
+    ///
+    /// See [`MenmosFs::resolve`](fs::MenmosFs::resolve).
+    #[must_use]
+    pub fn with_fs_root(mut self, root: fs::FsRoot) -> Self {
+        self.fs = self.fs.with_root(root);
+        self
+    }
+
+    /// Get the block cache of this client, if one was configured.
+    pub fn block_cache(&self) -> Option<&fs::BlockCache> {
+        self.fs.block_cache()
     }
 
     /// Get a reference to the internal low-level menmos client.
+    ///
+    /// # Panics
+    /// Panics if this client was created with [`Menmos::from_backend`]. Use [`Menmos::try_client`]
+    /// when that can be the case.
     pub fn client(&self) -> &Client {
-        self.client.as_ref()
+        self.try_client()
+            .expect("this menmos client was created without a low-level client")
+    }
+
+    /// Get a reference to the internal low-level menmos client.
+    ///
+    /// Returns `None` if this client was created with [`Menmos::from_backend`].
+    pub fn try_client(&self) -> Option<&Client> {
+        self.client.as_deref()
+    }
+
+    /// Get a reference to the backend used by this client.
+    pub fn backend(&self) -> &dyn Backend {
+        self.backend.as_ref()
     }
 
     /// Get a stream of results for a given query.
+    ///
+    /// Hits can be turned into file and directory handles with [`MenmosFs::open_hit`](fs::MenmosFs::open_hit).
     pub fn query(&self, query: Query) -> impl TryStream<Ok = Hit, Error = MenmosError> + Unpin {
-        util::scroll_query(query, &self.client).map_err(|e| MenmosError::Query { source: e })
+        util::scroll_query(query, &self.backend).map_err(|source| MenmosError::Query { source })
     }
 
     /// Recursively push a sequence of files and/or directories to the menmos cluster.
@@ -135,8 +240,10 @@ impl Menmos {
         &self,
         requests: Vec<UploadRequest>,
     ) -> impl TryStream<Ok = push::PushResult, Error = MenmosError> + Unpin {
-        let client = self.client.clone();
+        let backend = self.backend.clone();
         let metadata_detector = self.metadata_detector.clone();
+        let defaults = self.defaults.clone();
+        let chunked_upload = self.chunked_upload.clone();
 
         Box::pin(try_stream! {
             let mut working_stack = Vec::new();
@@ -146,19 +253,27 @@ impl Menmos {
                 if upload_request.path.is_file() {
                     let source_path = upload_request.path.clone();
                     let parent_id = upload_request.parent_id.clone();
-                    let blob_id = push::push_file(client.clone(), &metadata_detector, Type::File, upload_request).await.map_err(|e| MenmosError::FilePush{source: e})?;
+                    let blob_id = match chunked_upload.as_ref() {
+                        Some(settings) => push::push_file_chunked(backend.clone(), &metadata_detector, &defaults, upload_request, settings).await?,
+                        None => push::push_file(backend.clone(), &metadata_detector, &defaults, Type::File, upload_request).await?,
+                    };
                     yield push::PushResult{source_path, blob_id, parent_id};
                 } else {
                     let directory_id: String = push::push_file(
-                        client.clone(),
+                        backend.clone(),
                         &metadata_detector,
+                        &defaults,
                         Type::Directory,
                         upload_request.clone()                    )
-                    .await.context(FilePushSnafu)?;
+                    .await?;
 
                     // Add this directory's children to the working stack.
                     let read_dir_result: Result<std::fs::ReadDir> = upload_request.path.read_dir().map_err(|e| MenmosError::DirectoryRead{source: e});
                     for child in read_dir_result?.filter_map(|f| f.ok()) {
+                        if push::is_checkpoint(&child.path()) {
+                            continue;
+                        }
+
                         let mut req_clone = upload_request.clone();
                         req_clone.path = child.path().clone();
                         req_clone.parent_id = Some(directory_id.clone());
@@ -170,23 +285,50 @@ impl Menmos {
     }
 }
 
+enum ProfileSource {
+    Named(Option<String>),
+    Explicit(Profile),
+}
+
 pub struct MenmosBuilder {
-    profile: String,
+    profile: ProfileSource,
+    config_path: Option<PathBuf>,
     request_timeout: Option<time::Duration>,
     max_retry_count: Option<usize>,
     retry_interval: Option<time::Duration>,
+    block_cache: Option<fs::BlockCache>,
+    chunked_upload: Option<push::ChunkedUpload>,
+    fs_root: Option<fs::FsRoot>,
 }
 
 impl MenmosBuilder {
-    pub(crate) fn new(profile: String) -> Self {
+    fn new(profile: ProfileSource) -> Self {
         Self {
             profile,
+            config_path: None,
             request_timeout: None,
             max_retry_count: None,
             retry_interval: None,
+            block_cache: None,
+            chunked_upload: None,
+            fs_root: None,
         }
     }
 
+    /// Get a builder that connects using the provided profile.
+    ///
+    /// The profile is used as-is: no config file is read and no environment overrides are applied.
+    pub fn from_profile(profile: Profile) -> Self {
+        Self::new(ProfileSource::Explicit(profile))
+    }
+
+    /// Read named profiles from the config file at `path` instead of the default location.
+    #[must_use]
+    pub fn with_config_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.config_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
     #[must_use]
     pub fn with_request_timeout(mut self, request_timeout: time::Duration) -> Self {
         self.request_timeout = Some(request_timeout);
@@ -205,30 +347,81 @@ impl MenmosBuilder {
         self
     }
 
+    /// Read files through the provided block cache. See [`Menmos::with_block_cache`].
+    #[must_use]
+    pub fn with_block_cache(mut self, cache: fs::BlockCache) -> Self {
+        self.block_cache = Some(cache);
+        self
+    }
+
+    /// Upload files in resumable chunks when pushing them. See [`Menmos::with_chunked_upload`].
+    #[must_use]
+    pub fn with_chunked_upload(mut self, settings: push::ChunkedUpload) -> Self {
+        self.chunked_upload = Some(settings);
+        self
+    }
+
+    /// Resolve the paths used by the filesystem interface from the provided root.
+    /// See [`Menmos::with_fs_root`].
+    #[must_use]
+    pub fn with_fs_root(mut self, root: fs::FsRoot) -> Self {
+        self.fs_root = Some(root);
+        self
+    }
+
     pub async fn build(self) -> Result<Menmos> {
-        let profile = load_profile_from_config(&self.profile)?;
+        let profile = match self.profile {
+            ProfileSource::Named(name) => {
+                profile::resolve_profile(name.as_deref(), self.config_path.as_deref())
+                    .context(ConfigLoadSnafu)?
+            }
+            ProfileSource::Explicit(profile) => profile,
+        };
         let mut builder = Client::builder()
             .with_host(profile.host)
             .with_username(profile.username)
-            .with_password(profile.password);
-
-        if let Some(request_timeout) = self.request_timeout {
+            .with_password(
+                profile
+                    .credential
+                    .resolve()
+                    .await
+                    .context(CredentialSnafu)?
+                    .expose(),
+            );
+
+        // Explicit builder settings take precedence over the ones stored in the profile.
+        let settings = &profile.settings;
+
+        if let Some(request_timeout) = self.request_timeout.or_else(|| settings.request_timeout()) {
             builder = builder.with_request_timeout(request_timeout);
         }
 
-        if let Some(max_retry_count) = self.max_retry_count {
+        if let Some(max_retry_count) = self.max_retry_count.or(settings.max_retry_count) {
             builder = builder.with_max_retry_count(max_retry_count);
         }
 
-        if let Some(retry_interval) = self.retry_interval {
+        if let Some(retry_interval) = self.retry_interval.or_else(|| settings.retry_interval()) {
             builder = builder.with_retry_interval(retry_interval);
         }
 
         let client = builder
             .build()
             .await
-            .map_err(|_| MenmosError::ClientBuild)?;
+            .map_err(RequestError::from_client)
+            .context(ClientBuildSnafu)?;
+
+        let mut menmos =
+            Menmos::new_with_client(client, Defaults::from_settings(&profile.settings));
+        if let Some(cache) = self.block_cache {
+            menmos = menmos.with_block_cache(cache);
+        }
+        if let Some(settings) = self.chunked_upload {
+            menmos = menmos.with_chunked_upload(settings);
+        }
+        if let Some(root) = self.fs_root {
+            menmos = menmos.with_fs_root(root);
+        }
 
-        Ok(Menmos::new_with_client(client))
+        Ok(menmos)
     }
 }
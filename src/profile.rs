@@ -1,14 +1,42 @@
 use std::collections::HashMap;
 use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
 use snafu::prelude::*;
 
+use crate::credential::Credential;
+use crate::error::ErrorKind;
+
 pub const CONFIG_DIR_NAME: &str = "menmos";
 
+/// The version of the config file layout written by this SDK.
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Config files without a `version` key predate versioning.
+const UNVERSIONED_CONFIG_VERSION: u32 = 1;
+
+/// Migrations upgrading a config from the version at their index + 1 to the next one.
+const MIGRATIONS: [fn(&mut toml::value::Table); (CONFIG_VERSION - 1) as usize] = [migrate_v1_to_v2];
+
+/// Environment variable holding the name of the profile to use.
+pub const ENV_PROFILE: &str = "MENMOS_PROFILE";
+
+/// Environment variable holding the path of the config file to read profiles from.
+pub const ENV_CONFIG: &str = "MENMOS_CONFIG";
+
+/// Environment variable holding the cluster host.
+pub const ENV_HOST: &str = "MENMOS_HOST";
+
+/// Environment variable holding the cluster username.
+pub const ENV_USERNAME: &str = "MENMOS_USERNAME";
+
+/// Environment variable holding the cluster password.
+pub const ENV_PASSWORD: &str = "MENMOS_PASSWORD";
+
 #[derive(Debug, Snafu)]
 pub enum ProfileError {
     #[snafu(display("couldn't find config directory"))]
@@ -28,11 +56,65 @@ pub enum ProfileError {
 
     #[snafu(display("failed to serialize config: {}", source))]
     ConfigSerializeError { source: toml::ser::Error },
+
+    #[snafu(display("invalid config version: {}", version))]
+    InvalidConfigVersion { version: toml::Value },
+
+    #[snafu(display(
+        "config version {} is newer than the latest version supported by this SDK ({}), please upgrade",
+        version,
+        CONFIG_VERSION
+    ))]
+    UnsupportedConfigVersion { version: u32 },
+
+    #[snafu(display("failed to back up config before migrating it: {}", source))]
+    ConfigBackupError { source: std::io::Error },
+
+    #[snafu(display("profile '{}' does not exist", profile))]
+    MissingProfile { profile: String },
+
+    #[snafu(display("profile '{}' already exists", profile))]
+    ProfileAlreadyExists { profile: String },
+
+    #[snafu(display(
+        "no profile selected: set {}, set all of {}, {} and {}, or set a default profile",
+        ENV_PROFILE,
+        ENV_HOST,
+        ENV_USERNAME,
+        ENV_PASSWORD
+    ))]
+    NoProfileSelected,
+}
+
+impl ProfileError {
+    /// Get the kind of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ProfileError::MissingProfile { .. } => ErrorKind::NotFound,
+            ProfileError::ProfileAlreadyExists { .. } => ErrorKind::Conflict,
+            ProfileError::NoProfileSelected
+            | ProfileError::InvalidConfigVersion { .. }
+            | ProfileError::UnsupportedConfigVersion { .. } => ErrorKind::InvalidInput,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.kind().is_retryable()
+    }
 }
 
 type Result<T> = std::result::Result<T, ProfileError>;
 
 fn get_config_path() -> Result<PathBuf> {
+    get_config_path_with(|key| std::env::var(key).ok())
+}
+
+fn get_config_path_with<F: Fn(&str) -> Option<String>>(env: F) -> Result<PathBuf> {
+    if let Some(path) = env(ENV_CONFIG) {
+        return Ok(PathBuf::from(path));
+    }
+
     let root_config_dir = dirs::config_dir().context(MissingConfigDirectorySnafu)?;
 
     let cfg_dir_path = root_config_dir.join(CONFIG_DIR_NAME);
@@ -48,37 +130,817 @@ fn get_config_path() -> Result<PathBuf> {
 pub struct Profile {
     pub host: String,
     pub username: String,
-    pub password: String,
+
+    /// Where the password for this profile comes from.
+    #[serde(flatten)]
+    pub credential: Credential,
+
+    /// Client tuning for this profile.
+    #[serde(default, skip_serializing_if = "ClientSettings::is_empty")]
+    pub settings: ClientSettings,
+}
+
+impl Profile {
+    pub fn new<H: Into<String>, U: Into<String>>(
+        host: H,
+        username: U,
+        credential: Credential,
+    ) -> Self {
+        Self {
+            host: host.into(),
+            username: username.into(),
+            credential,
+            settings: ClientSettings::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_settings(mut self, settings: ClientSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+}
+
+/// Optional client settings stored with a profile.
+///
+/// These are applied automatically by [`MenmosBuilder::build`](crate::MenmosBuilder::build).
+/// Values set explicitly on the builder take precedence.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ClientSettings {
+    /// The timeout of a single request, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_timeout_ms: Option<u64>,
+
+    /// The maximum number of times a failed request is attempted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_retry_count: Option<usize>,
+
+    /// The delay between two attempts of a failed request, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_interval_ms: Option<u64>,
+
+    /// The number of results to fetch per page when listing directories.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query_page_size: Option<usize>,
+
+    /// Tags added to every blob created by the SDK.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub default_tags: Vec<String>,
+
+    /// Key/value pairs added to every blob created by the SDK, unless the key is already set.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub default_metadata: HashMap<String, String>,
+}
+
+impl ClientSettings {
+    fn is_empty(&self) -> bool {
+        self == &ClientSettings::default()
+    }
+
+    pub fn request_timeout(&self) -> Option<Duration> {
+        self.request_timeout_ms.map(Duration::from_millis)
+    }
+
+    pub fn retry_interval(&self) -> Option<Duration> {
+        self.retry_interval_ms.map(Duration::from_millis)
+    }
 }
 
 /// A client configuration, as stored on disk.
-#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Config {
+    /// The version of the layout of this config.
+    version: u32,
+
+    /// The name of the profile to use when none is specified.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default: Option<String>,
+
     /// The configuration profiles set by the user.
     pub profiles: HashMap<String, Profile>,
+
+    /// The file this config was loaded from, if any.
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            default: None,
+            profiles: HashMap::new(),
+            path: None,
+        }
+    }
 }
 
 impl Config {
+    /// Load the configuration from disk.
+    ///
+    /// The file at `MENMOS_CONFIG` is used if that variable is set,
+    /// otherwise the config is read from the user's config directory.
     pub fn load() -> Result<Self> {
-        let config_file = get_config_path()?;
+        Self::load_from(get_config_path()?)
+    }
 
-        let cfg = if config_file.exists() {
+    /// Load the configuration from the file at `path`.
+    ///
+    /// A missing file is treated as an empty configuration.
+    /// Changes made to this config will be written back to that same file.
+    ///
+    /// Files written by older versions of the SDK are upgraded in place, after their
+    /// original contents are copied to a `.bak` file next to them, which is only readable by
+    /// its owner on Unix. Backups from earlier upgrades are kept, and the new one is written to
+    /// `.bak.1`, `.bak.2`, and so on. If the file can't be
+    /// written, e.g. on a read-only mount, the upgraded configuration is only kept in memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file was written by a newer version of the SDK.
+    pub fn load_from<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let config_file = path.as_ref();
+
+        let mut cfg: Config = if config_file.exists() {
             let buf = fs::read(config_file).context(ConfigReadSnafu)?;
-            toml::from_slice(&buf).context(ConfigDeserializeSnafu)?
+            let mut table: toml::value::Table =
+                toml::from_slice(&buf).context(ConfigDeserializeSnafu)?;
+
+            let version = config_version(&table)?;
+            ensure!(
+                version <= CONFIG_VERSION,
+                UnsupportedConfigVersionSnafu { version }
+            );
+
+            if version < CONFIG_VERSION {
+                migrate(&mut table, version);
+                let cfg: Config = toml::Value::Table(table)
+                    .try_into()
+                    .context(ConfigDeserializeSnafu)?;
+
+                // The file is left untouched if it can't be backed up first.
+                if let Err(e) = write_backup(config_file, &buf).context(ConfigBackupSnafu) {
+                    tracing::warn!("not upgrading config file '{:?}': {}", config_file, e);
+                } else if let Err(e) = cfg.save_to(config_file) {
+                    tracing::warn!("failed to upgrade config file '{:?}': {}", config_file, e);
+                }
+                cfg
+            } else {
+                toml::Value::Table(table)
+                    .try_into()
+                    .context(ConfigDeserializeSnafu)?
+            }
         } else {
             Config::default()
         };
+        cfg.path = Some(config_file.to_path_buf());
 
         Ok(cfg)
     }
 
+    /// Write the configuration to the file at `path`.
+    ///
+    /// The configuration is first written to a temporary file in the same directory,
+    /// which is then renamed over `path`. This way, a crash never leaves a truncated config behind.
+    /// On Unix, the file is only readable by its owner.
+    pub fn save_to<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let encoded = toml::to_vec(&self).context(ConfigSerializeSnafu)?;
+
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        if !parent.exists() {
+            fs::create_dir_all(parent).context(ConfigDirectoryCreateSnafu)?;
+        }
+
+        private_temp_file(parent, &encoded)
+            .context(ConfigWriteSnafu)?
+            .persist(path)
+            .map_err(|e| ProfileError::ConfigWriteError { source: e.error })?;
+
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        let config_file = match &self.path {
+            Some(path) => path.clone(),
+            None => get_config_path()?,
+        };
+        self.save_to(config_file)
+    }
+
+    /// Get a profile by name.
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.profiles.get(name)
+    }
+
+    /// Add a profile and persist the configuration.
+    ///
+    /// The configuration is written to the file it was loaded from,
+    /// or to the default config location if it wasn't loaded from a file.
     pub fn add<S: Into<String>>(&mut self, name: S, profile: Profile) -> Result<()> {
         self.profiles.insert(name.into(), profile);
+        self.save()
+    }
 
-        let config_file = get_config_path()?;
-        let encoded = toml::to_vec(&self).context(ConfigSerializeSnafu)?;
-        let mut f = fs::File::create(config_file).context(ConfigWriteSnafu)?;
-        f.write_all(&encoded).context(ConfigWriteSnafu)?;
-        Ok(())
+    /// Remove a profile and persist the configuration.
+    ///
+    /// If the removed profile was the default profile, no profile will be the default afterwards.
+    pub fn remove(&mut self, name: &str) -> Result<Profile> {
+        let profile = self
+            .profiles
+            .remove(name)
+            .context(MissingProfileSnafu { profile: name })?;
+
+        if self.default.as_deref() == Some(name) {
+            self.default = None;
+        }
+
+        self.save()?;
+        Ok(profile)
+    }
+
+    /// Rename a profile and persist the configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `from` does not exist or if a profile named `to` already exists.
+    pub fn rename<S: Into<String>>(&mut self, from: &str, to: S) -> Result<()> {
+        let to = to.into();
+        ensure!(
+            !self.profiles.contains_key(&to),
+            ProfileAlreadyExistsSnafu { profile: to }
+        );
+
+        let profile = self
+            .profiles
+            .remove(from)
+            .context(MissingProfileSnafu { profile: from })?;
+
+        if self.default.as_deref() == Some(from) {
+            self.default = Some(to.clone());
+        }
+
+        self.profiles.insert(to, profile);
+        self.save()
+    }
+
+    /// Set the profile to use when none is specified, and persist the configuration.
+    pub fn set_default<S: Into<String>>(&mut self, name: S) -> Result<()> {
+        let name = name.into();
+        ensure!(
+            self.profiles.contains_key(&name),
+            MissingProfileSnafu { profile: name }
+        );
+
+        self.default = Some(name);
+        self.save()
+    }
+
+    /// Get the name of the profile to use when none is specified.
+    pub fn default_profile_name(&self) -> Option<&str> {
+        self.default.as_deref()
+    }
+
+    /// Get the profile to use when none is specified.
+    pub fn default_profile(&self) -> Option<&Profile> {
+        self.default
+            .as_ref()
+            .and_then(|name| self.profiles.get(name))
+    }
+}
+
+fn config_version(table: &toml::value::Table) -> Result<u32> {
+    match table.get("version") {
+        None => Ok(UNVERSIONED_CONFIG_VERSION),
+        Some(toml::Value::Integer(v)) if *v >= 1 && *v <= u32::MAX as i64 => Ok(*v as u32),
+        Some(other) => InvalidConfigVersionSnafu {
+            version: other.clone(),
+        }
+        .fail(),
+    }
+}
+
+/// Run the migrations needed to bring a config from `version` to [`CONFIG_VERSION`].
+fn migrate(table: &mut toml::value::Table, version: u32) {
+    for migration in MIGRATIONS.iter().skip((version - 1) as usize) {
+        migration(table);
+    }
+    table.insert(
+        String::from("version"),
+        toml::Value::Integer(CONFIG_VERSION as i64),
+    );
+}
+
+/// Version 2 introduced the `version` key and the default profile.
+/// Version 1 files have no default profile and may lack the `profiles` table when empty.
+fn migrate_v1_to_v2(table: &mut toml::value::Table) {
+    table
+        .entry("profiles")
+        .or_insert_with(|| toml::Value::Table(Default::default()));
+}
+
+/// Get the path of the `n`th backup of a config file: `.bak` first, then `.bak.1`, `.bak.2`...
+fn backup_path(config_file: &Path, n: usize) -> PathBuf {
+    let mut file_name = config_file
+        .file_name()
+        .map(|n| n.to_os_string())
+        .unwrap_or_default();
+    file_name.push(".bak");
+    if n > 0 {
+        file_name.push(format!(".{}", n));
+    }
+    config_file.with_file_name(file_name)
+}
+
+/// Back up the contents of a config file before migrating it.
+///
+/// Backups from earlier migrations are kept, the new one goes to the first free backup path.
+fn write_backup(config_file: &Path, contents: &[u8]) -> io::Result<PathBuf> {
+    let parent = match config_file.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let mut file = private_temp_file(parent, contents)?;
+    for n in 0.. {
+        let path = backup_path(config_file, n);
+        match file.persist_noclobber(&path) {
+            Ok(_) => return Ok(path),
+            Err(e) if e.error.kind() == io::ErrorKind::AlreadyExists => file = e.file,
+            Err(e) => return Err(e.error),
+        }
+    }
+    unreachable!("there is always a free backup path")
+}
+
+/// Write `contents` to a temporary file in `dir`, readable only by its owner on Unix.
+///
+/// The file is synced, so it can be renamed over the real one without risking a truncated file.
+fn private_temp_file(dir: &Path, contents: &[u8]) -> io::Result<tempfile::NamedTempFile> {
+    let mut f = tempfile::NamedTempFile::new_in(dir)?;
+    set_owner_only_permissions(f.as_file())?;
+    f.write_all(contents)?;
+    f.as_file().sync_all()?;
+    Ok(f)
+}
+
+#[cfg(unix)]
+fn set_owner_only_permissions(file: &fs::File) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(fs::Permissions::from_mode(0o600))
+}
+
+#[cfg(not(unix))]
+fn set_owner_only_permissions(_file: &fs::File) -> io::Result<()> {
+    Ok(())
+}
+
+/// Resolve the profile to use from the process environment.
+///
+/// Sources are considered in order, and the first one that is present wins:
+///
+/// 1. The profile name passed by the caller.
+/// 2. The profile name in `MENMOS_PROFILE`.
+/// 3. Inline credentials, if all of `MENMOS_HOST`, `MENMOS_USERNAME` and `MENMOS_PASSWORD` are set.
+/// 4. The default profile of the config file.
+///
+/// Profiles are read from `config_path` if provided, then from the file at `MENMOS_CONFIG`
+/// if set, and from the default config location otherwise. `MENMOS_HOST`, `MENMOS_USERNAME` and
+/// `MENMOS_PASSWORD` override the corresponding field of a profile from the config when they are set.
+pub(crate) fn resolve_profile(name: Option<&str>, config_path: Option<&Path>) -> Result<Profile> {
+    resolve_profile_with(name, config_path, |key| std::env::var(key).ok())
+}
+
+fn resolve_profile_with<F: Fn(&str) -> Option<String>>(
+    name: Option<&str>,
+    config_path: Option<&Path>,
+    env: F,
+) -> Result<Profile> {
+    let name = name.map(String::from).or_else(|| env(ENV_PROFILE));
+
+    if name.is_none() {
+        if let (Some(host), Some(username), Some(password)) =
+            (env(ENV_HOST), env(ENV_USERNAME), env(ENV_PASSWORD))
+        {
+            return Ok(Profile::new(host, username, Credential::password(password)));
+        }
+    }
+
+    let config = match config_path {
+        Some(path) => Config::load_from(path)?,
+        None => Config::load_from(get_config_path_with(&env)?)?,
+    };
+
+    let mut profile = match name {
+        Some(name) => config
+            .get(&name)
+            .cloned()
+            .context(MissingProfileSnafu { profile: name })?,
+        None => config
+            .default_profile()
+            .cloned()
+            .context(NoProfileSelectedSnafu)?,
+    };
+
+    if let Some(host) = env(ENV_HOST) {
+        profile.host = host;
+    }
+
+    if let Some(username) = env(ENV_USERNAME) {
+        profile.username = username;
+    }
+
+    if let Some(password) = env(ENV_PASSWORD) {
+        profile.credential = Credential::password(password);
+    }
+
+    Ok(profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn write_config(dir: &tempfile::TempDir) -> String {
+        let path = dir.path().join("client.toml");
+        fs::write(
+            &path,
+            r#"
+[profiles.local]
+host = "http://localhost:3030"
+username = "admin"
+password = "hunter2"
+"#,
+        )
+        .unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn env_from(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |key| vars.get(key).cloned()
+    }
+
+    #[test]
+    fn explicit_name_takes_precedence_over_env_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = write_config(&dir);
+        let env = env_from(&[(ENV_CONFIG, &config), (ENV_PROFILE, "remote")]);
+
+        let profile = resolve_profile_with(Some("local"), None, env).unwrap();
+        assert_eq!(profile.host, "http://localhost:3030");
+    }
+
+    #[tokio::test]
+    async fn env_profile_is_used_when_no_name_is_given() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = write_config(&dir);
+        let env = env_from(&[(ENV_CONFIG, &config), (ENV_PROFILE, "local")]);
+
+        let profile = resolve_profile_with(None, None, env).unwrap();
+        assert_eq!(profile.username, "admin");
+        assert_eq!(
+            profile.credential.resolve().await.unwrap().expose(),
+            "hunter2"
+        );
+    }
+
+    #[tokio::test]
+    async fn env_credentials_override_named_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = write_config(&dir);
+        let env = env_from(&[(ENV_CONFIG, &config), (ENV_PASSWORD, "swordfish")]);
+
+        let profile = resolve_profile_with(Some("local"), None, env).unwrap();
+        assert_eq!(profile.username, "admin");
+        assert_eq!(
+            profile.credential.resolve().await.unwrap().expose(),
+            "swordfish"
+        );
+    }
+
+    #[test]
+    fn inline_env_credentials() {
+        let env = env_from(&[
+            (ENV_HOST, "http://menmos:3030"),
+            (ENV_USERNAME, "ci"),
+            (ENV_PASSWORD, "secret"),
+        ]);
+
+        let profile = resolve_profile_with(None, None, env).unwrap();
+        assert_eq!(profile.host, "http://menmos:3030");
+        assert_eq!(profile.username, "ci");
+    }
+
+    #[test]
+    fn partial_env_credentials_are_rejected() {
+        let env = env_from(&[(ENV_HOST, "http://menmos:3030")]);
+
+        let err = resolve_profile_with(None, None, env).unwrap_err();
+        assert!(matches!(err, ProfileError::NoProfileSelected));
+    }
+
+    #[test]
+    fn explicit_config_path_takes_precedence_over_env_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = write_config(&dir);
+        let env = env_from(&[(ENV_CONFIG, "/does/not/exist.toml")]);
+
+        let profile = resolve_profile_with(Some("local"), Some(Path::new(&config)), env).unwrap();
+        assert_eq!(profile.host, "http://localhost:3030");
+    }
+
+    #[test]
+    fn save_and_load_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("client.toml");
+
+        let mut config = Config::load_from(&path).unwrap();
+        assert!(config.profiles.is_empty());
+
+        config
+            .add(
+                "test",
+                Profile::new(
+                    "http://localhost:3030",
+                    "admin",
+                    Credential::password("hunter2"),
+                ),
+            )
+            .unwrap();
+
+        let loaded = Config::load_from(&path).unwrap();
+        assert_eq!(loaded.profiles["test"].username, "admin");
+
+        let copy_path = dir.path().join("copy.toml");
+        loaded.save_to(&copy_path).unwrap();
+        let copy = Config::load_from(&copy_path).unwrap();
+        assert_eq!(copy.profiles["test"].host, "http://localhost:3030");
+    }
+
+    #[test]
+    fn parse_credential_sources() {
+        let config: Config = toml::from_str(
+            r#"
+version = 2
+
+[profiles.inline]
+host = "http://localhost:3030"
+username = "admin"
+password = "hunter2"
+
+[profiles.file]
+host = "http://localhost:3030"
+username = "admin"
+password_file = "/run/secrets/menmos"
+
+[profiles.process]
+host = "http://localhost:3030"
+username = "admin"
+credential_process = "pass show menmos"
+"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            config.profiles["inline"].credential,
+            Credential::Password { .. }
+        ));
+        assert!(matches!(
+            config.profiles["file"].credential,
+            Credential::PasswordFile { .. }
+        ));
+        assert!(matches!(
+            config.profiles["process"].credential,
+            Credential::CredentialProcess { .. }
+        ));
+    }
+
+    #[test]
+    fn conflicting_credential_sources_are_rejected() {
+        for fields in [
+            "password = \"hunter2\"\ncredential_process = \"pass show menmos\"",
+            "password = \"hunter2\"\npassword_file = \"/run/secrets/menmos\"",
+            "",
+        ] {
+            let config = format!(
+                "version = 2\n[profiles.local]\nhost = \"http://localhost:3030\"\nusername = \"admin\"\n{}\n",
+                fields
+            );
+            assert!(toml::from_str::<Config>(&config).is_err(), "{}", fields);
+        }
+    }
+
+    #[test]
+    fn default_profile_is_used_as_fallback() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = write_config(&dir);
+
+        let env = env_from(&[(ENV_CONFIG, &config_path)]);
+        let err = resolve_profile_with(None, None, &env).unwrap_err();
+        assert!(matches!(err, ProfileError::NoProfileSelected));
+
+        let mut config = Config::load_from(&config_path).unwrap();
+        config.set_default("local").unwrap();
+
+        let profile = resolve_profile_with(None, None, &env).unwrap();
+        assert_eq!(profile.host, "http://localhost:3030");
+    }
+
+    #[test]
+    fn manage_profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_config(&dir);
+
+        let mut config = Config::load_from(&path).unwrap();
+        assert!(config.set_default("nope").is_err());
+        config.set_default("local").unwrap();
+
+        config.rename("local", "dev").unwrap();
+        let config = Config::load_from(&path).unwrap();
+        assert!(config.get("local").is_none());
+        assert_eq!(config.default_profile_name(), Some("dev"));
+        assert_eq!(config.default_profile().unwrap().username, "admin");
+
+        let mut config = config;
+        config
+            .add(
+                "prod",
+                Profile::new(
+                    "http://menmos:3030",
+                    "admin",
+                    Credential::password("hunter2"),
+                ),
+            )
+            .unwrap();
+        let err = config.rename("prod", "dev").unwrap_err();
+        assert!(matches!(err, ProfileError::ProfileAlreadyExists { .. }));
+
+        config.remove("dev").unwrap();
+        let config = Config::load_from(&path).unwrap();
+        assert!(config.get("dev").is_none());
+        assert!(config.get("prod").is_some());
+        assert!(config.default_profile().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn saved_config_is_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("client.toml");
+        Config::default().save_to(&path).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn settings_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("client.toml");
+
+        let settings = ClientSettings {
+            request_timeout_ms: Some(5000),
+            max_retry_count: Some(3),
+            default_tags: vec![String::from("ci")],
+            ..Default::default()
+        };
+
+        let mut config = Config::load_from(&path).unwrap();
+        config
+            .add(
+                "tuned",
+                Profile::new("http://localhost:3030", "admin", Credential::password("a"))
+                    .with_settings(settings.clone()),
+            )
+            .unwrap();
+        config
+            .add(
+                "plain",
+                Profile::new("http://localhost:3030", "admin", Credential::password("a")),
+            )
+            .unwrap();
+
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.get("tuned").unwrap().settings, settings);
+        assert_eq!(
+            config.get("tuned").unwrap().settings.request_timeout(),
+            Some(Duration::from_secs(5))
+        );
+        assert!(config.get("plain").unwrap().settings.is_empty());
+    }
+
+    #[test]
+    fn unversioned_config_is_migrated_with_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_config(&dir);
+        let original = fs::read_to_string(&path).unwrap();
+
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.get("local").unwrap().username, "admin");
+
+        let backup = dir.path().join("client.toml.bak");
+        assert_eq!(fs::read_to_string(backup).unwrap(), original);
+
+        let migrated: toml::value::Table =
+            toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            migrated.get("version"),
+            Some(&toml::Value::Integer(CONFIG_VERSION as i64))
+        );
+    }
+
+    #[test]
+    fn readonly_config_is_migrated_in_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_config(&dir);
+        let original = fs::read_to_string(&path).unwrap();
+
+        // Permissions don't apply to root, but a backup name longer than the file name limit
+        // can't be written by anyone.
+        let path = dir.path().join(format!("{}.toml", "c".repeat(250)));
+        fs::write(&path, &original).unwrap();
+        let mut permissions = fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&path, permissions).unwrap();
+
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.get("local").unwrap().username, "admin");
+        assert_eq!(fs::read_to_string(&path).unwrap(), original);
+    }
+
+    #[test]
+    fn earlier_backups_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_config(&dir);
+        let first = fs::read_to_string(&path).unwrap();
+        Config::load_from(&path).unwrap();
+
+        fs::write(&path, first.replace("hunter2", "swordfish")).unwrap();
+        let second = fs::read_to_string(&path).unwrap();
+        Config::load_from(&path).unwrap();
+
+        let backup = fs::read_to_string(dir.path().join("client.toml.bak")).unwrap();
+        assert_eq!(backup, first);
+        let backup = fs::read_to_string(dir.path().join("client.toml.bak.1")).unwrap();
+        assert_eq!(backup, second);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn backups_are_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_config(&dir);
+        Config::load_from(&path).unwrap();
+
+        let backup = dir.path().join("client.toml.bak");
+        let mode = fs::metadata(backup).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn current_config_is_not_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("client.toml");
+        Config::default().save_to(&path).unwrap();
+
+        Config::load_from(&path).unwrap();
+        assert!(!dir.path().join("client.toml.bak").exists());
+    }
+
+    #[test]
+    fn newer_config_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("client.toml");
+        fs::write(
+            &path,
+            format!("version = {}\n[profiles]\n", CONFIG_VERSION + 1),
+        )
+        .unwrap();
+
+        let err = Config::load_from(&path).unwrap_err();
+        assert!(matches!(err, ProfileError::UnsupportedConfigVersion { .. }));
+    }
+
+    #[test]
+    fn missing_named_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = write_config(&dir);
+        let env = env_from(&[(ENV_CONFIG, &config)]);
+
+        let err = resolve_profile_with(Some("nope"), None, env).unwrap_err();
+        assert!(matches!(err, ProfileError::MissingProfile { .. }));
     }
 }
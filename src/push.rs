@@ -1,21 +1,70 @@
-use std::path::PathBuf;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use bytes::BytesMut;
 
 use menmos_client::{Meta, Type};
 
+use serde::{Deserialize, Serialize};
+
 use snafu::prelude::*;
 
-use crate::error;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::error::{self, ErrorKind, RequestError};
 use crate::metadata_detector::MetadataDetectorRC;
-use crate::{ClientRC, UploadRequest};
+use crate::{BackendRC, Defaults, UploadRequest};
 
 #[derive(Debug, Snafu)]
 pub enum PushError {
     MetadataPopulationError {
         source: error::MetadataDetectorError,
     },
-    // TODO: add source: ClientError once its exposed in menmos-client >= 0.1.0
-    #[snafu(display("failed to push '{:?}'", path))]
-    BlobPushError { path: PathBuf },
+
+    #[snafu(display("failed to push '{:?}': {}", path, source))]
+    BlobPushError { source: RequestError, path: PathBuf },
+
+    #[snafu(display("failed to read '{:?}': {}", path, source))]
+    SourceReadError {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("failed to upload '{:?}' at offset {}: {}", path, offset, source))]
+    ChunkUploadError {
+        source: RequestError,
+        path: PathBuf,
+        offset: u64,
+    },
+
+    #[snafu(display("failed to save upload checkpoint '{:?}': {}", path, source))]
+    CheckpointError {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+}
+
+impl PushError {
+    /// Get the kind of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            PushError::MetadataPopulationError { .. } => ErrorKind::Other,
+            PushError::BlobPushError { source, .. }
+            | PushError::ChunkUploadError { source, .. } => source.kind(),
+            PushError::SourceReadError { source, .. }
+            | PushError::CheckpointError { source, .. } => match source.kind() {
+                std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+                std::io::ErrorKind::PermissionDenied => ErrorKind::Unauthorized,
+                _ => ErrorKind::Other,
+            },
+        }
+    }
+
+    /// Whether the push that failed with this error could succeed if attempted again.
+    pub fn is_retryable(&self) -> bool {
+        self.kind().is_retryable()
+    }
 }
 
 type Result<T> = std::result::Result<T, PushError>;
@@ -26,12 +75,226 @@ pub struct PushResult {
     pub parent_id: Option<String>,
 }
 
-pub(crate) async fn push_file(
-    client: ClientRC,
+const DEFAULT_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+const DEFAULT_MAX_RETRIES: usize = 3;
+
+const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+const CHECKPOINT_EXTENSION: &str = "menmos-upload";
+
+/// Settings for uploading files in chunks.
+///
+/// When enabled with [`Menmos::with_chunked_upload`](crate::Menmos::with_chunked_upload), files are
+/// pushed by creating an empty blob and writing the file to it in fixed-size chunks. Each chunk is
+/// retried on its own, so a network failure doesn't restart the whole upload.
+///
+/// The offset of the last confirmed chunk is saved in a small checkpoint file. If the upload is
+/// interrupted, pushing the same file again resumes it from that offset, as long as the file
+/// wasn't modified in between. Otherwise, the partially uploaded blob is deleted and the upload
+/// starts over. The checkpoint is removed once the upload completes.
+///
+/// By default, the checkpoint of a file is stored next to it, as `.<file name>.menmos-upload`.
+/// Checkpoint files are never uploaded when pushing a directory.
+///
+/// # Examples
+/// ```no_run
+/// use std::collections::HashMap;
+///
+/// use futures::TryStreamExt;
+/// use menmos::{push::ChunkedUpload, Menmos, UploadRequest};
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let client = Menmos::new(None)
+///     .await
+///     .unwrap()
+///     .with_chunked_upload(ChunkedUpload::default().with_chunk_size(64 * 1024 * 1024));
+///
+/// let request = UploadRequest {
+///     path: "backup.tar".into(),
+///     metadata: HashMap::new(),
+///     tags: Vec::new(),
+///     parent_id: None,
+/// };
+/// client.push_files(vec![request]).try_collect::<Vec<_>>().await.unwrap();
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ChunkedUpload {
+    chunk_size: usize,
+    max_retries: usize,
+    retry_interval: Duration,
+    checkpoint_dir: Option<PathBuf>,
+}
+
+impl Default for ChunkedUpload {
+    fn default() -> Self {
+        Self {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+            checkpoint_dir: None,
+        }
+    }
+}
+
+impl ChunkedUpload {
+    /// Set the number of bytes written per request. The default is 8 MiB,
+    /// and the minimum is one byte.
+    ///
+    /// Changing the chunk size doesn't prevent resuming an upload.
+    #[must_use]
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Set the number of times a chunk is retried after a retryable error. The default is 3 retries.
+    #[must_use]
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Set the time to wait before retrying a chunk. The default is 500 milliseconds.
+    #[must_use]
+    pub fn with_retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    /// Store checkpoints in `dir` instead of next to the uploaded files.
+    ///
+    /// The directory must exist. This is useful when the uploaded files are in a read-only location.
+    #[must_use]
+    pub fn with_checkpoint_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.checkpoint_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    fn checkpoint_path(&self, path: &Path) -> PathBuf {
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        match self.checkpoint_dir.as_ref() {
+            Some(dir) => {
+                // Files with the same name from different directories get different checkpoints.
+                let full_path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+                dir.join(format!(
+                    "{}-{:016x}.{}",
+                    file_name,
+                    fnv1a(full_path.as_os_str().as_encoded_bytes()),
+                    CHECKPOINT_EXTENSION
+                ))
+            }
+            None => path.with_file_name(format!(".{}.{}", file_name, CHECKPOINT_EXTENSION)),
+        }
+    }
+}
+
+/// Hash bytes with 64-bit FNV-1a.
+///
+/// Unlike `DefaultHasher`, the result doesn't change between Rust releases, so checkpoints
+/// survive an upgrade.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// The state of an interrupted chunked upload.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+struct Checkpoint {
+    blob_id: String,
+
+    /// The number of bytes confirmed by the cluster.
+    offset: u64,
+
+    /// The size and modification time of the source file, to detect changes between attempts.
+    size: u64,
+    modified_ns: u64,
+}
+
+impl Checkpoint {
+    async fn load(path: &Path) -> Option<Self> {
+        let data = tokio::fs::read(path).await.ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+
+    async fn save(&self, path: &Path) -> std::io::Result<()> {
+        // Write to a temporary file first, so an interruption can't leave a corrupt checkpoint.
+        let tmp_path = path.with_extension(format!("{}.tmp", CHECKPOINT_EXTENSION));
+        tokio::fs::write(&tmp_path, serde_json::to_vec(self)?).await?;
+        tokio::fs::rename(tmp_path, path).await
+    }
+}
+
+/// Get whether `path` is an upload checkpoint, or a checkpoint being written.
+///
+/// Checkpoints are stored next to the uploaded files by default, so they are skipped
+/// when pushing directories.
+pub(crate) fn is_checkpoint(path: &Path) -> bool {
+    let file_name = match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return false,
+    };
+    file_name.ends_with(&format!(".{}", CHECKPOINT_EXTENSION))
+        || file_name.ends_with(&format!(".{}.tmp", CHECKPOINT_EXTENSION))
+}
+
+/// Get whether an upload can resume from `checkpoint`.
+///
+/// The blob of a checkpoint that can't be used anymore because the source file changed is deleted.
+async fn resume_checkpoint(
+    backend: &BackendRC,
+    checkpoint: Checkpoint,
+    size: u64,
+    modified_ns: u64,
+    path: &Path,
+) -> Result<Option<Checkpoint>> {
+    // The blob could have been deleted since the upload was interrupted.
+    match backend.get_meta(&checkpoint.blob_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return Ok(None),
+        // Starting over because of a transient error would throw away the uploaded chunks.
+        Err(e) if e.is_retryable() => return Err(e).context(BlobPushSnafu { path }),
+        Err(e) => {
+            tracing::warn!(
+                "failed to get blob '{}' of the upload checkpoint of '{:?}', uploading again: {}",
+                checkpoint.blob_id,
+                path,
+                e
+            );
+            return Ok(None);
+        }
+    }
+
+    if checkpoint.size == size && checkpoint.modified_ns == modified_ns && checkpoint.offset <= size
+    {
+        return Ok(Some(checkpoint));
+    }
+
+    if let Err(e) = backend.delete(&checkpoint.blob_id).await {
+        tracing::warn!(
+            "failed to delete blob '{}' of the stale upload checkpoint of '{:?}': {}",
+            checkpoint.blob_id,
+            path,
+            e
+        );
+    }
+
+    Ok(None)
+}
+
+fn build_meta(
     metadata_detector: &MetadataDetectorRC,
+    defaults: &Defaults,
     blob_type: Type,
-    request: UploadRequest,
-) -> Result<String> {
+    request: &UploadRequest,
+) -> Result<Meta> {
     let mut meta = Meta::new(
         request
             .path
@@ -50,7 +313,7 @@ pub(crate) async fn push_file(
         meta = meta.with_size(request.path.metadata().unwrap().len())
     }
 
-    if let Some(parent) = request.parent_id {
+    if let Some(parent) = request.parent_id.as_ref() {
         meta = meta.with_parent(parent);
     }
 
@@ -62,12 +325,458 @@ pub(crate) async fn push_file(
         meta = meta.with_meta(k, v);
     }
 
-    let item_id = client
+    defaults.apply(&mut meta.tags, &mut meta.metadata);
+
+    Ok(meta)
+}
+
+pub(crate) async fn push_file(
+    backend: BackendRC,
+    metadata_detector: &MetadataDetectorRC,
+    defaults: &Defaults,
+    blob_type: Type,
+    request: UploadRequest,
+) -> Result<String> {
+    let meta = build_meta(metadata_detector, defaults, blob_type, &request)?;
+
+    let item_id = backend
         .push(&request.path, meta)
         .await
-        .map_err(|_| PushError::BlobPushError {
+        .context(BlobPushSnafu {
             path: request.path.clone(),
         })?;
 
     Ok(item_id)
 }
+
+/// Push a file by writing it in chunks to an empty blob, resuming from a checkpoint if there is one.
+pub(crate) async fn push_file_chunked(
+    backend: BackendRC,
+    metadata_detector: &MetadataDetectorRC,
+    defaults: &Defaults,
+    request: UploadRequest,
+    settings: &ChunkedUpload,
+) -> Result<String> {
+    let path = request.path.as_path();
+    let meta = build_meta(metadata_detector, defaults, Type::File, &request)?;
+
+    let file_meta = path.metadata().context(SourceReadSnafu { path })?;
+    let size = file_meta.len();
+    let modified_ns = file_meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default();
+
+    let checkpoint_path = settings.checkpoint_path(path);
+    let mut checkpoint = match Checkpoint::load(&checkpoint_path).await {
+        Some(c) => resume_checkpoint(&backend, c, size, modified_ns, path).await?,
+        None => None,
+    };
+
+    if checkpoint.is_none() {
+        // The size is set once all the chunks are written.
+        let mut empty_meta = meta.clone();
+        empty_meta.size = 0;
+        let blob_id = backend
+            .create_empty(empty_meta)
+            .await
+            .context(BlobPushSnafu { path })?;
+        checkpoint = Some(Checkpoint {
+            blob_id,
+            offset: 0,
+            size,
+            modified_ns,
+        });
+    }
+    let mut checkpoint = checkpoint.unwrap();
+    checkpoint
+        .save(&checkpoint_path)
+        .await
+        .context(CheckpointSnafu {
+            path: &checkpoint_path,
+        })?;
+
+    let mut source = tokio::fs::File::open(path)
+        .await
+        .context(SourceReadSnafu { path })?;
+    source
+        .seek(SeekFrom::Start(checkpoint.offset))
+        .await
+        .context(SourceReadSnafu { path })?;
+
+    while checkpoint.offset < size {
+        let len = (size - checkpoint.offset).min(settings.chunk_size as u64) as usize;
+        let mut chunk = BytesMut::zeroed(len);
+        source
+            .read_exact(&mut chunk)
+            .await
+            .context(SourceReadSnafu { path })?;
+        let chunk = chunk.freeze();
+
+        let mut attempt = 0;
+        loop {
+            match backend
+                .write(&checkpoint.blob_id, checkpoint.offset, chunk.clone())
+                .await
+            {
+                Ok(()) => break,
+                Err(e) if e.is_retryable() && attempt < settings.max_retries => {
+                    attempt += 1;
+                    tracing::debug!(
+                        "retrying chunk at offset {} of '{:?}' (attempt {}): {}",
+                        checkpoint.offset,
+                        path,
+                        attempt,
+                        e
+                    );
+                    tokio::time::sleep(settings.retry_interval).await;
+                }
+                Err(e) => {
+                    return Err(e).context(ChunkUploadSnafu {
+                        path,
+                        offset: checkpoint.offset,
+                    })
+                }
+            }
+        }
+
+        checkpoint.offset += len as u64;
+        checkpoint
+            .save(&checkpoint_path)
+            .await
+            .context(CheckpointSnafu {
+                path: &checkpoint_path,
+            })?;
+    }
+
+    backend
+        .update_meta(&checkpoint.blob_id, meta)
+        .await
+        .context(BlobPushSnafu { path })?;
+
+    if let Err(e) = tokio::fs::remove_file(&checkpoint_path).await {
+        tracing::warn!(
+            "failed to remove upload checkpoint '{:?}': {}",
+            checkpoint_path,
+            e
+        );
+    }
+
+    Ok(checkpoint.blob_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+
+    use bytes::Bytes;
+
+    use interface::BlobMeta;
+    use menmos_client::{Query, QueryResponse};
+
+    use super::*;
+    use crate::backend::{self, Backend, MemoryBackend};
+    use crate::metadata_detector::MetadataDetector;
+
+    /// A backend failing the writes at `fail_offset` with a network error while `failures` is positive,
+    /// and the metadata reads while `meta_failures` is positive.
+    #[derive(Default)]
+    struct FlakyBackend {
+        inner: MemoryBackend,
+        writes: AtomicU64,
+        fail_offset: AtomicU64,
+        failures: AtomicU64,
+        meta_failures: AtomicU64,
+    }
+
+    #[async_trait]
+    impl Backend for FlakyBackend {
+        async fn push(&self, path: &Path, meta: Meta) -> backend::Result<String> {
+            self.inner.push(path, meta).await
+        }
+
+        async fn create_empty(&self, meta: Meta) -> backend::Result<String> {
+            self.inner.create_empty(meta).await
+        }
+
+        async fn write(&self, blob_id: &str, offset: u64, buffer: Bytes) -> backend::Result<()> {
+            if offset == self.fail_offset.load(Ordering::SeqCst)
+                && self
+                    .failures
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                    .is_ok()
+            {
+                return Err(RequestError::new(ErrorKind::Network, "injected failure"));
+            }
+
+            self.writes.fetch_add(1, Ordering::SeqCst);
+            self.inner.write(blob_id, offset, buffer).await
+        }
+
+        async fn read_range(&self, blob_id: &str, range: (u64, u64)) -> backend::Result<Vec<u8>> {
+            self.inner.read_range(blob_id, range).await
+        }
+
+        async fn get_meta(&self, blob_id: &str) -> backend::Result<Option<BlobMeta>> {
+            if self
+                .meta_failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok()
+            {
+                return Err(RequestError::new(ErrorKind::Timeout, "injected failure"));
+            }
+
+            self.inner.get_meta(blob_id).await
+        }
+
+        async fn query(&self, query: Query) -> backend::Result<QueryResponse> {
+            self.inner.query(query).await
+        }
+
+        async fn delete(&self, blob_id: &str) -> backend::Result<()> {
+            self.inner.delete(blob_id).await
+        }
+
+        async fn update_meta(&self, blob_id: &str, meta: Meta) -> backend::Result<()> {
+            self.inner.update_meta(blob_id, meta).await
+        }
+    }
+
+    fn request(path: &Path) -> UploadRequest {
+        UploadRequest {
+            path: path.to_path_buf(),
+            metadata: HashMap::new(),
+            tags: vec![String::from("chunked")],
+            parent_id: None,
+        }
+    }
+
+    fn settings() -> ChunkedUpload {
+        ChunkedUpload::default()
+            .with_chunk_size(1000)
+            .with_retry_interval(Duration::from_millis(1))
+    }
+
+    async fn push(
+        backend: &Arc<FlakyBackend>,
+        path: &Path,
+        settings: &ChunkedUpload,
+    ) -> Result<String> {
+        let detector: MetadataDetectorRC = Arc::new(MetadataDetector::new().unwrap());
+        push_file_chunked(
+            backend.clone(),
+            &detector,
+            &Defaults::default(),
+            request(path),
+            settings,
+        )
+        .await
+    }
+
+    fn source_file(dir: &Path) -> (PathBuf, Vec<u8>) {
+        let content: Vec<u8> = (0..4_500_u32).map(|i| (i % 251) as u8).collect();
+        let path = dir.join("data.bin");
+        std::fs::write(&path, &content).unwrap();
+        (path, content)
+    }
+
+    #[tokio::test]
+    async fn chunks_are_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, content) = source_file(dir.path());
+
+        let backend = Arc::new(FlakyBackend::default());
+        backend.fail_offset.store(2000, Ordering::SeqCst);
+        backend.failures.store(2, Ordering::SeqCst);
+
+        let blob_id = push(&backend, &path, &settings()).await.unwrap();
+        assert_eq!(backend.writes.load(Ordering::SeqCst), 5);
+
+        let meta = backend.get_meta(&blob_id).await.unwrap().unwrap();
+        assert_eq!(meta.size, 4_500);
+        assert_eq!(meta.name, "data.bin");
+        assert_eq!(meta.tags, vec![String::from("chunked")]);
+        assert_eq!(
+            backend.read_range(&blob_id, (0, 4_499)).await.unwrap(),
+            content
+        );
+        assert!(!settings().checkpoint_path(&path).exists());
+    }
+
+    #[tokio::test]
+    async fn interrupted_uploads_are_resumed() {
+        let dir = tempfile::tempdir().unwrap();
+        let checkpoints = tempfile::tempdir().unwrap();
+        let (path, content) = source_file(dir.path());
+        let settings = settings()
+            .with_max_retries(0)
+            .with_checkpoint_dir(checkpoints.path());
+
+        let backend = Arc::new(FlakyBackend::default());
+        backend.fail_offset.store(3000, Ordering::SeqCst);
+        backend.failures.store(1, Ordering::SeqCst);
+
+        let err = push(&backend, &path, &settings).await.unwrap_err();
+        assert!(matches!(
+            err,
+            PushError::ChunkUploadError { offset: 3000, .. }
+        ));
+        assert!(err.is_retryable());
+
+        let checkpoint = Checkpoint::load(&settings.checkpoint_path(&path))
+            .await
+            .unwrap();
+        assert_eq!(checkpoint.offset, 3000);
+
+        // Only the remaining chunks are written.
+        let blob_id = push(&backend, &path, &settings).await.unwrap();
+        assert_eq!(blob_id, checkpoint.blob_id);
+        assert_eq!(backend.writes.load(Ordering::SeqCst), 5);
+        assert_eq!(
+            backend.read_range(&blob_id, (0, 4_499)).await.unwrap(),
+            content
+        );
+        assert!(!settings.checkpoint_path(&path).exists());
+    }
+
+    #[tokio::test]
+    async fn modified_files_are_uploaded_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, _) = source_file(dir.path());
+        let settings = settings().with_max_retries(0);
+
+        let backend = Arc::new(FlakyBackend::default());
+        backend.fail_offset.store(1000, Ordering::SeqCst);
+        backend.failures.store(1, Ordering::SeqCst);
+        push(&backend, &path, &settings).await.unwrap_err();
+        let interrupted = Checkpoint::load(&settings.checkpoint_path(&path))
+            .await
+            .unwrap();
+
+        std::fs::write(&path, b"new content").unwrap();
+        let blob_id = push(&backend, &path, &settings).await.unwrap();
+        assert_ne!(blob_id, interrupted.blob_id);
+        assert_eq!(
+            backend.read_range(&blob_id, (0, 10)).await.unwrap(),
+            b"new content"
+        );
+
+        // The blob of the interrupted upload is deleted.
+        assert!(backend
+            .get_meta(&interrupted.blob_id)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn checkpoints_survive_transient_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, content) = source_file(dir.path());
+        let settings = settings().with_max_retries(0);
+
+        let backend = Arc::new(FlakyBackend::default());
+        backend.fail_offset.store(3000, Ordering::SeqCst);
+        backend.failures.store(1, Ordering::SeqCst);
+        push(&backend, &path, &settings).await.unwrap_err();
+        let interrupted = Checkpoint::load(&settings.checkpoint_path(&path))
+            .await
+            .unwrap();
+
+        backend.meta_failures.store(1, Ordering::SeqCst);
+        let err = push(&backend, &path, &settings).await.unwrap_err();
+        assert!(err.is_retryable());
+        assert_eq!(
+            Checkpoint::load(&settings.checkpoint_path(&path))
+                .await
+                .unwrap(),
+            interrupted
+        );
+
+        let blob_id = push(&backend, &path, &settings).await.unwrap();
+        assert_eq!(blob_id, interrupted.blob_id);
+        assert_eq!(
+            backend.read_range(&blob_id, (0, 4_499)).await.unwrap(),
+            content
+        );
+    }
+
+    #[tokio::test]
+    async fn interrupted_directory_pushes_are_resumed() {
+        use futures::TryStreamExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        std::fs::create_dir(&source).unwrap();
+        let (path, content) = source_file(&source);
+        let settings = settings().with_max_retries(0);
+
+        let backend = Arc::new(FlakyBackend::default());
+        backend.fail_offset.store(3000, Ordering::SeqCst);
+        backend.failures.store(1, Ordering::SeqCst);
+        let client = crate::Menmos::new_with_backend(backend.clone(), Defaults::default())
+            .with_chunked_upload(settings.clone());
+
+        let push_dir = || {
+            client
+                .push_files(vec![request(&source)])
+                .try_collect::<Vec<_>>()
+        };
+        assert!(push_dir().await.is_err());
+        let interrupted = Checkpoint::load(&settings.checkpoint_path(&path))
+            .await
+            .unwrap();
+
+        let results = push_dir().await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].source_path, path);
+        assert_eq!(results[0].blob_id, interrupted.blob_id);
+
+        // The resumed file is moved to the directory pushed by the second attempt.
+        let meta = backend
+            .get_meta(&interrupted.blob_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(meta.parents, vec![results[0].parent_id.clone().unwrap()]);
+        assert_eq!(
+            backend
+                .read_range(&interrupted.blob_id, (0, 4_499))
+                .await
+                .unwrap(),
+            content
+        );
+
+        // The checkpoint was never uploaded.
+        let hits = backend.query(Query::default()).await.unwrap().hits;
+        assert!(hits
+            .iter()
+            .all(|hit| !hit.meta.name.contains(CHECKPOINT_EXTENSION)));
+    }
+
+    #[test]
+    fn checkpoint_files_are_recognized() {
+        assert!(is_checkpoint(Path::new("/data/.a.bin.menmos-upload")));
+        assert!(is_checkpoint(Path::new("/data/.a.bin.menmos-upload.tmp")));
+        assert!(!is_checkpoint(Path::new("/data/a.bin")));
+        assert!(!is_checkpoint(Path::new("/")));
+    }
+
+    #[test]
+    fn checkpoint_names_are_stable() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+
+        let settings = ChunkedUpload::default().with_checkpoint_dir("/checkpoints");
+        assert_eq!(
+            settings.checkpoint_path(Path::new("/missing/data.bin")),
+            PathBuf::from("/checkpoints/data.bin-f0c9277be7877000.menmos-upload")
+        );
+    }
+}
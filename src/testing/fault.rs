@@ -0,0 +1,79 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A failure injected in place of a response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// Answer with an error response carrying this HTTP status code.
+    Status(u16),
+
+    /// Close the connection without answering.
+    DropConnection,
+}
+
+#[derive(Default)]
+struct FaultState {
+    latency: Duration,
+    pending: VecDeque<Fault>,
+    request_count: usize,
+}
+
+/// The faults injected by a [`TestServer`](super::TestServer) in the requests it serves.
+///
+/// Faults are consumed in the order they were queued, one per request. Clones share the same state,
+/// so faults can be changed while the server is running.
+#[derive(Clone, Default)]
+pub struct Faults {
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl Faults {
+    /// Delay every response by `latency`.
+    pub fn set_latency(&self, latency: Duration) {
+        self.state.lock().unwrap().latency = latency;
+    }
+
+    /// Inject `fault` in the next `count` requests.
+    pub fn inject(&self, fault: Fault, count: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.extend(std::iter::repeat_n(fault, count));
+    }
+
+    /// Answer the next `count` requests with an internal server error.
+    pub fn fail_next(&self, count: usize) {
+        self.inject(Fault::Status(500), count);
+    }
+
+    /// Close the connection of the next `count` requests without answering.
+    pub fn drop_next(&self, count: usize) {
+        self.inject(Fault::DropConnection, count);
+    }
+
+    /// Remove the latency and all pending faults.
+    pub fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.latency = Duration::ZERO;
+        state.pending.clear();
+    }
+
+    /// Get the number of requests received by the server, including the ones that were faulted.
+    pub fn request_count(&self) -> usize {
+        self.state.lock().unwrap().request_count
+    }
+
+    /// Record a request, waiting for the configured latency and returning the fault to inject, if any.
+    pub(crate) async fn next(&self) -> Option<Fault> {
+        let (latency, fault) = {
+            let mut state = self.state.lock().unwrap();
+            state.request_count += 1;
+            (state.latency, state.pending.pop_front())
+        };
+
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+
+        fault
+    }
+}
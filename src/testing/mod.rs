@@ -0,0 +1,13 @@
+//! Utilities for testing applications against a menmos cluster.
+//!
+//! [`TestServer`] runs a fake menmos node in-process, serving enough of the menmos HTTP API for
+//! [`Menmos`](crate::Menmos) clients to connect to it. Unlike [`MemoryBackend`](crate::backend::MemoryBackend),
+//! requests go through the same HTTP client used against a real cluster, and [`Faults`] can be injected to
+//! exercise retries and error handling.
+//!
+//! This module requires the `testing` feature.
+mod fault;
+mod server;
+
+pub use fault::{Fault, Faults};
+pub use server::{TestServer, TestServerBuilder, TestServerError};
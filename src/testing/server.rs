@@ -0,0 +1,556 @@
+use std::convert::Infallible;
+use std::fmt;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+
+use hyper::header::{self, HeaderValue};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+
+use menmos_client::{Meta, Query};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use snafu::prelude::*;
+
+use tokio::sync::oneshot;
+
+use super::{Fault, Faults};
+use crate::backend::{Backend, MemoryBackend};
+use crate::error::{ErrorKind, RequestError};
+use crate::{Credential, Profile};
+
+const DEFAULT_USERNAME: &str = "admin";
+const DEFAULT_PASSWORD: &str = "password";
+
+const BLOB_META_HEADER: &str = "x-blob-meta";
+
+#[derive(Debug, Snafu)]
+pub enum TestServerError {
+    #[snafu(display("failed to bind test server: {}", source))]
+    BindError { source: std::io::Error },
+
+    #[snafu(display("failed to start test server: {}", source))]
+    ServeError { source: hyper::Error },
+}
+
+type Result<T> = std::result::Result<T, TestServerError>;
+
+/// Returned by the request handler to make hyper close the connection without answering.
+#[derive(Debug)]
+struct ConnectionDropped;
+
+impl fmt::Display for ConnectionDropped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("connection dropped by fault injection")
+    }
+}
+
+impl std::error::Error for ConnectionDropped {}
+
+/// An error answered to the client.
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new<S: Into<String>>(status: StatusCode, message: S) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    /// Like the cluster, bad requests are reported as such, with some details appended.
+    fn bad_request<S: AsRef<str>>(message: S) -> Self {
+        Self::new(
+            StatusCode::BAD_REQUEST,
+            format!("bad request: {}", message.as_ref()),
+        )
+    }
+
+    fn into_response(self) -> Response<Body> {
+        json_response(self.status, &json!({ "error": self.message }))
+    }
+}
+
+impl From<RequestError> for ApiError {
+    fn from(e: RequestError) -> Self {
+        let status = match e.kind() {
+            ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorKind::Conflict => StatusCode::CONFLICT,
+            ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self::new(status, e.to_string())
+    }
+}
+
+type ApiResult = std::result::Result<Response<Body>, ApiError>;
+
+#[derive(Deserialize)]
+struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+#[derive(Serialize)]
+struct LoginResponse<'a> {
+    token: &'a str,
+}
+
+struct ServerState {
+    address: SocketAddr,
+    backend: MemoryBackend,
+    faults: Faults,
+    username: String,
+    password: String,
+    token: String,
+}
+
+fn json_response<T: Serialize>(status: StatusCode, value: &T) -> Response<Body> {
+    // Serializing our own response types can't fail.
+    let body = serde_json::to_vec(value).unwrap();
+
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
+fn empty_response() -> Response<Body> {
+    json_response(StatusCode::OK, &json!({ "message": "ok" }))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Extract the contents of the first part of a multipart body.
+fn multipart_content<'a>(body: &'a [u8], boundary: &str) -> Option<&'a [u8]> {
+    let delimiter = format!("--{}", boundary);
+    let part_start = find(body, delimiter.as_bytes())? + delimiter.len();
+    let content_start = part_start + find(&body[part_start..], b"\r\n\r\n")? + 4;
+
+    let closing_delimiter = format!("\r\n--{}", boundary);
+    let content_end = content_start + find(&body[content_start..], closing_delimiter.as_bytes())?;
+
+    Some(&body[content_start..content_end])
+}
+
+/// Parse an end-inclusive `bytes=a-b` range header.
+fn parse_range(request: &Request<Body>) -> std::result::Result<Option<(u64, u64)>, ApiError> {
+    let value = match request.headers().get(header::RANGE) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+
+    let invalid = || ApiError::bad_request("invalid range header");
+
+    let range = value
+        .to_str()
+        .ok()
+        .and_then(|v| v.strip_prefix("bytes="))
+        .and_then(|v| v.split_once('-'))
+        .ok_or_else(invalid)?;
+
+    let start = range.0.parse::<u64>().map_err(|_| invalid())?;
+    let end = range.1.parse::<u64>().map_err(|_| invalid())?;
+    if start > end {
+        return Err(invalid());
+    }
+
+    Ok(Some((start, end)))
+}
+
+fn parse_blob_meta(request: &Request<Body>) -> std::result::Result<Meta, ApiError> {
+    let encoded = request
+        .headers()
+        .get(BLOB_META_HEADER)
+        .ok_or_else(|| ApiError::bad_request("missing blob meta header"))?;
+
+    let decoded = base64::decode(encoded.as_bytes())
+        .map_err(|_| ApiError::bad_request("invalid blob meta header"))?;
+
+    serde_json::from_slice(&decoded).map_err(|_| ApiError::bad_request("invalid blob meta header"))
+}
+
+async fn read_body(request: Request<Body>) -> std::result::Result<Bytes, ApiError> {
+    hyper::body::to_bytes(request.into_body())
+        .await
+        .map_err(|e| ApiError::bad_request(format!("failed to read request body: {}", e)))
+}
+
+async fn read_json<T: for<'de> Deserialize<'de>>(
+    request: Request<Body>,
+) -> std::result::Result<T, ApiError> {
+    let body = read_body(request).await?;
+    serde_json::from_slice(&body)
+        .map_err(|e| ApiError::bad_request(format!("invalid request body: {}", e)))
+}
+
+impl ServerState {
+    async fn handle(
+        self: Arc<Self>,
+        request: Request<Body>,
+    ) -> std::result::Result<Response<Body>, ConnectionDropped> {
+        match self.faults.next().await {
+            Some(Fault::DropConnection) => return Err(ConnectionDropped),
+            Some(Fault::Status(code)) => {
+                let status =
+                    StatusCode::from_u16(code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+                let message = format!("injected failure: {}", status);
+                return Ok(ApiError::new(status, message).into_response());
+            }
+            None => {}
+        }
+
+        Ok(self
+            .route(request)
+            .await
+            .unwrap_or_else(ApiError::into_response))
+    }
+
+    async fn route(&self, request: Request<Body>) -> ApiResult {
+        let method = request.method().clone();
+        let path = request.uri().path().to_string();
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+        match (&method, segments.as_slice()) {
+            (&Method::POST, ["auth", "login"]) => return self.login(request).await,
+            (&Method::GET, ["health"]) => {
+                return Ok(json_response(
+                    StatusCode::OK,
+                    &json!({ "message": "healthy" }),
+                ))
+            }
+            _ => {}
+        }
+
+        self.authorize(&request)?;
+
+        match (&method, segments.as_slice()) {
+            (&Method::POST, ["query"]) => self.query(request).await,
+            (&Method::GET, ["blob", blob_id, "metadata"]) => self.get_meta(blob_id).await,
+
+            // Like a directory node, blob operations are redirected to the node storing the blob.
+            (&Method::POST, ["blob"])
+            | (&Method::GET | &Method::PUT | &Method::DELETE, ["blob", _])
+            | (&Method::PUT, ["blob", _, "metadata"])
+            | (&Method::POST, ["blob", _, "fsync"]) => Ok(self.redirect(&path)),
+
+            (&Method::POST, ["storage", "blob"]) => self.put(request).await,
+            (&Method::PUT, ["storage", "blob", blob_id]) => self.write(blob_id, request).await,
+            (&Method::GET, ["storage", "blob", blob_id]) => self.read(blob_id, &request).await,
+            (&Method::DELETE, ["storage", "blob", blob_id]) => {
+                self.backend.delete(blob_id).await?;
+                Ok(empty_response())
+            }
+            (&Method::PUT, ["storage", "blob", blob_id, "metadata"]) => {
+                let meta: Meta = read_json(request).await?;
+                self.backend.update_meta(blob_id, meta).await?;
+                Ok(empty_response())
+            }
+            (&Method::POST, ["storage", "blob", blob_id, "fsync"]) => {
+                self.ensure_exists(blob_id).await?;
+                Ok(empty_response())
+            }
+
+            _ => Err(ApiError::new(
+                StatusCode::NOT_FOUND,
+                format!("no route for {} {}", method, path),
+            )),
+        }
+    }
+
+    async fn login(&self, request: Request<Body>) -> ApiResult {
+        let login: LoginRequest = read_json(request).await?;
+
+        if login.username != self.username || login.password != self.password {
+            return Err(ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "invalid credentials",
+            ));
+        }
+
+        Ok(json_response(
+            StatusCode::OK,
+            &LoginResponse { token: &self.token },
+        ))
+    }
+
+    fn authorize(&self, request: &Request<Body>) -> std::result::Result<(), ApiError> {
+        let expected = format!("Bearer {}", self.token);
+        let authorized = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .map(|value| value.as_bytes() == expected.as_bytes())
+            .unwrap_or(false);
+
+        if authorized {
+            Ok(())
+        } else {
+            Err(ApiError::new(StatusCode::UNAUTHORIZED, "unauthorized"))
+        }
+    }
+
+    fn redirect(&self, path: &str) -> Response<Body> {
+        let location = format!("http://{}/storage{}", self.address, path);
+
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::TEMPORARY_REDIRECT;
+        // Our own address and request path always form a valid header value.
+        response
+            .headers_mut()
+            .insert(header::LOCATION, HeaderValue::from_str(&location).unwrap());
+        response
+    }
+
+    async fn ensure_exists(&self, blob_id: &str) -> std::result::Result<(), ApiError> {
+        match self.backend.get_meta(blob_id).await? {
+            Some(_) => Ok(()),
+            None => Err(ApiError::new(
+                StatusCode::NOT_FOUND,
+                format!("blob '{}' does not exist", blob_id),
+            )),
+        }
+    }
+
+    async fn put(&self, request: Request<Body>) -> ApiResult {
+        let meta = parse_blob_meta(&request)?;
+
+        let boundary = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| v.starts_with("multipart/form-data"))
+            .and_then(|v| v.split_once("boundary="))
+            .map(|(_, boundary)| boundary.to_string());
+
+        let body = read_body(request).await?;
+        let content = match boundary {
+            Some(boundary) => multipart_content(&body, &boundary)
+                .map(|content| body.slice_ref(content))
+                .ok_or_else(|| ApiError::bad_request("invalid multipart body"))?,
+            None => body,
+        };
+
+        let blob_id = self.backend.create_empty(meta).await?;
+        if !content.is_empty() {
+            self.backend.write(&blob_id, 0, content).await?;
+        }
+
+        Ok(json_response(StatusCode::OK, &json!({ "id": blob_id })))
+    }
+
+    async fn write(&self, blob_id: &str, request: Request<Body>) -> ApiResult {
+        let (offset, _) =
+            parse_range(&request)?.ok_or_else(|| ApiError::bad_request("missing range header"))?;
+
+        let body = read_body(request).await?;
+        self.backend.write(blob_id, offset, body).await?;
+
+        Ok(json_response(StatusCode::OK, &json!({ "id": blob_id })))
+    }
+
+    async fn read(&self, blob_id: &str, request: &Request<Body>) -> ApiResult {
+        let range = parse_range(request)?.unwrap_or((0, u64::MAX));
+        let content = self.backend.read_range(blob_id, range).await?;
+        Ok(Response::new(Body::from(content)))
+    }
+
+    async fn get_meta(&self, blob_id: &str) -> ApiResult {
+        let meta = self.backend.get_meta(blob_id).await?;
+        Ok(json_response(StatusCode::OK, &json!({ "meta": meta })))
+    }
+
+    async fn query(&self, request: Request<Body>) -> ApiResult {
+        let query: Query = read_json(request).await?;
+        let response = self.backend.query(query).await?;
+        Ok(json_response(StatusCode::OK, &response))
+    }
+}
+
+/// Configures a [`TestServer`] before starting it.
+pub struct TestServerBuilder {
+    backend: MemoryBackend,
+    username: String,
+    password: String,
+}
+
+impl TestServerBuilder {
+    /// Serve the blobs of an existing backend.
+    #[must_use]
+    pub fn with_backend(mut self, backend: MemoryBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Only accept logins with these credentials.
+    #[must_use]
+    pub fn with_credentials<U: Into<String>, P: Into<String>>(
+        mut self,
+        username: U,
+        password: P,
+    ) -> Self {
+        self.username = username.into();
+        self.password = password.into();
+        self
+    }
+
+    /// Start the server on a random localhost port.
+    ///
+    /// The server runs on the current tokio runtime until the returned handle is dropped.
+    pub async fn start(self) -> Result<TestServer> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).context(BindSnafu)?;
+        listener.set_nonblocking(true).context(BindSnafu)?;
+        let address = listener.local_addr().context(BindSnafu)?;
+
+        // The token only needs to differ between servers, so that clients can't mix them up.
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let token = format!("{:x}{:x}", nanos, address.port());
+
+        let faults = Faults::default();
+        let state = Arc::new(ServerState {
+            address,
+            backend: self.backend.clone(),
+            faults: faults.clone(),
+            username: self.username.clone(),
+            password: self.password.clone(),
+            token,
+        });
+
+        let make_service = make_service_fn(move |_| {
+            let state = state.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |request| state.clone().handle(request))) }
+        });
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let server = Server::from_tcp(listener)
+            .context(ServeSnafu)?
+            .serve(make_service)
+            .with_graceful_shutdown(async {
+                shutdown_rx.await.ok();
+            });
+        tokio::spawn(server);
+
+        Ok(TestServer {
+            address,
+            backend: self.backend,
+            faults,
+            username: self.username,
+            password: self.password,
+            shutdown: Some(shutdown_tx),
+        })
+    }
+}
+
+/// A fake menmos node running in-process.
+///
+/// Blobs are stored in a [`MemoryBackend`], which can be inspected directly by tests.
+/// The server is shut down when dropped.
+///
+/// # Examples
+/// ```
+/// use menmos::testing::TestServer;
+/// use menmos::{FileMetadata, MenmosBuilder};
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let server = TestServer::start().await.unwrap();
+/// let client = MenmosBuilder::from_profile(server.profile())
+///     .build()
+///     .await
+///     .unwrap();
+///
+/// let file = client.fs.create_file(FileMetadata::new("test.txt")).await.unwrap();
+/// # }
+/// ```
+pub struct TestServer {
+    address: SocketAddr,
+    backend: MemoryBackend,
+    faults: Faults,
+    username: String,
+    password: String,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl TestServer {
+    /// Get a builder to configure the server.
+    pub fn builder() -> TestServerBuilder {
+        TestServerBuilder {
+            backend: MemoryBackend::new(),
+            username: String::from(DEFAULT_USERNAME),
+            password: String::from(DEFAULT_PASSWORD),
+        }
+    }
+
+    /// Start a server with default settings.
+    pub async fn start() -> Result<Self> {
+        Self::builder().start().await
+    }
+
+    /// Get the address the server is listening on.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Get the URL of the server, to be used as a profile host.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    /// Get a profile connecting to this server.
+    pub fn profile(&self) -> Profile {
+        Profile::new(
+            self.url(),
+            self.username.clone(),
+            Credential::password(self.password.as_str()),
+        )
+    }
+
+    /// Get the backend storing the blobs of this server.
+    pub fn backend(&self) -> &MemoryBackend {
+        &self.backend
+    }
+
+    /// Get the faults injected by this server.
+    pub fn faults(&self) -> &Faults {
+        &self.faults
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            // The server task may already be gone if the runtime was shut down.
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_multipart_content() {
+        let body = b"--abc\r\nContent-Disposition: form-data; name=\"src\"\r\n\r\nhello\r\nworld\r\n--abc--\r\n";
+        assert_eq!(multipart_content(body, "abc").unwrap(), b"hello\r\nworld");
+        assert!(multipart_content(b"garbage", "abc").is_none());
+    }
+}
@@ -2,9 +2,54 @@ use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use menmos_client::Client;
+use interface::BlobMeta;
 
-pub type ClientRC = Arc<Client>;
+use crate::profile::ClientSettings;
+
+pub use crate::backend::BackendRC;
+
+pub type DefaultsRC = Arc<Defaults>;
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// Defaults applied by the SDK to the blobs it creates and the queries it sends.
+#[derive(Clone, Debug)]
+pub struct Defaults {
+    pub tags: Vec<String>,
+    pub metadata: HashMap<String, String>,
+    pub page_size: usize,
+}
+
+impl Defaults {
+    pub fn from_settings(settings: &ClientSettings) -> Self {
+        Self {
+            tags: settings.default_tags.clone(),
+            metadata: settings.default_metadata.clone(),
+            page_size: settings.query_page_size.unwrap_or(DEFAULT_PAGE_SIZE),
+        }
+    }
+
+    /// Add the default tags and key/value pairs to the provided blob tags and metadata.
+    ///
+    /// Keys that are already present keep their value.
+    pub fn apply(&self, tags: &mut Vec<String>, metadata: &mut HashMap<String, String>) {
+        for tag in self.tags.iter() {
+            if !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        }
+
+        for (k, v) in self.metadata.iter() {
+            metadata.entry(k.clone()).or_insert_with(|| v.clone());
+        }
+    }
+}
+
+impl Default for Defaults {
+    fn default() -> Self {
+        Self::from_settings(&ClientSettings::default())
+    }
+}
 
 /// The metadata of a blob.
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
@@ -58,6 +103,18 @@ impl FileMetadata {
     }
 }
 
+impl From<BlobMeta> for FileMetadata {
+    fn from(meta: BlobMeta) -> Self {
+        Self {
+            name: meta.name,
+            metadata: meta.metadata,
+            tags: meta.tags,
+            parents: meta.parents,
+            size: meta.size,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct UploadRequest {
     /// The path of the file to upload.
@@ -72,3 +129,28 @@ pub struct UploadRequest {
     /// The parent id of the file to upload.
     pub parent_id: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_do_not_override_explicit_values() {
+        let defaults = Defaults::from_settings(&ClientSettings {
+            default_tags: vec![String::from("app"), String::from("shared")],
+            default_metadata: HashMap::from([
+                (String::from("owner"), String::from("ci")),
+                (String::from("team"), String::from("infra")),
+            ]),
+            ..Default::default()
+        });
+
+        let mut tags = vec![String::from("shared")];
+        let mut metadata = HashMap::from([(String::from("owner"), String::from("alice"))]);
+        defaults.apply(&mut tags, &mut metadata);
+
+        assert_eq!(tags, vec![String::from("shared"), String::from("app")]);
+        assert_eq!(metadata["owner"], "alice");
+        assert_eq!(metadata["team"], "infra");
+    }
+}
@@ -6,36 +6,47 @@ use menmos_client::Query;
 
 use snafu::prelude::*;
 
-use crate::ClientRC;
+use crate::error::{ErrorKind, RequestError};
+use crate::BackendRC;
 
 #[derive(Debug, Snafu)]
 pub enum UtilError {
-    // TODO: add source: ClientError once its exposed in menmos-client >= 0.1.0
-    #[snafu(display("failed to get metadata for blob '{}'", blob_id))]
-    GetMetaError { blob_id: String },
+    #[snafu(display("failed to get metadata for blob '{}': {}", blob_id, source))]
+    GetMetaError {
+        source: RequestError,
+        blob_id: String,
+    },
 
     #[snafu(display("blob '{}' does not exist", blob_id))]
     BlobDoesNotExist { blob_id: String },
 
-    // TODO: add source: ClientError once its exposed in menmos-client >= 0.1.0
-    #[snafu(display("query failed"))]
-    QueryError,
+    #[snafu(display("query failed: {}", source))]
+    QueryError { source: RequestError },
+}
+
+impl UtilError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            UtilError::GetMetaError { source, .. } | UtilError::QueryError { source } => {
+                source.kind()
+            }
+            UtilError::BlobDoesNotExist { .. } => ErrorKind::NotFound,
+        }
+    }
 }
 
 type Result<T> = std::result::Result<T, UtilError>;
 
-pub async fn get_meta_if_exists(client: &ClientRC, blob_id: &str) -> Result<Option<BlobMeta>> {
-    let r = client
+pub async fn get_meta_if_exists(backend: &BackendRC, blob_id: &str) -> Result<Option<BlobMeta>> {
+    let r = backend
         .get_meta(blob_id)
         .await
-        .map_err(|_| UtilError::GetMetaError {
-            blob_id: blob_id.into(),
-        })?;
+        .context(GetMetaSnafu { blob_id })?;
     Ok(r)
 }
 
-pub async fn get_meta(client: &ClientRC, blob_id: &str) -> Result<BlobMeta> {
-    get_meta_if_exists(client, blob_id)
+pub async fn get_meta(backend: &BackendRC, blob_id: &str) -> Result<BlobMeta> {
+    get_meta_if_exists(backend, blob_id)
         .await?
         .context(BlobDoesNotExistSnafu {
             blob_id: String::from(blob_id),
@@ -45,15 +56,15 @@ pub async fn get_meta(client: &ClientRC, blob_id: &str) -> Result<BlobMeta> {
 /// Scrolls a given query until the end of results and returns the output lazily as a stream.
 pub fn scroll_query(
     query: Query,
-    client: &ClientRC,
+    backend: &BackendRC,
 ) -> impl TryStream<Ok = Hit, Error = UtilError> + Unpin {
     Box::pin(futures::stream::try_unfold(
-        (query, Vec::<Hit>::new(), false, client.clone()),
-        move |(mut n_query, mut pending_hits, mut page_end_reached, client)| async move {
+        (query, Vec::<Hit>::new(), false, backend.clone()),
+        move |(mut n_query, mut pending_hits, mut page_end_reached, backend)| async move {
             if let Some(hit) = pending_hits.pop() {
                 return Ok(Some((
                     hit,
-                    (n_query, pending_hits, page_end_reached, client),
+                    (n_query, pending_hits, page_end_reached, backend),
                 )));
             }
 
@@ -61,18 +72,15 @@ pub fn scroll_query(
                 return Ok(None);
             }
 
-            let results = client
-                .query(n_query.clone())
-                .await
-                .map_err(|_| UtilError::QueryError)?;
+            let results = backend.query(n_query.clone()).await.context(QuerySnafu)?;
 
-            pending_hits.extend(results.hits.into_iter());
+            pending_hits.extend(results.hits);
 
             n_query.from += results.count;
             page_end_reached = n_query.from >= results.total;
 
             if let Some(r_val) = pending_hits.pop() {
-                let ret_tuple = (n_query, pending_hits, page_end_reached, client);
+                let ret_tuple = (n_query, pending_hits, page_end_reached, backend);
                 Ok(Some((r_val, ret_tuple)))
             } else {
                 Ok(None)
@@ -1,12 +1,13 @@
-use std::{io::SeekFrom, time::Duration};
+use std::io::SeekFrom;
 
 use futures::TryStreamExt;
 
+use menmos::backend::MemoryBackend;
 use menmos::*;
 
 #[tokio::test]
 async fn menmos_file_api() -> std::result::Result<(), Box<dyn std::error::Error>> {
-    let client = Menmos::new("local").await?;
+    let client = Menmos::from_backend(MemoryBackend::new());
 
     // Test file creation
     let mut file = client
@@ -21,8 +22,7 @@ async fn menmos_file_api() -> std::result::Result<(), Box<dyn std::error::Error>
     file.seek(SeekFrom::Start(0)).await?;
 
     // Read the first word.
-    let mut buf = Vec::new();
-    buf.resize(5, 0_u8);
+    let mut buf = vec![0_u8; 5];
     let read = file.read(&mut buf).await?;
 
     assert_eq!(read, 5);
@@ -58,8 +58,7 @@ async fn menmos_file_api() -> std::result::Result<(), Box<dyn std::error::Error>
 
 #[tokio::test]
 async fn menmos_dir_api() -> Result<(), Box<dyn std::error::Error>> {
-    // TODO: Add a way to specify a profile via env var.
-    let client = Menmos::new("local").await?;
+    let client = Menmos::from_backend(MemoryBackend::new());
 
     let dir_a = client
         .fs
@@ -89,13 +88,69 @@ async fn menmos_dir_api() -> Result<(), Box<dyn std::error::Error>> {
         )
         .await?;
 
-    std::thread::sleep(Duration::from_millis(100));
-
     let results = dir_a.list().try_collect::<Vec<_>>().await?;
     assert_eq!(results.len(), 1);
 
     client.fs.remove_dir_all(dir_a.id()).await?;
     client.fs.remove_dir_all(dir_b.id()).await?;
 
+    let remaining = client
+        .query(Query::default().and_tag("sdk_test"))
+        .try_collect::<Vec<_>>()
+        .await?;
+    assert!(remaining.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn menmos_entry_metadata() -> Result<(), Box<dyn std::error::Error>> {
+    let client = Menmos::from_backend(MemoryBackend::new());
+
+    let dir = client
+        .fs
+        .create_dir(FileMetadata::new("photos").with_tag("entry_test"))
+        .await?;
+    let file = client
+        .fs
+        .create_file(
+            FileMetadata::new("cat.jpg")
+                .with_tag("entry_test")
+                .with_meta("camera", "film")
+                .with_parent(dir.id()),
+        )
+        .await?;
+    file.write_at(0, b"meow").await?;
+
+    // Listed entries carry the metadata they were listed with.
+    let mut entries = dir.list().try_collect::<Vec<_>>().await?;
+    assert_eq!(entries.len(), 1);
+    let entry = &mut entries[0];
+    assert_eq!(entry.id(), file.id());
+    assert_eq!(entry.name(), "cat.jpg");
+    assert_eq!(entry.size(), 4);
+    assert_eq!(entry.tags(), ["entry_test"]);
+    assert_eq!(entry.metadata()["camera"], "film");
+    assert_eq!(entry.parents(), [dir.id()]);
+
+    client
+        .fs
+        .update(file.id())
+        .rename("dog.jpg")
+        .apply()
+        .await?;
+    assert_eq!(entry.name(), "cat.jpg");
+    entry.refresh().await?;
+    assert_eq!(entry.name(), "dog.jpg");
+
+    // So do query results.
+    let mut names = Vec::new();
+    let mut hits = client.query(Query::default().and_tag("entry_test"));
+    while let Some(hit) = hits.try_next().await? {
+        names.push(String::from(client.fs.open_hit(hit)?.name()));
+    }
+    names.sort();
+    assert_eq!(names, ["dog.jpg", "photos"]);
+
     Ok(())
 }
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::time::Duration;
+
+use futures::TryStreamExt;
+
+use menmos::backend::Backend;
+use menmos::testing::{Fault, TestServer};
+use menmos::*;
+
+async fn connect(server: &TestServer) -> Menmos {
+    MenmosBuilder::from_profile(server.profile())
+        .with_max_retry_count(3)
+        .with_retry_interval(Duration::from_millis(10))
+        .build()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn file_roundtrip_over_http() -> Result<(), Box<dyn std::error::Error>> {
+    let server = TestServer::start().await?;
+    let client = connect(&server).await;
+
+    let mut file = client
+        .fs
+        .create_file(FileMetadata::new("test.txt").with_tag("sdk_test"))
+        .await?;
+
+    file.write("Hello world!".as_bytes()).await?;
+    file.seek(SeekFrom::Start(6)).await?;
+    file.write("there".as_bytes()).await?;
+
+    file.seek(SeekFrom::Start(0)).await?;
+    let mut buf = String::new();
+    file.read_to_string(&mut buf).await?;
+    assert_eq!(&buf, "Hello there!");
+
+    let meta = server.backend().get_meta(file.id()).await?.unwrap();
+    assert_eq!(meta.name, "test.txt");
+    assert_eq!(meta.size, 12);
+
+    client.fs.remove_file(file.id()).await?;
+    assert!(server.backend().get_meta(file.id()).await?.is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn paginated_listing_and_push() -> Result<(), Box<dyn std::error::Error>> {
+    let server = TestServer::start().await?;
+    let settings = ClientSettings {
+        query_page_size: Some(2),
+        ..Default::default()
+    };
+    let client = MenmosBuilder::from_profile(server.profile().with_settings(settings))
+        .build()
+        .await?;
+
+    let local_dir = tempfile::tempdir()?;
+    std::fs::write(local_dir.path().join("a.txt"), "first file")?;
+    std::fs::write(local_dir.path().join("b.txt"), "second file")?;
+
+    let dir = client.fs.create_dir(FileMetadata::new("dir")).await?;
+    for i in 0..5 {
+        client
+            .fs
+            .create_file(FileMetadata::new(format!("file_{}", i)).with_parent(dir.id()))
+            .await?;
+    }
+
+    let pushed = client
+        .push_files(vec![UploadRequest {
+            path: local_dir.path().to_path_buf(),
+            metadata: HashMap::new(),
+            tags: vec![String::from("pushed")],
+            parent_id: Some(String::from(dir.id())),
+        }])
+        .try_collect::<Vec<_>>()
+        .await?;
+    assert_eq!(pushed.len(), 2);
+
+    let entries = dir.list().try_collect::<Vec<_>>().await?;
+    assert_eq!(entries.len(), 6);
+
+    let files = client
+        .query(Query::default().and_tag("pushed"))
+        .try_collect::<Vec<_>>()
+        .await?;
+    assert_eq!(files.len(), 3);
+    for hit in files.into_iter().filter(|hit| hit.meta.size > 0) {
+        let content = server
+            .backend()
+            .read_range(&hit.id, (0, hit.meta.size - 1))
+            .await?;
+        assert_eq!(content.len() as u64, hit.meta.size);
+    }
+
+    client.fs.remove_dir_all(dir.id()).await?;
+    let remaining = client
+        .query(Query::default())
+        .try_collect::<Vec<_>>()
+        .await?;
+    assert!(remaining.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn invalid_credentials_are_unauthorized() {
+    let server = TestServer::builder()
+        .with_credentials("alice", "hunter2")
+        .start()
+        .await
+        .unwrap();
+
+    assert!(MenmosBuilder::from_profile(server.profile())
+        .build()
+        .await
+        .is_ok());
+
+    let profile = Profile::new(server.url(), "alice", Credential::password("wrong"));
+    let err = MenmosBuilder::from_profile(profile)
+        .build()
+        .await
+        .map(|_| ())
+        .unwrap_err();
+    assert!(matches!(err, MenmosError::ClientBuild { .. }));
+    assert_eq!(err.kind(), ErrorKind::Unauthorized);
+}
+
+#[tokio::test]
+async fn server_errors_are_retryable() -> Result<(), Box<dyn std::error::Error>> {
+    let server = TestServer::start().await?;
+    let client = connect(&server).await;
+
+    server.faults().fail_next(1);
+    let err = client
+        .fs
+        .create_dir(FileMetadata::new("dir"))
+        .await
+        .map(|_| ())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Server);
+    assert!(err.is_retryable());
+
+    server.faults().inject(Fault::Status(404), 1);
+    let err = client
+        .query(Query::default())
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("injected failure"));
+
+    // Faults are consumed, so the next attempt goes through.
+    client.fs.create_dir(FileMetadata::new("dir")).await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn dropped_connections_are_retried() -> Result<(), Box<dyn std::error::Error>> {
+    let server = TestServer::start().await?;
+    let client = connect(&server).await;
+    let dir = client.fs.create_dir(FileMetadata::new("dir")).await?;
+
+    server.faults().drop_next(2);
+    let before = server.faults().request_count();
+    let entries = dir.list().try_collect::<Vec<_>>().await?;
+    assert!(entries.is_empty());
+    assert_eq!(server.faults().request_count() - before, 3);
+
+    server.faults().drop_next(3);
+    let err = dir
+        .list()
+        .try_collect::<Vec<_>>()
+        .await
+        .map(|_| ())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Network);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn slow_responses_time_out() -> Result<(), Box<dyn std::error::Error>> {
+    let server = TestServer::start().await?;
+    let client = MenmosBuilder::from_profile(server.profile())
+        .with_request_timeout(Duration::from_millis(100))
+        .with_max_retry_count(1)
+        .build()
+        .await?;
+
+    server.faults().set_latency(Duration::from_millis(500));
+    let err = client
+        .query(Query::default())
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Timeout);
+
+    server.faults().reset();
+    client
+        .query(Query::default())
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn async_io_over_http() -> Result<(), Box<dyn std::error::Error>> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    let server = TestServer::start().await?;
+    let client = connect(&server).await;
+    let mut file = client.fs.create_file(FileMetadata::new("copy.bin")).await?;
+
+    let content: Vec<u8> = (0..=255).cycle().take(20_000).collect();
+    tokio::io::copy(&mut content.as_slice(), &mut file).await?;
+
+    AsyncSeekExt::seek(&mut file, SeekFrom::Start(0)).await?;
+    let mut buf = Vec::new();
+    AsyncReadExt::read_to_end(&mut file, &mut buf).await?;
+    assert_eq!(buf, content);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn download_retries_failed_ranges() -> Result<(), Box<dyn std::error::Error>> {
+    let server = TestServer::start().await?;
+    let client = connect(&server).await;
+
+    let content: Vec<u8> = (0..=255).cycle().take(50_000).collect();
+    let file = client
+        .fs
+        .create_file_from_reader(FileMetadata::new("large.bin"), content.as_slice())
+        .await?;
+
+    let local = tempfile::tempdir()?;
+    let path = local.path().join("large.bin");
+    let options = fs::DownloadOptions::default()
+        .with_chunk_size(8192)
+        .with_retry_interval(Duration::from_millis(10));
+
+    server.faults().fail_next(2);
+    assert_eq!(file.download_to(&path, options).await?, 50_000);
+    assert_eq!(std::fs::read(&path)?, content);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn failed_download_keeps_local_file() -> Result<(), Box<dyn std::error::Error>> {
+    let server = TestServer::start().await?;
+    let client = connect(&server).await;
+
+    let file = client
+        .fs
+        .create_file_from_reader(FileMetadata::new("remote.txt"), "remote".as_bytes())
+        .await?;
+
+    let local = tempfile::tempdir()?;
+    let path = local.path().join("local.txt");
+    std::fs::write(&path, "local")?;
+
+    // The request for the size of the blob fails before anything is downloaded.
+    server.faults().inject(Fault::Status(500), 100);
+    let options = fs::DownloadOptions::default().with_max_retries(0);
+    file.download_to(&path, options).await.unwrap_err();
+    server.faults().reset();
+
+    assert_eq!(std::fs::read_to_string(&path)?, "local");
+    assert_eq!(std::fs::read_dir(local.path())?.count(), 1);
+
+    Ok(())
+}